@@ -71,5 +71,5 @@ fn main() {
     sm.dispatch(&evt_a);
 
     let evt_b = BasicEvt::B;
-    sm.dispatch(&evt_b)
+    sm.dispatch(&evt_b);
 }
@@ -0,0 +1,355 @@
+use kaori_hsm::*;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Host measurement of the stack space used by `StateMachine::dispatch()` down to the deepest
+/// user-defined method it calls.
+/// Run with `cargo run --release --example stack_usage`.
+///
+/// State hierarchy:
+/// ```text
+/// Top
+/// ├── Shore
+/// ├── D1
+/// │   └── ... (19 levels)
+/// │       └── D19
+/// ├── Flat (regions)
+/// │   ├── FA
+/// │   │   └── FA1
+/// │   └── FB
+/// │       └── FB1
+/// └── Outer (regions)
+///     ├── RA
+///     │   └── Inner (regions)
+///     │       ├── RA1
+///     │       │   └── A1
+///     │       └── RA2
+///     │           └── A2
+///     └── RB
+///         └── B
+/// ```
+enum StackEvt {
+    // Transition from `Shore` to `D19`, whose 19 levels are entered
+    Dive,
+    // Transition from `D19` to `Shore`, whose 19 levels are exited
+    Surface,
+    // Transition from `Shore` to the orthogonal state `Flat`
+    EnterFlat,
+    // Transition from `FA1` to `Shore`, exiting the regions of `Flat`
+    LeaveFlat,
+    // Transition from `Shore` to `Outer`, entering the nested orthogonal state `Inner`
+    Enter,
+    // Handled by `Shore`, the current state of the state machine
+    Tap,
+    // Self-transition of `Shore`, exiting and entering a single level
+    Splash,
+    // Handled by `B`, in a region of the orthogonal state `Outer`
+    TapB,
+    // Handled by `A1`, in a region of `Inner`, itself in a region of `Outer`
+    Poke,
+    // Transition from `A1` to `Shore`, exiting every region
+    Leave,
+}
+
+struct StackStateMachine {
+    flat_regions: Regions<Self, 2>,
+    outer_regions: Regions<Self, 2>,
+    inner_regions: Regions<Self, 2>,
+}
+
+// Lowest address of the stack reached by the user-defined methods
+static STACK_BOTTOM: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+// Return the address of the stack at the frame of the caller of this function
+#[inline(never)]
+fn stack_address() -> usize {
+    let marker = 0u8;
+    black_box(&marker) as *const u8 as usize
+}
+
+// Record the address of the stack reached by a user-defined method
+fn probe() {
+    STACK_BOTTOM.fetch_min(stack_address(), Ordering::Relaxed);
+}
+
+impl TopState for StackStateMachine {
+    type Evt = StackEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Shore)
+    }
+}
+
+#[state(super_state= Top)]
+impl State<Shore> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, evt: &StackEvt) -> HandleResult<Self> {
+        probe();
+        match evt {
+            StackEvt::Dive => transition!(D19),
+            StackEvt::EnterFlat => transition!(Flat),
+            StackEvt::Enter => transition!(Outer),
+            StackEvt::Tap => handled!(),
+            StackEvt::Splash => transition!(Shore),
+            _ => ignored!(),
+        }
+    }
+}
+
+// Composite state `$tag`, nested in `$super_state`, whose initial transition targets `$child`
+macro_rules! depth_state {
+    ($tag:ident, $super_state:ident, $child:ident) => {
+        #[state(super_state= $super_state)]
+        impl State<$tag> for StackStateMachine {
+            fn init(&mut self) -> InitResult<Self> {
+                init_transition!($child)
+            }
+
+            fn entry(&mut self) {
+                probe();
+            }
+
+            fn exit(&mut self) {
+                probe();
+            }
+
+            fn handle(&mut self, _evt: &StackEvt) -> HandleResult<Self> {
+                ignored!()
+            }
+        }
+    };
+}
+
+depth_state!(D1, Top, D2);
+depth_state!(D2, D1, D3);
+depth_state!(D3, D2, D4);
+depth_state!(D4, D3, D5);
+depth_state!(D5, D4, D6);
+depth_state!(D6, D5, D7);
+depth_state!(D7, D6, D8);
+depth_state!(D8, D7, D9);
+depth_state!(D9, D8, D10);
+depth_state!(D10, D9, D11);
+depth_state!(D11, D10, D12);
+depth_state!(D12, D11, D13);
+depth_state!(D13, D12, D14);
+depth_state!(D14, D13, D15);
+depth_state!(D15, D14, D16);
+depth_state!(D16, D15, D17);
+depth_state!(D17, D16, D18);
+depth_state!(D18, D17, D19);
+
+#[state(super_state= D18)]
+impl State<D19> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, evt: &StackEvt) -> HandleResult<Self> {
+        probe();
+        match evt {
+            StackEvt::Surface => transition!(Shore),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state= Top, regions= [FA, FB] in flat_regions)]
+impl State<Flat> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, _evt: &StackEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+depth_state!(FA, Flat, FA1);
+depth_state!(FB, Flat, FB1);
+
+#[state(super_state= FA)]
+impl State<FA1> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, evt: &StackEvt) -> HandleResult<Self> {
+        match evt {
+            StackEvt::LeaveFlat => {
+                probe();
+                transition!(Shore)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state= FB)]
+impl State<FB1> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, _evt: &StackEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state= Top, regions= [RA, RB] in outer_regions)]
+impl State<Outer> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, _evt: &StackEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+depth_state!(RA, Outer, Inner);
+depth_state!(RB, Outer, B);
+
+#[state(super_state= RA, regions= [RA1, RA2] in inner_regions)]
+impl State<Inner> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, _evt: &StackEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+depth_state!(RA1, Inner, A1);
+depth_state!(RA2, Inner, A2);
+
+#[state(super_state= RA1)]
+impl State<A1> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, evt: &StackEvt) -> HandleResult<Self> {
+        match evt {
+            StackEvt::Poke => {
+                probe();
+                handled!()
+            }
+            StackEvt::Leave => {
+                probe();
+                transition!(Shore)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state= RA2)]
+impl State<A2> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, _evt: &StackEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state= RB)]
+impl State<B> for StackStateMachine {
+    fn entry(&mut self) {
+        probe();
+    }
+
+    fn exit(&mut self) {
+        probe();
+    }
+
+    fn handle(&mut self, evt: &StackEvt) -> HandleResult<Self> {
+        match evt {
+            StackEvt::TapB => {
+                probe();
+                handled!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+// Dispatch `evt` and print the stack space used from the caller of `dispatch()` down to the
+// deepest user-defined method
+fn measure(sm: &mut StateMachine<StackStateMachine>, label: &str, evt: &StackEvt) {
+    STACK_BOTTOM.store(usize::MAX, Ordering::Relaxed);
+    let stack_top = stack_address();
+    black_box(sm.dispatch(black_box(evt)));
+    let stack_bottom = STACK_BOTTOM.load(Ordering::Relaxed);
+    println!("{:<46} {:>6} bytes", label, stack_top - stack_bottom);
+}
+
+fn main() {
+    let ism = InitStateMachine::from(StackStateMachine {
+        flat_regions: Regions::new(),
+        outer_regions: Regions::new(),
+        inner_regions: Regions::new(),
+    });
+    let mut sm = ism.init();
+
+    measure(&mut sm, "Handled by the current state", &StackEvt::Tap);
+    measure(&mut sm, "Exit and enter 1 level", &StackEvt::Splash);
+    measure(&mut sm, "Enter 19 levels", &StackEvt::Dive);
+    measure(&mut sm, "Exit 19 levels", &StackEvt::Surface);
+    measure(&mut sm, "Enter an orthogonal state", &StackEvt::EnterFlat);
+    measure(&mut sm, "Exit an orthogonal state", &StackEvt::LeaveFlat);
+    measure(
+        &mut sm,
+        "Enter two nested orthogonal states",
+        &StackEvt::Enter,
+    );
+    measure(&mut sm, "Handled in a region", &StackEvt::TapB);
+    measure(&mut sm, "Handled in a region of a region", &StackEvt::Poke);
+    measure(
+        &mut sm,
+        "Exit two nested orthogonal states",
+        &StackEvt::Leave,
+    );
+}
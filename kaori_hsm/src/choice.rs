@@ -0,0 +1,88 @@
+use crate::proto_state_machine::TopState;
+use crate::state::{HandleResult, State};
+
+//For doc
+#[allow(unused_imports)]
+use crate::{ModelError, StateMachine};
+
+/// Trait for defining a choice pseudostate, the equivalent of a UML choice point.
+///
+/// A choice pseudostate can be the target of a transition like any state, but is never entered.
+/// When a transition targets it, the states between the source of the transition and the LCA of
+/// the source and of the choice are exited first. The [`Choice::choose()`] method is then
+/// called to evaluate the guards of the choice, and returns the transition to take. This second
+/// transition starts from the LCA, so that the choice only affects states which have not already
+/// been exited. A choice may select another choice.
+///
+/// The choice must be implemented with the `#[choice()]` procedural macro, which takes the same
+/// `super_state` argument as the `#[state()]` macro and an optional `targets` list. The latter
+/// declares the states which may be chosen, and is exposed through [`Choice::TARGETS`] so that
+/// the choice can be drawn on a diagram along with its outgoing transitions.
+///
+/// *Note: The outcome of a transition to a choice reported by [`StateMachine::dispatch()`]
+/// holds the chosen target. A choice cannot be the target of an initial transition.*
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# enum BasicEvt{A(u8)}
+/// struct BasicStateMachine{
+///     level: u8,
+/// }
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(Low)
+///#   }
+///# }
+///
+/// #[state(super_state= Top)]
+/// impl State<Low> for BasicStateMachine{
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::A(level) => {
+///                 self.level = *level;
+///                 transition!(LevelChoice)
+///             }
+///         }
+///     }
+/// }
+///
+/// #[state(super_state= Top)]
+/// impl State<High> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// #[choice(super_state= Top, targets= [Low, High])]
+/// impl Choice<LevelChoice> for BasicStateMachine{
+///     fn choose(&mut self) -> HandleResult<Self> {
+///         if self.level > 10 {
+///             transition!(High)
+///         } else {
+///             transition!(Low)
+///         }
+///     }
+/// }
+///
+/// let ism = InitStateMachine::from(BasicStateMachine{ level: 0 });
+/// let mut sm = ism.init();
+/// sm.dispatch(&BasicEvt::A(20));
+/// assert!(sm.is_in::<High>());
+/// assert_eq!(<BasicStateMachine as Choice<LevelChoice>>::TARGETS, ["Low", "High"]);
+/// ```
+pub trait Choice<Tag>: State<Tag>
+where
+    Self: TopState,
+{
+    /// Names of the states which may be chosen, as listed in the `targets` argument of the
+    /// `#[choice()]` procedural macro.
+    const TARGETS: &'static [&'static str] = &[];
+
+    /// Evaluate the guards of the choice and return the transition to take, using either the
+    /// `transition!()` or the `transition_history!()` macro. Any other result is reported as
+    /// [`ModelError::ChoiceWithoutTransition`].
+    fn choose(&mut self) -> HandleResult<Self>;
+}
@@ -0,0 +1,105 @@
+use core::fmt;
+
+//For doc
+#[allow(unused_imports)]
+use crate::{Choice, HandleResult, InitResult, InitStateMachine, Regions, StateMachine, TopState};
+
+/// Inconsistency of the user-defined state machine, detected while taking a transition.
+///
+/// Once such an error has been detected, the states exited by the faulty transition are not
+/// entered again and the state machine is terminated (see [`StateMachine::is_terminated()`]).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelError {
+    /// The target of a transition is a descendant of an orthogonal state, but not a descendant of
+    /// any of the region states listed in its [`Regions`].
+    TargetOutsideRegions,
+    /// [`Choice::choose()`] returned something else than a transition.
+    ChoiceWithoutTransition,
+    /// The state recorded in the history of a composite state is not one of its descendants.
+    HistoryOutsideState,
+    /// The state handling the event is not an ancestor of the current state.
+    SourceOutsideLineage,
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ModelError::TargetOutsideRegions => {
+                "Target state is not inside a region of its orthogonal state"
+            }
+            ModelError::ChoiceWithoutTransition => {
+                "Choice pseudostate did not select any transition"
+            }
+            ModelError::HistoryOutsideState => {
+                "History state is not a descendant of its composite state"
+            }
+            ModelError::SourceOutsideLineage => {
+                "Handling state is not an ancestor of the current state"
+            }
+        })
+    }
+}
+
+/// Error returned by [`InitStateMachine::try_init()`] and
+/// [`InitStateMachine::try_init_with_defer_queue()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitError {
+    /// [`TopState::init()`] returned [`InitResult::NotImplemented`].
+    MissingTopInitTarget,
+    /// The state machine is inconsistent.
+    Model(ModelError),
+    /// The dispatch of an event posted during the initialization failed.
+    Dispatch(DispatchError),
+}
+
+impl From<ModelError> for InitError {
+    fn from(model_error: ModelError) -> Self {
+        InitError::Model(model_error)
+    }
+}
+
+impl From<DispatchError> for InitError {
+    fn from(dispatch_error: DispatchError) -> Self {
+        InitError::Dispatch(dispatch_error)
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::MissingTopInitTarget => f.write_str("Topmost Init should return a state"),
+            InitError::Model(model_error) => model_error.fmt(f),
+            InitError::Dispatch(dispatch_error) => dispatch_error.fmt(f),
+        }
+    }
+}
+
+/// Error returned by [`StateMachine::try_dispatch()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchError {
+    /// The state machine is terminated and does not accept events anymore.
+    Terminated,
+    /// The event has been deferred (see [`HandleResult::Deferred`]) while the defer queue is
+    /// full. The event is dropped.
+    DeferQueueFull,
+    /// The state machine is inconsistent.
+    Model(ModelError),
+}
+
+impl From<ModelError> for DispatchError {
+    fn from(model_error: ModelError) -> Self {
+        DispatchError::Model(model_error)
+    }
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DispatchError::Terminated => f.write_str("State machine is terminated"),
+            DispatchError::DeferQueueFull => {
+                f.write_str("Deferred event does not fit in the defer queue")
+            }
+            DispatchError::Model(model_error) => model_error.fmt(f),
+        }
+    }
+}
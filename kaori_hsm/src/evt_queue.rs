@@ -0,0 +1,79 @@
+use core::mem::MaybeUninit;
+
+// Fixed-capacity FIFO queue of events, used both for keeping the events deferred by the states
+// of a state machine until they are recalled and for the events posted by its states.
+pub(crate) struct EvtQueue<Evt, const N: usize> {
+    evts: [MaybeUninit<Evt>; N],
+    first: usize,
+    len: usize,
+    // Deferring an event requires copying it, which is only possible if the event type
+    // implements `Clone`.
+    clone_evt: Option<fn(&Evt) -> Evt>,
+}
+
+impl<Evt, const N: usize> EvtQueue<Evt, N> {
+    pub(crate) const fn new(clone_evt: Option<fn(&Evt) -> Evt>) -> EvtQueue<Evt, N> {
+        EvtQueue {
+            // An array of `MaybeUninit` does not require initialization
+            evts: unsafe { MaybeUninit::uninit().assume_init() },
+            first: 0,
+            len: 0,
+            clone_evt,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    // Push a copy of `evt` at the back of the queue. Return `false` if it does not fit.
+    pub(crate) fn push_copy(&mut self, evt: &Evt) -> bool {
+        match self.clone_evt {
+            Some(clone_evt) if self.len < N => {
+                self.push(clone_evt(evt));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Push `evt` at the back of the queue, or give it back if the queue is full.
+    pub(crate) fn try_push(&mut self, evt: Evt) -> Result<(), Evt> {
+        if self.len < N {
+            self.push(evt);
+            Ok(())
+        } else {
+            Err(evt)
+        }
+    }
+
+    // Push `evt` at the back of the queue, which must not be full.
+    pub(crate) fn push(&mut self, evt: Evt) {
+        assert!(self.len < N, "Event queue is full");
+        self.evts[(self.first + self.len) % N].write(evt);
+        self.len += 1;
+    }
+
+    // Pop the event at the front of the queue.
+    pub(crate) fn pop(&mut self) -> Option<Evt> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let evt = unsafe { self.evts[self.first].assume_init_read() };
+        self.first = (self.first + 1) % N;
+        self.len -= 1;
+        Some(evt)
+    }
+
+    // Drop every event of the queue.
+    pub(crate) fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<Evt, const N: usize> Drop for EvtQueue<Evt, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
@@ -0,0 +1,109 @@
+use crate::proto_state_machine::TopState;
+use crate::state::{HandleResult, State};
+
+//For doc
+#[allow(unused_imports)]
+use crate::StateMachine;
+
+/// Extension of the [`TopState`] trait for the state machines whose states handle events with
+/// fallible code, such as driver calls returning a `Result`.
+///
+/// It defines the type of error returned by the [`FallibleState::try_handle()`] method of the
+/// states, and the top-level handler of these errors.
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# #[derive(Debug)]
+///# enum DriverError{Timeout}
+///# enum BasicEvt{Read}
+/// struct BasicStateMachine{
+///     last_error: Option<DriverError>,
+/// }
+///
+/// impl BasicStateMachine{
+///     fn read_sensor(&mut self) -> Result<u8, DriverError>{
+///         Err(DriverError::Timeout)
+///     }
+/// }
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(Running)
+///#   }
+///# }
+///
+/// impl FallibleTopState for BasicStateMachine{
+///     type Error = DriverError;
+///
+///     fn on_error(&mut self, error: DriverError) -> HandleResult<Self> {
+///         self.last_error = Some(error);
+///         transition!(Fault)
+///     }
+/// }
+///
+/// #[state(super_state= Top)]
+/// impl State<Running> for BasicStateMachine{
+///     fn try_handle(&mut self, evt: & BasicEvt) -> Result<HandleResult<Self>, DriverError> {
+///         match evt{
+///             BasicEvt::Read => {
+///                 self.read_sensor()?;
+///                 Ok(handled!())
+///             }
+///         }
+///     }
+/// }
+///
+/// #[state(super_state= Top)]
+/// impl State<Fault> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// let ism = InitStateMachine::from(BasicStateMachine{ last_error: None });
+/// let mut sm = ism.init();
+/// sm.dispatch(&BasicEvt::Read);
+/// assert!(sm.is_in::<Fault>());
+/// ```
+pub trait FallibleTopState: TopState {
+    /// Type of the errors returned by the [`FallibleState::try_handle()`] method of the states.
+    type Error;
+
+    /// Handle an error returned by the [`FallibleState::try_handle()`] method of a state which
+    /// does not define its own error handler.
+    /// The returned [`HandleResult`] is treated as if it was returned by the state which failed,
+    /// so that a transition, typically to a fault state, starts from this state.
+    fn on_error(&mut self, error: Self::Error) -> HandleResult<Self>;
+}
+
+/// Trait for defining a state whose events are handled by fallible code.
+///
+/// The state is implemented as any other state with the `#[state()]` procedural macro, except
+/// that the `handle()` method is replaced by the [`FallibleState::try_handle()`] method. The macro
+/// implements [`State::handle()`] so that the errors are passed to the
+/// [`FallibleState::on_error()`] method, which may also be defined in the same implementation
+/// block. See [`FallibleTopState`] for an example.
+pub trait FallibleState<Tag>: State<Tag>
+where
+    Self: FallibleTopState,
+{
+    /// Events injected into the state machine through the [`StateMachine::dispatch()`] method
+    /// are received by this method if the present state is the current state of the state
+    /// machine, in the same manner as [`State::handle()`].
+    fn try_handle(
+        &mut self,
+        evt: &<Self as TopState>::Evt,
+    ) -> Result<HandleResult<Self>, <Self as FallibleTopState>::Error>;
+
+    /// Handle an error returned by [`FallibleState::try_handle()`]. The returned
+    /// [`HandleResult`] is treated as if it was returned by [`State::handle()`].
+    ///
+    ///# Implementation policy
+    /// The implementation of this method is optional. The default implementation calls
+    /// [`FallibleTopState::on_error()`].
+    fn on_error(&mut self, error: <Self as FallibleTopState>::Error) -> HandleResult<Self> {
+        <Self as FallibleTopState>::on_error(self, error)
+    }
+}
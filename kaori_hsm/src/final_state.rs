@@ -0,0 +1,120 @@
+use crate::proto_state_machine::TopState;
+use crate::state::State;
+
+//For doc
+#[allow(unused_imports)]
+use crate::{Regions, StateMachine};
+
+/// Trait for defining a final state, the equivalent of a UML final state.
+///
+/// A final state is a leaf state which can be the target of a transition like any other state,
+/// but which has no behavior of its own: the events it receives are all ignored and dispatched to
+/// its ancestors. Reaching a final state completes its parent state:
+/// - If the parent is an ordinary composite state, its [`State::on_completion()`] method is
+///   called, typically returning its completion transition.
+/// - If the parent is a region state (see [`Regions`]), its region is completed. Once each region
+///   of the orthogonal state has reached one of its final states, the [`State::on_completion()`]
+///   method of the orthogonal state is called.
+/// - If the parent is the top state, every active state is exited and the state machine
+///   terminates, as with the `terminate!()` macro (see [`StateMachine::is_terminated()`]).
+///
+/// The completion is carried on right after the transition which reached the final state, as
+/// part of the dispatch of the same event. A transition triggered by [`State::on_completion()`]
+/// may in turn complete another state.
+///
+/// The final state must be implemented with the `#[final_state()]` procedural macro, which takes
+/// the same `super_state` argument as the `#[state()]` macro.
+///
+/// *Note: The completion of the states reached by the initial transition of the state machine is
+/// not carried on, for [`InitStateMachine::init()`](crate::InitStateMachine::init) dispatches no
+/// event.*
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# enum HandshakeEvt{Sent, Received}
+/// struct HandshakeStateMachine{
+///     exchange_regions: Regions<Self, 2>,
+/// }
+///#
+///# impl TopState for HandshakeStateMachine{
+///#   type Evt = HandshakeEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(Exchange)
+///#   }
+///# }
+///
+/// #[state(super_state= Top, regions= [Tx, Rx] in exchange_regions)]
+/// impl State<Exchange> for HandshakeStateMachine{
+///     fn handle(&mut self, _evt: & HandshakeEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+///
+///     fn on_completion(&mut self) -> HandleResult<Self> {
+///         transition!(Done)
+///     }
+/// }
+///
+/// #[state(super_state= Exchange)]
+/// impl State<Tx> for HandshakeStateMachine{
+///     fn init(&mut self) -> InitResult<Self> {
+///         init_transition!(Sending)
+///     }
+///
+///     fn handle(&mut self, _evt: & HandshakeEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// #[state(super_state= Tx)]
+/// impl State<Sending> for HandshakeStateMachine{
+///     fn handle(&mut self, evt: & HandshakeEvt) -> HandleResult<Self> {
+///         match evt{
+///             HandshakeEvt::Sent => transition!(TxDone),
+///             _ => ignored!()
+///         }
+///     }
+/// }
+///
+/// #[final_state(super_state= Tx)]
+/// impl FinalState<TxDone> for HandshakeStateMachine{}
+///
+/// #[state(super_state= Exchange)]
+/// impl State<Rx> for HandshakeStateMachine{
+///     fn init(&mut self) -> InitResult<Self> {
+///         init_transition!(Receiving)
+///     }
+///
+///     fn handle(&mut self, _evt: & HandshakeEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// #[state(super_state= Rx)]
+/// impl State<Receiving> for HandshakeStateMachine{
+///     fn handle(&mut self, evt: & HandshakeEvt) -> HandleResult<Self> {
+///         match evt{
+///             HandshakeEvt::Received => transition!(RxDone),
+///             _ => ignored!()
+///         }
+///     }
+/// }
+///
+/// #[final_state(super_state= Rx)]
+/// impl FinalState<RxDone> for HandshakeStateMachine{}
+///
+/// #[final_state(super_state= Top)]
+/// impl FinalState<Done> for HandshakeStateMachine{}
+///
+/// let ism = InitStateMachine::from(HandshakeStateMachine{ exchange_regions: Regions::new() });
+/// let mut sm = ism.init();
+/// sm.dispatch(&HandshakeEvt::Received);
+/// assert!(sm.is_in::<RxDone>() && sm.is_in::<Sending>());
+/// sm.dispatch(&HandshakeEvt::Sent);
+/// assert!(sm.is_terminated());
+/// ```
+pub trait FinalState<Tag>: State<Tag>
+where
+    Self: TopState,
+{
+}
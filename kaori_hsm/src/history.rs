@@ -0,0 +1,94 @@
+use crate::proto_state_machine::TopState;
+use crate::state::StateFn;
+
+//For doc
+#[allow(unused_imports)]
+use crate::{Regions, State};
+
+/// Record of the substate which was active the last time a composite state has been exited,
+/// used for re-entering it through a history transition.
+///
+/// A `History` field must be declared in the user state machine structure for each composite
+/// state having a history, and be assigned to this state with either the `shallow_history` or the
+/// `deep_history` argument of the `#[state()]` procedural macro:
+/// - Shallow history restores the direct substate that was active when the composite state was
+///   last exited, which then goes through its own initial transition.
+/// - Deep history restores the whole nested configuration that was active when the composite
+///   state was last exited, down to the leaf state. The regions of a restored orthogonal state
+///   are restored down to the state each of them was left in, as recorded in its [`Regions`].
+///
+/// In both cases, the history takes the room of a single state reference in the structure. As
+/// long as the composite state has never been exited, a history transition to it behaves like a
+/// regular transition and goes through its [`State::init()`] method.
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# enum BasicEvt{A, B}
+/// struct BasicStateMachine{
+///     s0_history: History<Self>,
+/// }
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+///
+/// #[state(super_state= Top, shallow_history= s0_history)]
+/// impl State<S0> for BasicStateMachine{
+///     fn init(&mut self) -> InitResult<Self> {
+///         init_transition!(S01)
+///     }
+///
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::A => transition!(S1),
+///             _ => ignored!()
+///         }
+///     }
+/// }
+///# #[state(super_state= S0)]
+///# impl State<S01> for BasicStateMachine{
+///#     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///#         ignored!()
+///#     }
+///# }
+///
+/// #[state(super_state= Top)]
+/// impl State<S1> for BasicStateMachine{
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::B => transition_history!(S0),
+///             _ => ignored!()
+///         }
+///     }
+/// }
+///
+/// let ism = InitStateMachine::from(BasicStateMachine{ s0_history: History::new() });
+/// let mut sm = ism.init();
+/// ```
+#[repr(transparent)]
+pub struct History<UserStateMachine: TopState + ?Sized> {
+    pub(crate) state_fn: Option<StateFn<UserStateMachine>>,
+}
+
+impl<UserStateMachine: TopState + ?Sized> History<UserStateMachine> {
+    /// Create an empty history, as if the composite state had never been exited.
+    pub const fn new() -> History<UserStateMachine> {
+        History { state_fn: None }
+    }
+
+    /// Forget the recorded substate. The next history transition to the composite state will go
+    /// through its [`State::init()`] method.
+    pub fn clear(&mut self) {
+        self.state_fn = None;
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized> Default for History<UserStateMachine> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,4 +1,8 @@
+use crate::error::InitError;
+use crate::evt_queue::EvtQueue;
 use crate::proto_state_machine::TopState;
+use crate::state::{denatured, HasTopStateVTable, InitResult, StateRef};
+use crate::state_machine::{disable_poster, enable_poster};
 use crate::{sm_business_logic::SMBusinessLogic, StateMachine};
 
 //For doc
@@ -23,22 +27,135 @@ impl<UserStateMachine: TopState> InitStateMachine<UserStateMachine> {
     /// Consume the structure instance, triggerring the call to [`TopState::init()`] and
     /// performing transition to the first state. A fully operational state machine
     /// is returned.   
-    pub fn init(mut self) -> StateMachine<UserStateMachine> {
+    /// The returned state machine has no room for deferring events, use
+    /// [`InitStateMachine::init_with_defer_queue()`] if some of your states defer events.
+    ///
+    /// # Panics
+    /// Panics if [`TopState::init()`] does not return a target state or if the state machine is
+    /// found to be inconsistent. Use [`InitStateMachine::try_init()`] for handling those errors.
+    pub fn init(self) -> StateMachine<UserStateMachine> {
+        expect_init(self.try_init())
+    }
+
+    /// Same as [`InitStateMachine::init()`], except that errors are returned instead of causing
+    /// a panic.
+    /// # Example
+    /// ```
+    ///# use kaori_hsm::*;
+    ///# enum BasicEvt{A}
+    ///# struct BasicStateMachine{}
+    /// impl TopState for BasicStateMachine{
+    ///   type Evt = BasicEvt;
+    ///   fn init(&mut self) -> InitResult<Self> {
+    ///     InitResult::NotImplemented
+    ///   }
+    /// }
+    ///
+    /// let ism = InitStateMachine::from(BasicStateMachine{});
+    /// assert!(matches!(ism.try_init(), Err(InitError::MissingTopInitTarget)));
+    /// ```
+    pub fn try_init(self) -> Result<StateMachine<UserStateMachine>, InitError> {
+        self.init_state_machine(EvtQueue::new(None))
+    }
+
+    /// Same as [`InitStateMachine::init()`], except that the returned state machine has a defer
+    /// queue able to hold `DEFER_CAPACITY` events. Deferring events requires the event type
+    /// to implement `Clone`.
+    /// # Example
+    /// ```
+    ///# use kaori_hsm::*;
+    ///# #[derive(Clone)]
+    ///# enum BasicEvt{A}
+    ///# struct BasicStateMachine{}
+    ///# impl TopState for BasicStateMachine{
+    ///#   type Evt = BasicEvt;
+    ///#   fn init(&mut self) -> InitResult<Self> {
+    ///#     init_transition!(S0)
+    ///#   }
+    ///# }
+    ///# #[state(super_state= Top)]
+    ///# impl State<S0> for BasicStateMachine{
+    ///#     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+    ///#         deferred!()
+    ///#     }
+    ///# }
+    /// let ism = InitStateMachine::from(BasicStateMachine{});
+    /// let mut sm: StateMachine<_, 4> = ism.init_with_defer_queue();
+    /// sm.dispatch(&BasicEvt::A);
+    /// assert_eq!(sm.deferred_evt_count(), 1);
+    /// ```
+    pub fn init_with_defer_queue<const DEFER_CAPACITY: usize>(
+        self,
+    ) -> StateMachine<UserStateMachine, DEFER_CAPACITY>
+    where
+        <UserStateMachine as TopState>::Evt: Clone,
+    {
+        expect_init(self.try_init_with_defer_queue())
+    }
+
+    /// Same as [`InitStateMachine::init_with_defer_queue()`], except that errors are returned
+    /// instead of causing a panic.
+    pub fn try_init_with_defer_queue<const DEFER_CAPACITY: usize>(
+        self,
+    ) -> Result<StateMachine<UserStateMachine, DEFER_CAPACITY>, InitError>
+    where
+        <UserStateMachine as TopState>::Evt: Clone,
+    {
+        self.init_state_machine(EvtQueue::new(Some(
+            <<UserStateMachine as TopState>::Evt as Clone>::clone,
+        )))
+    }
+
+    fn init_state_machine<const DEFER_CAPACITY: usize>(
+        mut self,
+        defer_queue: EvtQueue<<UserStateMachine as TopState>::Evt, DEFER_CAPACITY>,
+    ) -> Result<StateMachine<UserStateMachine, DEFER_CAPACITY>, InitError> {
+        enable_poster(&mut self.user_state_machine);
+
         // Call user top initial pseudostate implementation
         let init_result = self.user_state_machine.init();
 
-        unsafe {
-            let curr_state_fn = <Self as SMBusinessLogic>::init(
-                core::mem::transmute(&mut self.user_state_machine),
-                core::mem::transmute(&init_result),
-            );
+        let curr_state = unsafe {
+            <Self as SMBusinessLogic>::init(
+                core::mem::transmute::<&mut UserStateMachine, &mut denatured::OpaqueType>(
+                    &mut self.user_state_machine,
+                ),
+                core::mem::transmute::<&InitResult<UserStateMachine>, &denatured::InitResult>(
+                    &init_result,
+                ),
+            )
+        };
+        let curr_state = match curr_state {
+            Ok(curr_state) => curr_state,
+            Err(init_error) => {
+                disable_poster(&mut self.user_state_machine);
+                return Err(init_error);
+            }
+        };
 
+        let mut state_machine = unsafe {
             StateMachine {
                 user_state_machine: self.user_state_machine,
-                curr_state: core::mem::transmute(curr_state_fn),
+                curr_state: core::mem::transmute::<denatured::StateRef, StateRef<UserStateMachine>>(
+                    curr_state,
+                ),
+                defer_queue,
             }
-        }
+        };
+
+        // The events posted during the initialization are processed by the operational state
+        // machine
+        disable_poster(&mut state_machine.user_state_machine);
+        state_machine.process_posted_evts()?;
+        Ok(state_machine)
     }
 }
 
-impl<UserStateMachine: TopState> SMBusinessLogic for InitStateMachine<UserStateMachine> {}
+fn expect_init<T>(init_result: Result<T, InitError>) -> T {
+    init_result.unwrap_or_else(|init_error| panic!("{}", init_error))
+}
+
+impl<UserStateMachine: TopState> SMBusinessLogic for InitStateMachine<UserStateMachine> {
+    const TOP_STATE_FN: denatured::StateFn =
+        &<UserStateMachine as HasTopStateVTable>::TOP_STATE_VTABLE;
+}
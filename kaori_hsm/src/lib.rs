@@ -6,6 +6,8 @@
 //! Some of the key advantages of this library are:
 //! - No use of dynamic memory allocation
 //! - Fast execution, low stack and program memory usage
+//! - Stack usage independent of the depth of the state hierarchy, only growing with the nesting
+//!   of orthogonal states
 //! - no use of rust standard library, nor any other external crate
 //!## What are hierarchical state machines ?
 //! States machines are software entities processing events differently depending on the state in
@@ -23,13 +25,15 @@
 //! To build your own state machine, you first have to define the structure that will hold its
 //! data and then you will need to implement the following traits of the library on it: the [`TopState`]
 //! trait and as many variants of the [`State<Tag>`] trait as you want to define states.
+//! Alternatively, the `hsm!{}` macro declares the whole state machine in a single block, from which
+//! it implements these traits.
 //!
 //! The following sequence has to be followed in order to build an operational state machine:
 //! - Create an instance of the structure which will hold the data of your state machine.
 //! - Encapsulate an instance of this structure into an InitStateMachine instance using the [`InitStateMachine::from()`] function.
 //! - Initialize the state machine by calling the [`InitStateMachine::init()`] method on this instance. It will initialize the state machine and lead
-//! it to its first state. A [`StateMachine`] instance will be returned from this method. This type represents a fully operational state machine
-//! and only exposes the [`StateMachine::dispatch()`] method used for injecting event variants into it.
+//!   it to its first state. A [`StateMachine`] instance will be returned from this method. This type represents a fully operational state machine
+//!   and mainly exposes the [`StateMachine::dispatch()`] method used for injecting event variants into it.
 //!
 //! ## Examples across the  project
 //! This library features many examples that show you its potential and help you understand how to use it. Most of them can be
@@ -250,20 +254,48 @@
 //! at your option.
 
 #![no_std]
+mod choice;
+mod error;
+mod evt_queue;
+mod fallible;
+mod final_state;
+mod history;
 mod init_state_machine;
+mod lineage;
+mod poster;
 mod proto_state_machine;
+mod regions;
 mod sm_business_logic;
 mod state;
+mod state_id;
 mod state_machine;
+pub use choice::Choice;
+pub use error::{DispatchError, InitError, ModelError};
+pub use fallible::{FallibleState, FallibleTopState};
+pub use final_state::FinalState;
+pub use history::History;
 pub use init_state_machine::InitStateMachine;
+#[doc(hidden)]
+pub use lineage::Lineage;
+pub use poster::{PostQueue, Poster};
 pub use proto_state_machine::TopState;
-pub use state::{HandleResult, InitResult, ParentState, State};
-pub use state_machine::StateMachine;
+#[doc(hidden)]
+pub use regions::RegionRecord;
+pub use regions::Regions;
+pub use state::{DispatchOutcome, HandleResult, InitResult, ParentState, State, StateRef};
+#[doc(hidden)]
+pub use state::StateFn;
+pub use state_id::HasStateId;
+#[doc(hidden)]
+pub use state_id::{check_listed_state, ListedState, ProbeNoStateId, ProbeStateId, StateIdProbe};
+pub use state_machine::{ActiveStates, StateMachine};
 extern crate kaori_hsm_derive;
-pub use kaori_hsm_derive::state;
+pub use kaori_hsm_derive::{choice, final_state, hsm, state, state_machine};
 
 /// Sugar for constructing a `InitResult::TargetState` enum variant containing the target of the
-/// initial transition. Can be either used in [`TopState::init`] or [`State<Tag>::init`]
+/// initial transition. Can be either used in [`TopState::init`] or [`State<Tag>::init`]. The tag
+/// of the target may be a path, as in `init_transition!(motor::Starting)`, when the target state
+/// is declared in another module.
 /// # Example
 /// ```
 ///# use kaori_hsm::*;
@@ -296,7 +328,7 @@ pub use kaori_hsm_derive::state;
 ///         }
 ///     }    
 ///#  
-///# #[state(super_state= Top)]
+///# #[state(super_state= S0)]
 ///# impl State<S1> for BasicStateMachine{
 ///#  
 ///#      fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
@@ -308,21 +340,29 @@ pub use kaori_hsm_derive::state;
 ///```
 #[macro_export]
 macro_rules! init_transition {
-    ($target_state_tag:ident) => {
-        kaori_hsm::InitResult::TargetState(kaori_hsm::State::<$target_state_tag>::core_handle)
+    ($target_state_tag:path) => {
+        $crate::InitResult::TargetState($crate::State::<$target_state_tag>::state_fn())
     };
 }
 
 /// Sugar for constructing a `HandleResult::Transition` enum variant containing the target of the
-/// transition
+/// transition. An action may be attached to the transition with `action = method`, `method`
+/// taking `&mut self` as only argument. It is executed once every state left by the transition has
+/// been exited, and before any state is entered.
 /// # Example
 /// ```
 ///# use kaori_hsm::*;
-///# enum BasicEvt{A}
+///# enum BasicEvt{A, B}
 ///#
 ///# struct BasicStateMachine{
 ///# }
 ///#
+///# impl BasicStateMachine{
+///#     fn on_b(&mut self){
+///#         println!("S0-TO-S1-ON-B");
+///#     }
+///# }
+///#
 ///#
 ///# impl TopState for BasicStateMachine{
 ///#   type Evt = BasicEvt;
@@ -352,14 +392,94 @@ macro_rules! init_transition {
 ///               println!("S0-HANDLES-A");
 ///               transition!(S0)
 ///             }
+///             BasicEvt::B => transition!(S1, action = Self::on_b)
 ///         }
 ///     }    
 /// }
 ///```
 #[macro_export]
 macro_rules! transition {
-    ($target_state_tag:ident) => {
-        kaori_hsm::HandleResult::Transition(kaori_hsm::State::<$target_state_tag>::core_handle)
+    ($target_state_tag:path) => {
+        $crate::HandleResult::Transition(
+            $crate::State::<$target_state_tag>::state_fn(),
+            None,
+        )
+    };
+    ($target_state_tag:path, action = $action:expr) => {
+        $crate::HandleResult::Transition(
+            $crate::State::<$target_state_tag>::state_fn(),
+            Some($action as fn(&mut _)),
+        )
+    };
+}
+/// Sugar for constructing a `HandleResult::HistoryTransition` enum variant containing the
+/// composite state whose history is the target of the transition. The substate recorded in the
+/// [`History`] of the composite state is re-entered, or the initial transition of the composite
+/// state is taken if it has never been exited. As with [`transition!()`], an action may be
+/// attached to the transition with `action = method`.
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# enum BasicEvt{A}
+///#
+///# struct BasicStateMachine{
+///#     s0_history: History<Self>,
+///# }
+///#
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#    init_transition!(S1)
+///#   }
+///#
+///# }
+///#
+///# #[state(super_state= Top, shallow_history= s0_history)]
+///# impl State<S0> for BasicStateMachine{
+///#     fn init(&mut self) -> InitResult<Self> {
+///#         init_transition!(S01)
+///#     }
+///#
+///#     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///#         ignored!()
+///#     }
+///# }
+///#
+///# #[state(super_state= S0)]
+///# impl State<S01> for BasicStateMachine{
+///#     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///#         ignored!()
+///#     }
+///# }
+///#
+/// #[state(super_state= Top)]
+/// impl State<S1> for BasicStateMachine{
+///
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::A => {
+///               println!("S1-HANDLES-A");
+///               transition_history!(S0)
+///             }
+///         }
+///     }
+/// }
+///```
+#[macro_export]
+macro_rules! transition_history {
+    ($target_state_tag:path) => {
+        $crate::HandleResult::HistoryTransition(
+            $crate::State::<$target_state_tag>::state_fn(),
+            None,
+        )
+    };
+    ($target_state_tag:path, action = $action:expr) => {
+        $crate::HandleResult::HistoryTransition(
+            $crate::State::<$target_state_tag>::state_fn(),
+            Some($action as fn(&mut _)),
+        )
     };
 }
 /// Sugar for constructing a `HandleResult::Ignored` enum variant meaning no event has been handled
@@ -393,7 +513,7 @@ macro_rules! transition {
 #[macro_export]
 macro_rules! ignored {
     () => {
-        kaori_hsm::HandleResult::Ignored
+        $crate::HandleResult::Ignored
     };
 }
 /// Sugar for constructing a `HandleResult::Handle` enum variant meaning the event has been caught
@@ -431,6 +551,90 @@ macro_rules! ignored {
 #[macro_export]
 macro_rules! handled {
     () => {
-        kaori_hsm::HandleResult::Handled
+        $crate::HandleResult::Handled
+    };
+}
+/// Sugar for constructing a `HandleResult::Deferred` enum variant meaning the event is kept in
+/// the defer queue of the state machine until the next transition, after which it is dispatched
+/// again. See [`InitStateMachine::init_with_defer_queue()`].
+/// ```
+///# use kaori_hsm::*;
+///# #[derive(Clone)]
+///# enum BasicEvt{A}
+///#
+///# struct BasicStateMachine{
+///# }
+///#
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#    init_transition!(S0)
+///#   }
+///#
+///# }
+///#
+/// #[state(super_state= Top)]
+/// impl State<S0> for BasicStateMachine{
+///
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::A => {
+///                 println!("S0-DEFERS-A");
+///                 deferred!()
+///             }
+///         }
+///     }
+/// }
+///```
+#[macro_export]
+macro_rules! deferred {
+    () => {
+        $crate::HandleResult::Deferred
+    };
+}
+
+/// Sugar for constructing a `HandleResult::Terminate` enum variant meaning every active state is
+/// exited and the state machine terminates, as when reaching a final state of the topmost region.
+/// See [`StateMachine::is_terminated()`].
+/// ```
+///# use kaori_hsm::*;
+///# enum BasicEvt{A}
+///#
+///# struct BasicStateMachine{
+///# }
+///#
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#    init_transition!(S0)
+///#   }
+///#
+///# }
+///#
+/// #[state(super_state= Top)]
+/// impl State<S0> for BasicStateMachine{
+///
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::A => {
+///                 println!("S0-TERMINATES");
+///                 terminate!()
+///             }
+///         }
+///     }
+/// }
+///
+/// let mut sm = InitStateMachine::from(BasicStateMachine{}).init();
+/// sm.dispatch(&BasicEvt::A);
+/// assert!(sm.is_terminated());
+///```
+#[macro_export]
+macro_rules! terminate {
+    () => {
+        $crate::HandleResult::Terminate
     };
 }
@@ -0,0 +1,80 @@
+/// Position of a state in the hierarchy of the state machine, as declared through the
+/// `#[state()]` procedural macro. The lineage of every state is a constant, so that the hierarchy
+/// can be validated at compile time, and so that the state machine knows the depth of a state
+/// without ascending the hierarchy.
+#[doc(hidden)]
+pub enum Lineage {
+    /// The state has not been declared through the `#[state()]` procedural macro.
+    Unknown,
+    Top,
+    State {
+        /// Path of the tag of the state, made of the path of the module declaring it followed by
+        /// its name. Unlike the name, it tells apart the states whose tags share the same name.
+        key: &'static str,
+        parent: &'static Lineage,
+        /// Number of ancestors of the state, the top state included, or `None` if the lineage of
+        /// one of them is unknown.
+        depth: Option<usize>,
+    },
+}
+
+impl Lineage {
+    /// Return the lineage of the state whose tag has the `key` path, and whose parent has the
+    /// `parent` lineage.
+    pub const fn state(key: &'static str, parent: &'static Lineage) -> Lineage {
+        Lineage::State {
+            key,
+            parent,
+            depth: match parent {
+                Lineage::Top => Some(1),
+                Lineage::State {
+                    depth: Some(depth), ..
+                } => Some(*depth + 1),
+                _ => None,
+            },
+        }
+    }
+
+    /// Return `false` only if the present state is known not to be a strict descendant of the
+    /// `ancestor` state.
+    pub const fn may_descend_from(&self, ancestor: &Lineage) -> bool {
+        let ancestor_key = match ancestor {
+            Lineage::Unknown => return true,
+            Lineage::Top => return !matches!(self, Lineage::Top),
+            Lineage::State { key, .. } => *key,
+        };
+
+        let mut lineage = match self {
+            Lineage::State { parent, .. } => *parent,
+            Lineage::Unknown => return true,
+            Lineage::Top => return false,
+        };
+        loop {
+            match lineage {
+                Lineage::State { key, parent, .. } => {
+                    if str_eq(key, ancestor_key) {
+                        return true;
+                    }
+                    lineage = parent;
+                }
+                Lineage::Unknown => return true,
+                Lineage::Top => return false,
+            }
+        }
+    }
+}
+
+const fn str_eq(lhs: &str, rhs: &str) -> bool {
+    let (lhs, rhs) = (lhs.as_bytes(), rhs.as_bytes());
+    if lhs.len() != rhs.len() {
+        return false;
+    }
+    let mut index = 0;
+    while index < lhs.len() {
+        if lhs[index] != rhs[index] {
+            return false;
+        }
+        index += 1;
+    }
+    true
+}
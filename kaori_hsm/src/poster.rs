@@ -0,0 +1,157 @@
+use crate::evt_queue::EvtQueue;
+use crate::proto_state_machine::TopState;
+
+//For doc
+#[allow(unused_imports)]
+use crate::{InitStateMachine, State, StateMachine};
+
+/// Handle through which the states of a state machine post internal events to it.
+///
+/// A `Poster` field must be declared in the user state machine structure and be returned by
+/// [`TopState::poster()`]. It holds the queue of the posted events, able to hold `CAPACITY`
+/// events. While the state machine processes an event, the poster accepts the events posted
+/// with [`Poster::post()`] by the [`State::handle()`], [`State::entry()`] and [`State::exit()`]
+/// methods.
+///
+/// Posted events are dispatched in their order of arrival by [`StateMachine::dispatch()`] once
+/// the processing of the current event is completed, and before it returns. The events posted
+/// during the initialization of the state machine are dispatched at the end of
+/// [`InitStateMachine::init()`].
+///
+/// # Location of the queue
+/// The queue of the posted events is held by the poster, in the user state machine structure,
+/// and not by the [`StateMachine`]. The states only reach the user state machine structure
+/// through `&mut self`, and a handle referring to a queue held by the [`StateMachine`] would
+/// dangle as soon as the state machine is moved. The capacity of the queue is therefore a
+/// parameter of the poster instead of the [`StateMachine`]. The poster does not refer to the
+/// state machine, and is neither `Send` nor `Sync`.
+///
+/// # Moved poster
+/// When the state machine starts processing an event, it records the address of the poster
+/// returned by [`TopState::poster()`], and [`Poster::post()`] only accepts events while the
+/// poster is at this address. If a state moves the poster during the processing of an event,
+/// alone or along with the whole structure, as `core::mem::replace()` would do:
+/// - the moved poster gives back every event posted to it, and the events it already held are
+///   never dispatched;
+/// - the poster left in the field returned by [`TopState::poster()`] may give back the events
+///   posted to it until the state machine processes the next event.
+///
+/// The result of [`Poster::post()`] must therefore be checked for an event not to be lost
+/// silently.
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# enum BasicEvt{Start, Done}
+/// struct BasicStateMachine{
+///     poster: Poster<Self, 1>,
+///     done: bool,
+/// }
+///
+/// impl TopState for BasicStateMachine{
+///     type Evt = BasicEvt;
+///
+///     fn init(&mut self) -> InitResult<Self> {
+///         init_transition!(S0)
+///     }
+///
+///     fn poster(&mut self) -> Option<&mut dyn PostQueue<BasicEvt>> {
+///         Some(&mut self.poster)
+///     }
+/// }
+///
+/// #[state(super_state= Top)]
+/// impl State<S0> for BasicStateMachine{
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::Start => {
+///                 if let Err(_evt) = self.poster.post(BasicEvt::Done) {
+///                     // The post queue is full
+///                 }
+///                 handled!()
+///             }
+///             BasicEvt::Done => {
+///                 self.done = true;
+///                 handled!()
+///             }
+///         }
+///     }
+/// }
+///
+/// let ism = InitStateMachine::from(BasicStateMachine{ poster: Poster::new(), done: false });
+/// let mut sm = ism.init();
+/// sm.dispatch(&BasicEvt::Start);
+/// ```
+pub struct Poster<UserStateMachine: TopState + ?Sized, const CAPACITY: usize> {
+    post_queue: EvtQueue<<UserStateMachine as TopState>::Evt, CAPACITY>,
+    // Address of the poster while the state machine processes an event, null otherwise. A
+    // poster which has been moved since is not at this address anymore.
+    enabled_at: *const (),
+}
+
+impl<UserStateMachine: TopState + ?Sized, const CAPACITY: usize>
+    Poster<UserStateMachine, CAPACITY>
+{
+    /// Create a poster with an empty queue.
+    pub const fn new() -> Poster<UserStateMachine, CAPACITY> {
+        Poster {
+            post_queue: EvtQueue::new(None),
+            enabled_at: core::ptr::null(),
+        }
+    }
+
+    /// Post `evt` to the state machine, which will dispatch it after the processing of the
+    /// current event. The event is given back if the post queue is full, if the state machine
+    /// is not processing any event, or if the poster has been moved out of the state machine.
+    pub fn post(
+        &mut self,
+        evt: <UserStateMachine as TopState>::Evt,
+    ) -> Result<(), <UserStateMachine as TopState>::Evt> {
+        if !self.is_enabled() {
+            return Err(evt);
+        }
+        self.post_queue.try_push(evt)
+    }
+
+    fn is_enabled(&self) -> bool {
+        core::ptr::eq(self.enabled_at, self as *const Self as *const ())
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized, const CAPACITY: usize> Default
+    for Poster<UserStateMachine, CAPACITY>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Queue of the events posted by the states of a state machine, returned by
+/// [`TopState::poster()`] and implemented by [`Poster`].
+pub trait PostQueue<Evt> {
+    // Accept the events posted while the state machine processes an event
+    #[doc(hidden)]
+    fn enable(&mut self);
+
+    #[doc(hidden)]
+    fn disable(&mut self);
+
+    // Pop the oldest posted event
+    #[doc(hidden)]
+    fn pop(&mut self) -> Option<Evt>;
+}
+
+impl<UserStateMachine: TopState + ?Sized, const CAPACITY: usize>
+    PostQueue<<UserStateMachine as TopState>::Evt> for Poster<UserStateMachine, CAPACITY>
+{
+    fn enable(&mut self) {
+        self.enabled_at = self as *const Self as *const ();
+    }
+
+    fn disable(&mut self) {
+        self.enabled_at = core::ptr::null();
+    }
+
+    fn pop(&mut self) -> Option<<UserStateMachine as TopState>::Evt> {
+        self.post_queue.pop()
+    }
+}
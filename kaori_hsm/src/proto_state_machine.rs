@@ -1,9 +1,9 @@
+use crate::poster::PostQueue;
+use crate::state::{HandleResult, InitResult};
+
 //For doc
 #[allow(unused_imports)]
-use crate::{
-    state::{CoreHandleResult, HandleResult, InitResult, ParentState, State},
-    InitStateMachine, StateMachine,
-};
+use crate::{state::State, InitStateMachine, Poster, StateMachine};
 
 /// Define the initial pseudostate and the type of event variant the state machine can receive.
 /// # Example
@@ -55,4 +55,25 @@ pub trait TopState {
     /// The user must implement this method and return a state which has the top state as its
     /// parent.  
     fn init(&mut self) -> InitResult<Self>;
+
+    /// Receive the events which have been ignored by every active state, in the manner of the
+    /// top state of a Samek-style hierarchical state machine.
+    /// The returned [`HandleResult`] is treated as if it was returned by a state whose parent is
+    /// the top state: the event may be dropped (`Ignored`), handled (`Handled`) or trigger a
+    /// transition after all the active states have been exited (`Transition`), or terminate the
+    /// state machine (`Terminate`).
+    /// # Implementation policy
+    /// The implementation of this method is optional. The default implementation ignores the event.
+    fn on_unhandled(&mut self, _evt: &Self::Evt) -> HandleResult<Self> {
+        HandleResult::Ignored
+    }
+
+    /// Return the [`Poster`] through which the states post internal events to the state
+    /// machine.
+    /// # Implementation policy
+    /// The implementation of this method is optional. It must return a `Poster` field of the
+    /// structure for internal events to be posted, always the same one.
+    fn poster(&mut self) -> Option<&mut dyn PostQueue<Self::Evt>> {
+        None
+    }
 }
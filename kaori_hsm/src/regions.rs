@@ -0,0 +1,135 @@
+use crate::proto_state_machine::TopState;
+use crate::state::StateRef;
+
+//For doc
+#[allow(unused_imports)]
+use crate::{State, StateMachine};
+
+/// Record of the active state of each of the `N` orthogonal regions of a state.
+///
+/// An orthogonal state (or AND-state) is a composite state whose direct substates, called region
+/// states, are all active at the same time. Every event dispatched to the state machine is
+/// dispatched to each region in turn, the orthogonal state and its ancestors only receiving the
+/// events ignored by all of its regions.
+///
+/// A `Regions` field must be declared in the user state machine structure for each orthogonal
+/// state, and be assigned to this state with the `regions` argument of the `#[state()]`
+/// procedural macro, which also lists the region states in their order of entry:
+/// `regions = [RegionA, RegionB] in field_name`. The `N` parameter must match the number of
+/// listed region states.
+///
+/// Entering the orthogonal state enters each region state in turn, which then goes through its
+/// own initial transition. The [`State::init()`] method of the orthogonal state is not used.
+/// Transitions between states of a same region only affect this region, while a transition
+/// leaving a region exits every region of the orthogonal state before proceeding.
+///
+/// Each region also records the state it was left in. A deep history restoring an orthogonal
+/// state, or one of its ancestors, restores every region down to this state, while a shallow
+/// history enters the regions through their initial transitions.
+///
+/// *Note: The history of a state only records states of its own region. A transition targeting
+/// the history of a state inside a region enters the other regions through their initial
+/// transitions.*
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# enum BasicEvt{A, B}
+/// struct BasicStateMachine{
+///     s0_regions: Regions<Self, 2>,
+/// }
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+///
+/// #[state(super_state= Top, regions= [S01, S02] in s0_regions)]
+/// impl State<S0> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// #[state(super_state= S0)]
+/// impl State<S01> for BasicStateMachine{
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::A => handled!(),
+///             _ => ignored!()
+///         }
+///     }
+/// }
+///
+/// #[state(super_state= S0)]
+/// impl State<S02> for BasicStateMachine{
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::B => handled!(),
+///             _ => ignored!()
+///         }
+///     }
+/// }
+///
+/// let ism = InitStateMachine::from(BasicStateMachine{ s0_regions: Regions::new() });
+/// let mut sm = ism.init();
+/// assert!(sm.is_in::<S01>() && sm.is_in::<S02>());
+/// ```
+pub struct Regions<UserStateMachine: TopState + ?Sized, const N: usize> {
+    records: [RegionRecord<UserStateMachine>; N],
+}
+
+impl<UserStateMachine: TopState + ?Sized, const N: usize> Regions<UserStateMachine, N> {
+    /// Create the record of inactive regions.
+    pub const fn new() -> Regions<UserStateMachine, N> {
+        Regions {
+            records: [RegionRecord {
+                active_state: None,
+                last_state: None,
+            }; N],
+        }
+    }
+
+    /// Return the innermost active state of the region at index `region`, or `None` if the
+    /// orthogonal state is not active or if there is no such region.
+    pub fn active_state(&self, region: usize) -> Option<StateRef<UserStateMachine>> {
+        self.records
+            .get(region)
+            .and_then(|record| record.active_state)
+    }
+
+    #[doc(hidden)]
+    pub fn as_slice(&self) -> &[RegionRecord<UserStateMachine>] {
+        &self.records
+    }
+
+    #[doc(hidden)]
+    pub fn as_mut_slice(&mut self) -> &mut [RegionRecord<UserStateMachine>] {
+        &mut self.records
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized, const N: usize> Default for Regions<UserStateMachine, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Active state of a region, and state it was current in when it was last exited. The layout
+// matches `denatured::RegionRecord`.
+#[doc(hidden)]
+#[repr(C)]
+pub struct RegionRecord<UserStateMachine: TopState + ?Sized> {
+    active_state: Option<StateRef<UserStateMachine>>,
+    last_state: Option<StateRef<UserStateMachine>>,
+}
+
+impl<UserStateMachine: TopState + ?Sized> Clone for RegionRecord<UserStateMachine> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized> Copy for RegionRecord<UserStateMachine> {}
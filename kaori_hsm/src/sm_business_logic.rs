@@ -1,124 +1,886 @@
-use crate::state::denatured;
+use crate::error::{InitError, ModelError};
+use crate::lineage::Lineage;
+use crate::state::{denatured, StateUid};
 
-pub struct Link<'a> {
+// Path descending from the `top_state_fn` state to its `bottom_state_fn` descendant, `length`
+// being the number of states below `top_state_fn`. The states in between are not stored but found
+// again by ascending from the bottom state, so that a path takes the same stack space whatever
+// the depth of the state hierarchy.
+#[derive(Clone, Copy)]
+pub struct Path {
+    top_state_fn: denatured::StateFn,
+    bottom_state_fn: denatured::StateFn,
+    length: usize,
+}
+
+// Maximum number of states of a path collected through a single ascent when the path is entered
+const PATH_CHUNK_LENGTH: usize = 8;
+
+// LCA of a transition, along with the path descending from it to the target state
+pub enum LCANature {
+    TopState(Path),
+    State(Path),
+    // The LCA is not inside the region in which the transition has been triggered
+    Container,
+}
+
+// Way to descend from the target state of a transition
+#[derive(Clone, Copy)]
+pub enum TargetKind {
+    Init,
+    History,
+    // Descend to the states recorded when the target state was last exited, down to the active
+    // states of its regions
+    Restore,
+}
+
+// Target state of a transition, along with the way to descend from it and the action to execute
+// between the exit and the entry phases
+#[derive(Clone, Copy)]
+pub struct Target {
     state_fn: denatured::StateFn,
-    next_link: Option<&'a Link<'a>>,
+    kind: TargetKind,
+    action: Option<denatured::ActionFn>,
+}
+
+// Result of the dispatch of a user event to the active states of a region
+pub enum RegionOutcome {
+    Ignored,
+    Processed(denatured::DispatchOutcome),
+    Escaped {
+        source: denatured::StateFn,
+        target: Target,
+    },
+    // Every state of the region has been exited, and the termination of the state machine has
+    // to be carried on by the enclosing region
+    Terminated {
+        terminated_by: denatured::StateFn,
+    },
+    // Every region of an orthogonal state has reached a final state during the dispatch, and
+    // the completion of the orthogonal state has to be carried on by the region containing it
+    Completed(denatured::DispatchOutcome),
 }
-pub enum LCANature<'a> {
-    TopState,
-    State(&'a Link<'a>),
+
+// Result of a transition taken inside a region
+pub enum TransitionOutcome {
+    // The transition has been completed inside the region, `target` being its final target once
+    // every choice pseudostate has been resolved.
+    Completed { target: denatured::StateFn },
+    // The LCA is not inside the region, whose states have all been exited
+    Escaped { target: Target },
 }
+
+// The current state is explicitly dereferenced, for it not to be mistaken with the reference
+// through which it is updated.
+#[allow(clippy::explicit_auto_deref)]
 pub trait SMBusinessLogic {
-    // Function existing only in order to attribute a unique adress to what is refered as the
-    // top state. Is not meant to be called.
-    fn top_state_fn(
-        _user_state_machine: *mut denatured::OpaqueType,
-        _evt: &denatured::CoreEvt,
-    ) -> denatured::CoreHandleResult {
-        panic!("Top state function should never be called !");
-    }
+    // Table of functions standing for what is refered as the top state, which forwards the user
+    // events ignored by every active state to `TopState::on_unhandled()`.
+    const TOP_STATE_FN: denatured::StateFn;
 
-    // Dispatch the user event to the current state.
+    // Dispatch the user event to the `current_state` of the state machine, which is updated
+    // along with its identifier.
     fn dispatch_evt_to_current_state(
         user_state_machine: &mut denatured::OpaqueType,
-        current_state_fn: &mut denatured::StateFn,
-        evt: &denatured::CoreEvt,
-    ) {
-        Self::dispatch_evt_to_handling_state(
+        current_state: &mut denatured::StateRef,
+        evt: &denatured::UserEvt,
+    ) -> Result<denatured::DispatchOutcome, ModelError> {
+        // The top state is considered as a state with a single region, out of which no transition
+        // can escape.
+        let dispatch_outcome = match Self::dispatch_evt_to_region(
             user_state_machine,
-            current_state_fn,
-            *current_state_fn,
+            &mut current_state.state_fn,
+            Self::TOP_STATE_FN,
             evt,
-        )
+        )? {
+            RegionOutcome::Processed(dispatch_outcome) => dispatch_outcome,
+            RegionOutcome::Ignored
+            | RegionOutcome::Escaped { .. }
+            | RegionOutcome::Terminated { .. }
+            | RegionOutcome::Completed(_) => denatured::DispatchOutcome::Ignored,
+        };
+        current_state.uid = Self::dispatch_get_uid(current_state.state_fn);
+        Ok(dispatch_outcome)
     }
 
-    // Dispatch the user event to a state different from the current one.
-    fn dispatch_evt_to_handling_state(
+    // Return `true` if `lhs_state_fn` and `rhs_state_fn` are the same state. The compiler being
+    // free to duplicate a constant across codegen units, the states are compared through their
+    // unique identifier. Equal addresses however always denote the same state.
+    fn same_state(lhs_state_fn: denatured::StateFn, rhs_state_fn: denatured::StateFn) -> bool {
+        core::ptr::eq(lhs_state_fn, rhs_state_fn)
+            || Self::dispatch_get_uid(lhs_state_fn) == Self::dispatch_get_uid(rhs_state_fn)
+    }
+
+    // Return `true` if the `state_fn` state is the top state, the only one whose lineage is
+    // `Lineage::Top`.
+    fn is_top_state(state_fn: denatured::StateFn) -> bool {
+        matches!(state_fn.lineage, Lineage::Top)
+    }
+
+    // Dispatch the user event to the `current_state_fn` state of a region of the
+    // `container_state_fn` state. If the current state is an orthogonal state, the event is
+    // first dispatched to each of its own regions. The states completed by a transition taken
+    // inside the region are completed before returning.
+    fn dispatch_evt_to_region(
         user_state_machine: &mut denatured::OpaqueType,
         current_state_fn: &mut denatured::StateFn,
-        handling_state_fn: denatured::StateFn,
-        evt: &denatured::CoreEvt,
-    ) {
-        let core_handle_result = handling_state_fn(user_state_machine, evt);
+        container_state_fn: denatured::StateFn,
+        evt: &denatured::UserEvt,
+    ) -> Result<RegionOutcome, ModelError> {
+        let orthogonal_state_fn = *current_state_fn;
 
-        // Treat result of the event dispatch
-        match core_handle_result {
-            denatured::CoreHandleResult::Handled => {}
-            denatured::CoreHandleResult::Ignored(parent_state_fn) => Self::dispatch_evt_to_parent(
+        let region_outcome =
+            match Self::dispatch_evt_to_regions(user_state_machine, orthogonal_state_fn, evt)? {
+                RegionOutcome::Completed(dispatch_outcome) => {
+                    return Self::complete_states(
+                        user_state_machine,
+                        current_state_fn,
+                        container_state_fn,
+                        Some(orthogonal_state_fn),
+                        dispatch_outcome,
+                    )
+                }
+                RegionOutcome::Ignored => Self::dispatch_evt_to_handling_state(
+                    user_state_machine,
+                    current_state_fn,
+                    container_state_fn,
+                    orthogonal_state_fn,
+                    evt,
+                ),
+                RegionOutcome::Escaped { source, target } => {
+                    // The transition is carried on from the orthogonal state, as if it had been
+                    // triggered by it, but without exiting it if it is the target.
+                    match Self::handle_transition(
+                        user_state_machine,
+                        current_state_fn,
+                        container_state_fn,
+                        orthogonal_state_fn,
+                        target,
+                        true,
+                    )? {
+                        TransitionOutcome::Completed { target } => Ok(RegionOutcome::Processed(
+                            denatured::DispatchOutcome::Transition {
+                                source: Self::dispatch_get_state_ref(source),
+                                target: Self::dispatch_get_state_ref(target),
+                            },
+                        )),
+                        TransitionOutcome::Escaped { target } => {
+                            Ok(RegionOutcome::Escaped { source, target })
+                        }
+                    }
+                }
+                RegionOutcome::Terminated { terminated_by } => Self::terminate(
+                    user_state_machine,
+                    current_state_fn,
+                    container_state_fn,
+                    orthogonal_state_fn,
+                    terminated_by,
+                ),
+                region_outcome => Ok(region_outcome),
+            }?;
+
+        match region_outcome {
+            RegionOutcome::Processed(
+                dispatch_outcome @ denatured::DispatchOutcome::Transition { .. },
+            ) => Self::complete_states(
                 user_state_machine,
                 current_state_fn,
-                parent_state_fn,
-                evt,
+                container_state_fn,
+                None,
+                dispatch_outcome,
             ),
-            denatured::CoreHandleResult::Transition(target_state_fn) => Self::handle_transition(
+            region_outcome => Ok(region_outcome),
+        }
+    }
+
+    // Dispatch the user event to each region of the `state_fn` state, if it has any. The outcome
+    // of the first region not ignoring the event is returned. A transition escaping one of the
+    // regions interrupts the dispatch. The orthogonal state is reported as completed if a
+    // transition has led the last of its regions to a final state.
+    fn dispatch_evt_to_regions(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        evt: &denatured::UserEvt,
+    ) -> Result<RegionOutcome, ModelError> {
+        let mut regions_outcome = RegionOutcome::Ignored;
+        let mut transition_taken = false;
+        let mut region = 0;
+
+        while let denatured::InitResult::TargetState(_) =
+            Self::dispatch_get_region_init(state_fn, region)
+        {
+            if let Some(mut leaf_state_fn) =
+                Self::dispatch_get_region_leaf(user_state_machine, state_fn, region)
+            {
+                match Self::dispatch_evt_to_region(
+                    user_state_machine,
+                    &mut leaf_state_fn,
+                    state_fn,
+                    evt,
+                )? {
+                    region_outcome @ (RegionOutcome::Escaped { .. }
+                    | RegionOutcome::Terminated { .. }) => {
+                        // Every state of the region has already been exited, `leaf_state_fn`
+                        // still being the state which was current before the transition started
+                        Self::dispatch_record_region_exit(
+                            user_state_machine,
+                            state_fn,
+                            region,
+                            leaf_state_fn,
+                        );
+                        return Ok(region_outcome);
+                    }
+                    region_outcome => {
+                        Self::dispatch_set_region_leaf(
+                            user_state_machine,
+                            state_fn,
+                            region,
+                            Some(leaf_state_fn),
+                        );
+                        transition_taken |= matches!(
+                            region_outcome,
+                            RegionOutcome::Processed(denatured::DispatchOutcome::Transition { .. })
+                        );
+                        if let RegionOutcome::Ignored = regions_outcome {
+                            regions_outcome = region_outcome;
+                        }
+                    }
+                }
+            }
+            region += 1;
+        }
+
+        match regions_outcome {
+            RegionOutcome::Processed(dispatch_outcome)
+                if transition_taken
+                    && Self::are_regions_completed(user_state_machine, state_fn) =>
+            {
+                Ok(RegionOutcome::Completed(dispatch_outcome))
+            }
+            regions_outcome => Ok(regions_outcome),
+        }
+    }
+
+    // Dispatch the user event to the `handling_state_fn` state, then to each of its ancestors
+    // as long as it is ignored, at the condition the ancestor is neither the top state nor the
+    // state containing the region.
+    fn dispatch_evt_to_handling_state(
+        user_state_machine: &mut denatured::OpaqueType,
+        current_state_fn: &mut denatured::StateFn,
+        container_state_fn: denatured::StateFn,
+        handling_state_fn: denatured::StateFn,
+        evt: &denatured::UserEvt,
+    ) -> Result<RegionOutcome, ModelError> {
+        let mut handling_state_fn = handling_state_fn;
+
+        loop {
+            // Treat result of the event dispatch
+            let handle_result = (handling_state_fn.handle)(user_state_machine, evt);
+            if !matches!(handle_result, denatured::HandleResult::Ignored) {
+                return Self::treat_handle_result(
+                    user_state_machine,
+                    current_state_fn,
+                    container_state_fn,
+                    handling_state_fn,
+                    handle_result,
+                );
+            }
+
+            match Self::dispatch_get_super_state(handling_state_fn) {
+                denatured::ParentState::Exists(super_state) => {
+                    if Self::same_state(super_state, container_state_fn) {
+                        return Ok(RegionOutcome::Ignored);
+                    }
+                    handling_state_fn = super_state;
+                }
+                denatured::ParentState::TopReached => {
+                    return Self::dispatch_evt_to_top_state(
+                        user_state_machine,
+                        current_state_fn,
+                        evt,
+                    )
+                }
+            }
+        }
+    }
+
+    // Dispatch the user event ignored by every active state to the top state.
+    fn dispatch_evt_to_top_state(
+        user_state_machine: &mut denatured::OpaqueType,
+        current_state_fn: &mut denatured::StateFn,
+        evt: &denatured::UserEvt,
+    ) -> Result<RegionOutcome, ModelError> {
+        match (Self::TOP_STATE_FN.handle)(user_state_machine, evt) {
+            denatured::HandleResult::Ignored => Ok(RegionOutcome::Processed(
+                denatured::DispatchOutcome::Ignored,
+            )),
+            handle_result => Self::treat_handle_result(
                 user_state_machine,
                 current_state_fn,
-                handling_state_fn,
-                target_state_fn,
+                Self::TOP_STATE_FN,
+                Self::TOP_STATE_FN,
+                handle_result,
             ),
-            _ => {}
         }
     }
 
-    // Dispatch the user event to the parent state at the condition it is not the top state.
-    fn dispatch_evt_to_parent(
+    // Take the action ordered by the `handling_state_fn` state after it handled the user event.
+    fn treat_handle_result(
         user_state_machine: &mut denatured::OpaqueType,
         current_state_fn: &mut denatured::StateFn,
-        parent_state_variant: denatured::ParentState,
-        evt: &denatured::CoreEvt,
-    ) {
-        match parent_state_variant {
-            denatured::ParentState::Exists(super_state) => Self::dispatch_evt_to_handling_state(
+        container_state_fn: denatured::StateFn,
+        handling_state_fn: denatured::StateFn,
+        handle_result: denatured::HandleResult,
+    ) -> Result<RegionOutcome, ModelError> {
+        let target = match handle_result {
+            denatured::HandleResult::Handled => {
+                return Ok(RegionOutcome::Processed(
+                    denatured::DispatchOutcome::Handled {
+                        handled_by: Self::dispatch_get_state_ref(handling_state_fn),
+                    },
+                ))
+            }
+            denatured::HandleResult::Deferred => {
+                return Ok(RegionOutcome::Processed(
+                    denatured::DispatchOutcome::Deferred {
+                        deferred_by: Self::dispatch_get_state_ref(handling_state_fn),
+                    },
+                ))
+            }
+            denatured::HandleResult::Terminate => {
+                return Self::terminate(
+                    user_state_machine,
+                    current_state_fn,
+                    container_state_fn,
+                    handling_state_fn,
+                    handling_state_fn,
+                )
+            }
+            handle_result => match Self::get_target(handle_result) {
+                Some(target) => target,
+                None => {
+                    return Ok(RegionOutcome::Processed(
+                        denatured::DispatchOutcome::Ignored,
+                    ))
+                }
+            },
+        };
+
+        match Self::handle_transition(
+            user_state_machine,
+            current_state_fn,
+            container_state_fn,
+            handling_state_fn,
+            target,
+            false,
+        )? {
+            TransitionOutcome::Completed { target } => Ok(RegionOutcome::Processed(
+                denatured::DispatchOutcome::Transition {
+                    source: Self::dispatch_get_state_ref(handling_state_fn),
+                    target: Self::dispatch_get_state_ref(target),
+                },
+            )),
+            TransitionOutcome::Escaped { target } => Ok(RegionOutcome::Escaped {
+                source: handling_state_fn,
+                target,
+            }),
+        }
+    }
+
+    // Complete the `completed_state_fn` state if provided, or else the state completed by the
+    // transition which led to the `current_state_fn` state of a region of the
+    // `container_state_fn` state, if any. The completion is repeated as long as the transition
+    // triggered by the completed state completes another state, the outcome of the dispatch
+    // becoming the last transition taken. A final state of the topmost region terminates the
+    // state machine.
+    fn complete_states(
+        user_state_machine: &mut denatured::OpaqueType,
+        current_state_fn: &mut denatured::StateFn,
+        container_state_fn: denatured::StateFn,
+        completed_state_fn: Option<denatured::StateFn>,
+        dispatch_outcome: denatured::DispatchOutcome,
+    ) -> Result<RegionOutcome, ModelError> {
+        let mut completed_state_fn = completed_state_fn.or_else(|| {
+            Self::get_completed_state(user_state_machine, *current_state_fn, container_state_fn)
+        });
+        let mut dispatch_outcome = dispatch_outcome;
+
+        while let Some(state_fn) = completed_state_fn {
+            if Self::is_top_state(state_fn) {
+                let final_state_fn = *current_state_fn;
+                return Self::terminate(
+                    user_state_machine,
+                    current_state_fn,
+                    container_state_fn,
+                    final_state_fn,
+                    final_state_fn,
+                );
+            }
+
+            let handle_result = (state_fn.on_completion)(user_state_machine);
+            match Self::treat_handle_result(
                 user_state_machine,
                 current_state_fn,
-                super_state,
-                evt,
-            ),
-            denatured::ParentState::TopReached => {}
+                container_state_fn,
+                state_fn,
+                handle_result,
+            )? {
+                RegionOutcome::Processed(
+                    transition @ denatured::DispatchOutcome::Transition { .. },
+                ) => {
+                    dispatch_outcome = transition;
+                    completed_state_fn = Self::get_completed_state(
+                        user_state_machine,
+                        *current_state_fn,
+                        container_state_fn,
+                    );
+                }
+                RegionOutcome::Ignored | RegionOutcome::Processed(_) => break,
+                region_outcome => return Ok(region_outcome),
+            }
         }
+        Ok(RegionOutcome::Processed(dispatch_outcome))
     }
 
-    // Take a transition from the `handling_state_fn` to the `target_state_fn`, thus setting
-    // `target_state_fn` as the new current state of the state machine at the end of the process.
-    fn handle_transition(
+    // Return the state completed once the `state_fn` state has become the current state of a
+    // region of the `container_state_fn` state, if any: either the parent of a final state, or an
+    // orthogonal state whose regions have all reached a final state. The completion of a region
+    // state is left to its orthogonal state.
+    fn get_completed_state(
+        user_state_machine: &denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        container_state_fn: denatured::StateFn,
+    ) -> Option<denatured::StateFn> {
+        if !state_fn.is_final {
+            return Self::are_regions_completed(user_state_machine, state_fn).then_some(state_fn);
+        }
+
+        let parent_state_fn = Self::get_parent_state_fn(state_fn);
+        let is_region_state = !Self::is_top_state(container_state_fn)
+            && Self::same_state(
+                Self::get_parent_state_fn(parent_state_fn),
+                container_state_fn,
+            );
+        (!is_region_state).then_some(parent_state_fn)
+    }
+
+    // Return `true` if the `state_fn` state is an orthogonal state whose regions have all reached
+    // one of their final states.
+    fn are_regions_completed(
+        user_state_machine: &denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+    ) -> bool {
+        let mut region = 0;
+
+        while let denatured::InitResult::TargetState(region_state_fn) =
+            Self::dispatch_get_region_init(state_fn, region)
+        {
+            match Self::dispatch_get_region_leaf(user_state_machine, state_fn, region) {
+                Some(leaf_state_fn)
+                    if leaf_state_fn.is_final
+                        && Self::same_state(
+                            Self::get_parent_state_fn(leaf_state_fn),
+                            region_state_fn,
+                        ) => {}
+                _ => return false,
+            }
+            region += 1;
+        }
+        region > 0
+    }
+
+    // Return the target of the transition ordered by the `handle_result`, if any.
+    fn get_target(handle_result: denatured::HandleResult) -> Option<Target> {
+        match handle_result {
+            denatured::HandleResult::Transition(state_fn, action) => Some(Target {
+                state_fn,
+                kind: TargetKind::Init,
+                action,
+            }),
+            denatured::HandleResult::HistoryTransition(state_fn, action) => Some(Target {
+                state_fn,
+                kind: TargetKind::History,
+                action,
+            }),
+            _ => None,
+        }
+    }
+
+    // Exit every state of the region from its `current_state_fn` up to the `handling_state_fn`
+    // (included). The current state becomes the top state once the state machine is terminated,
+    // that is once the states of the outermost region have all been exited.
+    fn terminate(
         user_state_machine: &mut denatured::OpaqueType,
         current_state_fn: &mut denatured::StateFn,
+        container_state_fn: denatured::StateFn,
         handling_state_fn: denatured::StateFn,
-        target_state_fn: denatured::StateFn,
-    ) {
-        Self::exit_substates(user_state_machine, *current_state_fn, handling_state_fn);
+        terminated_by: denatured::StateFn,
+    ) -> Result<RegionOutcome, ModelError> {
+        Self::exit_substates(user_state_machine, *current_state_fn, handling_state_fn)?;
 
-        // Special handling in case of targetting the current state
-        if handling_state_fn == target_state_fn {
-            Self::dispatch_exit_evt(user_state_machine, handling_state_fn);
-            Self::dispatch_entry_evt(user_state_machine, handling_state_fn);
+        let mut state_fn = handling_state_fn;
+        while !Self::same_state(state_fn, container_state_fn) {
+            match Self::exit_state(user_state_machine, state_fn, *current_state_fn)? {
+                denatured::ParentState::Exists(parent_state_fn) => state_fn = parent_state_fn,
+                denatured::ParentState::TopReached => break,
+            }
+        }
+
+        if Self::is_top_state(container_state_fn) {
+            *current_state_fn = Self::TOP_STATE_FN;
+            Ok(RegionOutcome::Processed(
+                denatured::DispatchOutcome::Terminated {
+                    terminated_by: Self::dispatch_get_state_ref(terminated_by),
+                },
+            ))
         } else {
-            let target_state_link = Link {
-                state_fn: target_state_fn,
-                next_link: None,
+            Ok(RegionOutcome::Terminated { terminated_by })
+        }
+    }
+
+    // Take a transition from the `handling_state_fn` to the `target` state, thus setting the
+    // state reached from `target` as the new current state of the region at the end of the
+    // process. The action of the transition is executed between the exit and the entry phases.
+    // The transition escapes the region of the `container_state_fn` state if its LCA is outside
+    // of it, in which case the states of the region have been exited and the transition has to be
+    // carried on by the enclosing region. A `local` transition does not exit the handling state
+    // when it is also the target.
+    //
+    // A target choice pseudostate is not entered: its guards are evaluated once the exit phase is
+    // over, and the transition is carried on from the LCA to the chosen target.
+    fn handle_transition(
+        user_state_machine: &mut denatured::OpaqueType,
+        current_state_fn: &mut denatured::StateFn,
+        container_state_fn: denatured::StateFn,
+        handling_state_fn: denatured::StateFn,
+        target: Target,
+        local: bool,
+    ) -> Result<TransitionOutcome, ModelError> {
+        let (mut handling_state_fn, mut target, mut local) = (handling_state_fn, target, local);
+
+        loop {
+            Self::exit_substates(user_state_machine, *current_state_fn, handling_state_fn)?;
+
+            // Special handling in case of targetting the current state
+            if !local && Self::same_state(handling_state_fn, target.state_fn) {
+                Self::exit_state(user_state_machine, handling_state_fn, *current_state_fn)?;
+                Self::dispatch_action(user_state_machine, target.action);
+                Self::dispatch_entry_evt(user_state_machine, handling_state_fn);
+                *current_state_fn = Self::reach_target_descendant(
+                    user_state_machine,
+                    target.state_fn,
+                    target.kind,
+                )?;
+                return Ok(TransitionOutcome::Completed {
+                    target: target.state_fn,
+                });
+            }
+
+            let lca_path = match Self::search_lca_state(
+                user_state_machine,
+                handling_state_fn,
+                *current_state_fn,
+                container_state_fn,
+                target.state_fn,
+            )? {
+                LCANature::State(lca_path) => {
+                    // The regions of an orthogonal LCA are all exited before being entered again
+                    Self::exit_regions(user_state_machine, lca_path.top_state_fn)?;
+                    lca_path
+                }
+                LCANature::TopState(lca_path) => lca_path,
+                LCANature::Container => return Ok(TransitionOutcome::Escaped { target }),
             };
 
-            Self::reach_target_state(user_state_machine, target_state_link, handling_state_fn);
+            Self::dispatch_action(user_state_machine, target.action);
+
+            match Self::dispatch_choose(user_state_machine, target.state_fn)? {
+                Some(chosen_target) => {
+                    *current_state_fn = lca_path.top_state_fn;
+                    handling_state_fn = lca_path.top_state_fn;
+                    target = chosen_target;
+                    local = true;
+                }
+                None => {
+                    *current_state_fn =
+                        Self::enter_substates(user_state_machine, lca_path, target.kind)?;
+                    return Ok(TransitionOutcome::Completed {
+                        target: target.state_fn,
+                    });
+                }
+            }
         }
-        *current_state_fn = Self::reach_init_target(user_state_machine, target_state_fn);
+    }
+
+    // Execute the `action` of a transition, if it has one
+    fn dispatch_action(
+        user_state_machine: &mut denatured::OpaqueType,
+        action: Option<denatured::ActionFn>,
+    ) {
+        if let Some(action) = action {
+            action(user_state_machine);
+        }
+    }
+
+    // Descend from the `target_state_fn`, either through its initial transition or to the
+    // substate recorded in its history, and return the reached state. A restored orthogonal
+    // state restores the active states of its regions.
+    fn reach_target_descendant(
+        user_state_machine: &mut denatured::OpaqueType,
+        target_state_fn: denatured::StateFn,
+        target_kind: TargetKind,
+    ) -> Result<denatured::StateFn, ModelError> {
+        match target_kind {
+            TargetKind::Restore if Self::has_regions(target_state_fn) => Self::enter_regions(
+                user_state_machine,
+                target_state_fn,
+                None,
+                TargetKind::Restore,
+            ),
+            TargetKind::Init | TargetKind::Restore => {
+                Self::reach_init_target(user_state_machine, target_state_fn)
+            }
+            TargetKind::History => Self::reach_history_target(user_state_machine, target_state_fn),
+        }
+    }
+
+    // Descend from the `target_state_fn` to the substate recorded in its history, or take its
+    // initial transition if no substate has been recorded.
+    fn reach_history_target(
+        user_state_machine: &mut denatured::OpaqueType,
+        target_state_fn: denatured::StateFn,
+    ) -> Result<denatured::StateFn, ModelError> {
+        match Self::dispatch_get_history(user_state_machine, target_state_fn) {
+            Some((history_state_fn, history_kind)) => {
+                let history_path = Self::get_history_path(target_state_fn, history_state_fn)?;
+                Self::enter_substates(user_state_machine, history_path, history_kind)
+            }
+            None => Self::reach_init_target(user_state_machine, target_state_fn),
+        }
+    }
+
+    // Return the path descending from the `state_fn` state to the `history_state_fn` substate
+    // recorded in its history.
+    fn get_history_path(
+        state_fn: denatured::StateFn,
+        history_state_fn: denatured::StateFn,
+    ) -> Result<Path, ModelError> {
+        let mut ancestor_state_fn = history_state_fn;
+        let mut length = 0;
+
+        while !Self::same_state(ancestor_state_fn, state_fn) {
+            ancestor_state_fn = Self::get_parent_state_fn(ancestor_state_fn);
+            if Self::is_top_state(ancestor_state_fn) {
+                return Err(ModelError::HistoryOutsideState);
+            }
+            length += 1;
+        }
+        Ok(Path {
+            top_state_fn: state_fn,
+            bottom_state_fn: history_state_fn,
+            length,
+        })
     }
 
     // Descend the state hierarchy by potentially executing the series of initial transitions and entry
-    // conditions until the leaf state is reached.
+    // conditions until either a leaf state or an orthogonal state is reached. The regions of the
+    // latter are then entered.
     fn reach_init_target(
         user_state_machine: &mut denatured::OpaqueType,
         target_state_fn: denatured::StateFn,
-    ) -> denatured::StateFn {
+    ) -> Result<denatured::StateFn, ModelError> {
         let mut current_target_state_fn = target_state_fn;
 
-        while let denatured::InitResult::TargetState(next_target_state) =
-            Self::dispatch_init_evt(user_state_machine, current_target_state_fn)
+        loop {
+            if Self::has_regions(current_target_state_fn) {
+                return Self::enter_regions(
+                    user_state_machine,
+                    current_target_state_fn,
+                    None,
+                    TargetKind::Init,
+                );
+            }
+
+            match Self::dispatch_init_evt(user_state_machine, current_target_state_fn) {
+                denatured::InitResult::TargetState(next_target_state) => {
+                    current_target_state_fn = next_target_state;
+                    Self::dispatch_entry_evt(user_state_machine, current_target_state_fn);
+                }
+                denatured::InitResult::NotImplemented => return Ok(current_target_state_fn),
+            }
+        }
+    }
+
+    // Enter each region of the orthogonal state `state_fn` by entering its region state, and
+    // return the orthogonal state as the current state of its own region. The region whose
+    // region state is the top state of the `region_state_path` is entered down to the bottom state
+    // of the path, while the other regions are entered through their initial transitions, or
+    // down to the state they were left in if `target_kind` is `Restore`.
+    fn enter_regions(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        region_state_path: Option<Path>,
+        target_kind: TargetKind,
+    ) -> Result<denatured::StateFn, ModelError> {
+        let mut target_found = region_state_path.is_none();
+        let mut region = 0;
+
+        while let denatured::InitResult::TargetState(region_state_fn) =
+            Self::dispatch_get_region_init(state_fn, region)
         {
-            current_target_state_fn = next_target_state;
-            Self::dispatch_entry_evt(user_state_machine, current_target_state_fn);
+            Self::dispatch_entry_evt(user_state_machine, region_state_fn);
+
+            let leaf_state_fn = match region_state_path {
+                Some(region_state_path)
+                    if Self::same_state(region_state_path.top_state_fn, region_state_fn) =>
+                {
+                    target_found = true;
+                    Self::enter_substates(user_state_machine, region_state_path, target_kind)?
+                }
+                _ => Self::reach_region_target(
+                    user_state_machine,
+                    state_fn,
+                    region,
+                    region_state_fn,
+                    target_kind,
+                )?,
+            };
+            Self::dispatch_set_region_leaf(
+                user_state_machine,
+                state_fn,
+                region,
+                Some(leaf_state_fn),
+            );
+            region += 1;
+        }
+
+        if !target_found {
+            return Err(ModelError::TargetOutsideRegions);
+        }
+        Ok(state_fn)
+    }
+
+    // Descend from the `region_state_fn` state of the region at index `region` of the orthogonal
+    // state `state_fn`, either through its initial transition or, if `target_kind` is `Restore`,
+    // to the state which was current when the region was last exited.
+    fn reach_region_target(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        region: usize,
+        region_state_fn: denatured::StateFn,
+        target_kind: TargetKind,
+    ) -> Result<denatured::StateFn, ModelError> {
+        match (
+            target_kind,
+            Self::dispatch_get_region_history(user_state_machine, state_fn, region),
+        ) {
+            (TargetKind::Restore, Some(last_state_fn)) => {
+                let history_path = Self::get_history_path(region_state_fn, last_state_fn)?;
+                Self::enter_substates(user_state_machine, history_path, TargetKind::Restore)
+            }
+            _ => Self::reach_init_target(user_state_machine, region_state_fn),
         }
+    }
+
+    // Exit the active states of every region of the orthogonal state `state_fn`, if it has
+    // any.
+    fn exit_regions(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+    ) -> Result<(), ModelError> {
+        let mut region = 0;
 
-        current_target_state_fn
+        while let denatured::InitResult::TargetState(_) =
+            Self::dispatch_get_region_init(state_fn, region)
+        {
+            if let Some(leaf_state_fn) =
+                Self::dispatch_get_region_leaf(user_state_machine, state_fn, region)
+            {
+                Self::exit_substates(user_state_machine, leaf_state_fn, state_fn)?;
+                Self::dispatch_record_region_exit(
+                    user_state_machine,
+                    state_fn,
+                    region,
+                    leaf_state_fn,
+                );
+            }
+            region += 1;
+        }
+        Ok(())
+    }
+
+    // Return `true` if the state `state_fn` is an orthogonal state.
+    fn has_regions(state_fn: denatured::StateFn) -> bool {
+        matches!(
+            Self::dispatch_get_region_init(state_fn, 0),
+            denatured::InitResult::TargetState(_)
+        )
+    }
+
+    // Return the region state of the region at index `region` of the state `state_fn`, or
+    // `NotImplemented` if there is no such region.
+    fn dispatch_get_region_init(
+        state_fn: denatured::StateFn,
+        region: usize,
+    ) -> denatured::InitResult {
+        (state_fn.region_init)(region)
+    }
+
+    // Return the current state of the region at index `region` of the state `state_fn`
+    fn dispatch_get_region_leaf(
+        user_state_machine: &denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        region: usize,
+    ) -> Option<denatured::StateFn> {
+        (state_fn.regions)(user_state_machine)
+            .and_then(|regions| regions.get(region))
+            .and_then(|record| record.active_state)
+            .map(|leaf_state| leaf_state.state_fn)
+    }
+
+    // Return the state which was current when the region at index `region` of the state
+    // `state_fn` was last exited
+    fn dispatch_get_region_history(
+        user_state_machine: &denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        region: usize,
+    ) -> Option<denatured::StateFn> {
+        (state_fn.regions)(user_state_machine)
+            .and_then(|regions| regions.get(region))
+            .and_then(|record| record.last_state)
+            .map(|last_state| last_state.state_fn)
+    }
+
+    // Record `leaf_state_fn` as the current state of the region at index `region` of the state
+    // `state_fn`
+    fn dispatch_set_region_leaf(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        region: usize,
+        leaf_state_fn: Option<denatured::StateFn>,
+    ) {
+        if let Some(record) =
+            (state_fn.regions_mut)(user_state_machine).and_then(|regions| regions.get_mut(region))
+        {
+            record.active_state = leaf_state_fn.map(Self::dispatch_get_state_ref);
+        }
+    }
+
+    // Mark the region at index `region` of the state `state_fn` as exited, recording the
+    // `leaf_state_fn` state which was current before it was left, so that a deep history
+    // restores it.
+    fn dispatch_record_region_exit(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        region: usize,
+        leaf_state_fn: denatured::StateFn,
+    ) {
+        if let Some(record) =
+            (state_fn.regions_mut)(user_state_machine).and_then(|regions| regions.get_mut(region))
+        {
+            record.active_state = None;
+            record.last_state = Some(Self::dispatch_get_state_ref(leaf_state_fn));
+        }
     }
 
     // Exit all ascendants of the `source_state_fn` until the `lca_state_fn` is reached
@@ -126,27 +888,84 @@ pub trait SMBusinessLogic {
         user_state_machine: &mut denatured::OpaqueType,
         source_state_fn: denatured::StateFn,
         lca_state_fn: denatured::StateFn,
-    ) {
+    ) -> Result<(), ModelError> {
         let mut next_state_fn = source_state_fn;
 
-        while next_state_fn != lca_state_fn {
-            if let denatured::ParentState::Exists(parent_state_fn) =
-                Self::dispatch_get_super_state(user_state_machine, next_state_fn)
-            {
-                Self::dispatch_exit_evt(user_state_machine, next_state_fn);
-                next_state_fn = parent_state_fn;
-            } else {
-                panic!("Target state not found when ascending state hierarchy")
+        while !Self::same_state(next_state_fn, lca_state_fn) {
+            match Self::exit_state(user_state_machine, next_state_fn, source_state_fn)? {
+                denatured::ParentState::Exists(parent_state_fn) => {
+                    next_state_fn = parent_state_fn;
+                }
+                denatured::ParentState::TopReached if Self::is_top_state(lca_state_fn) => {
+                    next_state_fn = lca_state_fn;
+                }
+                denatured::ParentState::TopReached => return Err(ModelError::SourceOutsideLineage),
             }
         }
+        Ok(())
+    }
+
+    // Exit the regions of the state `state_fn` if it has any, then the state itself, and record
+    // it, along with the `leaf_state_fn` state which was current before the transition started,
+    // in the history of its parent state, which is returned.
+    fn exit_state(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        leaf_state_fn: denatured::StateFn,
+    ) -> Result<denatured::ParentState, ModelError> {
+        Self::exit_regions(user_state_machine, state_fn)?;
+        Self::dispatch_exit_evt(user_state_machine, state_fn);
+
+        let parent_state = Self::dispatch_get_super_state(state_fn);
+        if let denatured::ParentState::Exists(parent_state_fn) = parent_state {
+            Self::dispatch_record_history_evt(
+                user_state_machine,
+                parent_state_fn,
+                state_fn,
+                leaf_state_fn,
+            );
+        }
+        Ok(parent_state)
+    }
+
+    // Record the `child_state_fn` and `leaf_state_fn` states in the history of the state
+    // `state_fn`
+    fn dispatch_record_history_evt(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+        child_state_fn: denatured::StateFn,
+        leaf_state_fn: denatured::StateFn,
+    ) {
+        if let Some(history) = (state_fn.shallow_history)(user_state_machine) {
+            history.state_fn = Some(child_state_fn);
+        } else if let Some(history) = (state_fn.deep_history)(user_state_machine) {
+            history.state_fn = Some(leaf_state_fn);
+        }
     }
+
+    // Return the substate recorded in the history of the state `state_fn`, along with the way to
+    // descend from it: through its initial transition for a shallow history, or down to the
+    // states it was left in for a deep history.
+    fn dispatch_get_history(
+        user_state_machine: &mut denatured::OpaqueType,
+        state_fn: denatured::StateFn,
+    ) -> Option<(denatured::StateFn, TargetKind)> {
+        match (state_fn.shallow_history)(user_state_machine) {
+            Some(history) => history
+                .state_fn
+                .map(|state_fn| (state_fn, TargetKind::Init)),
+            None => (state_fn.deep_history)(user_state_machine)
+                .and_then(|history| history.state_fn)
+                .map(|state_fn| (state_fn, TargetKind::Restore)),
+        }
+    }
+
     // Trigger the exit condition of the state `state_fn`
     fn dispatch_exit_evt(
         user_state_machine: &mut denatured::OpaqueType,
         state_fn: denatured::StateFn,
     ) {
-        let exit_evt = denatured::CoreEvt::ExitEvt;
-        state_fn(user_state_machine, &exit_evt);
+        (state_fn.exit)(user_state_machine);
     }
 
     // Trigger the entry condition of the state `state_fn`
@@ -154,98 +973,206 @@ pub trait SMBusinessLogic {
         user_state_machine: &mut denatured::OpaqueType,
         state_fn: denatured::StateFn,
     ) {
-        let entry_evt = denatured::CoreEvt::EntryEvt;
-        state_fn(user_state_machine, &entry_evt);
+        (state_fn.entry)(user_state_machine);
     }
 
-    // Recursive function whose role is to create a stack-allocated linked list of all the ancestors
-    // of the target state up to the top state. This linked list is then used by the function
-    // `search_lca_state()` for finding the lca and transitioning to the target state.
-    fn reach_target_state(
+    // Evaluate the guards of the `state_fn` choice pseudostate and return the chosen target, or
+    // `None` if `state_fn` is a regular state.
+    fn dispatch_choose(
         user_state_machine: &mut denatured::OpaqueType,
-        target_state_link: Link,
-        source_state_fn: denatured::StateFn,
-    ) {
-        if let denatured::ParentState::Exists(parent_state_fn) =
-            Self::dispatch_get_super_state(user_state_machine, target_state_link.state_fn)
-        {
-            let parent_state_link = Link {
-                state_fn: parent_state_fn,
-                next_link: Some(&target_state_link),
-            };
-            Self::reach_target_state(user_state_machine, parent_state_link, source_state_fn)
-        } else {
-            match Self::search_lca_state(user_state_machine, &target_state_link, source_state_fn) {
-                LCANature::State(state_link) => {
-                    Self::enter_substates(user_state_machine, state_link)
-                }
-                LCANature::TopState => {
-                    let top_state_link = &Link {
-                        state_fn: Self::top_state_fn,
-                        next_link: Some(&target_state_link),
-                    };
-                    Self::enter_substates(user_state_machine, top_state_link)
-                }
-            }
+        state_fn: denatured::StateFn,
+    ) -> Result<Option<Target>, ModelError> {
+        match (state_fn.choose)(user_state_machine) {
+            None => Ok(None),
+            // A choice which did not select any transition is an error
+            Some(handle_result) => Self::get_target(handle_result)
+                .map(Some)
+                .ok_or(ModelError::ChoiceWithoutTransition),
         }
     }
 
-    // Return the parent state of the `state_fn` state sent as argument
+    // Return the parent state of the `state_fn` state sent as argument, read from its table of
+    // functions unless the state has been implemented without the procedural macros
     #[inline(always)]
-    fn dispatch_get_super_state(
-        user_state_machine: &mut denatured::OpaqueType,
-        state_fn: denatured::StateFn,
-    ) -> denatured::ParentState {
-        let get_parent_state_evt = denatured::CoreEvt::GetParentStateEvt;
+    fn dispatch_get_super_state(state_fn: denatured::StateFn) -> denatured::ParentState {
+        match state_fn.parent {
+            Some(parent_state_fn) if Self::is_top_state(parent_state_fn) => {
+                denatured::ParentState::TopReached
+            }
+            Some(parent_state_fn) => denatured::ParentState::Exists(parent_state_fn),
+            None => (state_fn.get_parent_state)(),
+        }
+    }
 
-        let core_handle_result = state_fn(user_state_machine, &get_parent_state_evt);
+    // Return the name of the `state_fn` state sent as argument
+    fn dispatch_get_name(state_fn: denatured::StateFn) -> &'static str {
+        state_fn.name
+    }
 
-        if let denatured::CoreHandleResult::GetParentStateResult(parent_state_fn) =
-            core_handle_result
-        {
-            parent_state_fn
-        } else {
-            panic!("Variant returned by state fn is not ParentState")
+    // Return the identifier of the `state_fn` state sent as argument
+    fn dispatch_get_uid(state_fn: denatured::StateFn) -> StateUid {
+        (state_fn.uid)()
+    }
+
+    // Return the reference to the `state_fn` state sent as argument
+    fn dispatch_get_state_ref(state_fn: denatured::StateFn) -> denatured::StateRef {
+        denatured::StateRef {
+            state_fn,
+            uid: Self::dispatch_get_uid(state_fn),
         }
     }
-    // Descending phase of a transition. Entry condition in every LCA descendant are successively
-    // executed until the target state is reach.
-    fn enter_substates(user_state_machine: &mut denatured::OpaqueType, lca_state_link: &Link) {
-        let mut state_link = lca_state_link;
-        while let Some(child_state_link) = state_link.next_link {
-            Self::dispatch_entry_evt(user_state_machine, child_state_link.state_fn);
-            state_link = child_state_link;
+
+    // Return the parent of the `state_fn` state, the top state being the parent of the states
+    // whose super state is `Top` as well as its own parent.
+    fn get_parent_state_fn(state_fn: denatured::StateFn) -> denatured::StateFn {
+        if let Some(parent_state_fn) = state_fn.parent {
+            return parent_state_fn;
         }
+        match (state_fn.get_parent_state)() {
+            denatured::ParentState::Exists(parent_state_fn) => parent_state_fn,
+            denatured::ParentState::TopReached => Self::TOP_STATE_FN,
+        }
+    }
+
+    // Return the ancestor of the `state_fn` state located `generations` levels above it.
+    fn get_ancestor(state_fn: denatured::StateFn, generations: usize) -> denatured::StateFn {
+        let mut ancestor_state_fn = state_fn;
+        for _ in 0..generations {
+            ancestor_state_fn = Self::get_parent_state_fn(ancestor_state_fn);
+        }
+        ancestor_state_fn
+    }
+
+    // Return the depth of the `state_fn` state, that is its number of ancestors, the top state
+    // included. The depth of the states declared through the `#[state()]` procedural macro is
+    // a constant of their lineage.
+    fn get_depth(state_fn: denatured::StateFn) -> usize {
+        let mut ancestor_state_fn = state_fn;
+        let mut generations = 0;
+
+        loop {
+            match ancestor_state_fn.lineage {
+                Lineage::State {
+                    depth: Some(depth), ..
+                } => return generations + depth,
+                Lineage::Top => return generations,
+                _ => {
+                    ancestor_state_fn = Self::get_parent_state_fn(ancestor_state_fn);
+                    generations += 1;
+                }
+            }
+        }
+    }
+
+    // Write into `chunk` the states of `path` located right below its top state, from the child
+    // of the top state downwards, and return their number. They are collected through a single
+    // ascent from the bottom state of the path.
+    fn get_path_chunk(path: Path, chunk: &mut [denatured::StateFn; PATH_CHUNK_LENGTH]) -> usize {
+        let chunk_length = path.length.min(PATH_CHUNK_LENGTH);
+        let mut state_fn = Self::get_ancestor(path.bottom_state_fn, path.length - chunk_length);
+        for chunk_state_fn in chunk[..chunk_length].iter_mut().rev() {
+            *chunk_state_fn = state_fn;
+            state_fn = Self::get_parent_state_fn(state_fn);
+        }
+        chunk_length
+    }
+
+    // Descending phase of a transition. Entry condition in every state of the `lca_path` below the
+    // LCA are successively executed until the target state is reached, from which the state
+    // machine then descends. Return the new current state of the region of the LCA.
+    //
+    // The states to enter are collected by chunks of `PATH_CHUNK_LENGTH` states, each one through
+    // a single ascent from the bottom of the path, so that entering a path no longer than a
+    // chunk ascends it only once.
+    fn enter_substates(
+        user_state_machine: &mut denatured::OpaqueType,
+        lca_path: Path,
+        target_kind: TargetKind,
+    ) -> Result<denatured::StateFn, ModelError> {
+        let mut path = lca_path;
+        let mut chunk = [Self::TOP_STATE_FN; PATH_CHUNK_LENGTH];
+
+        while path.length > 0 {
+            let chunk_length = Self::get_path_chunk(path, &mut chunk);
+            for &child_state_fn in &chunk[..chunk_length] {
+                let child_path = Path {
+                    top_state_fn: child_state_fn,
+                    bottom_state_fn: path.bottom_state_fn,
+                    length: path.length - 1,
+                };
+                if Self::has_regions(path.top_state_fn) {
+                    return Self::enter_regions(
+                        user_state_machine,
+                        path.top_state_fn,
+                        Some(child_path),
+                        target_kind,
+                    );
+                }
+                Self::dispatch_entry_evt(user_state_machine, child_state_fn);
+                path = child_path;
+            }
+        }
+        Self::reach_target_descendant(user_state_machine, path.top_state_fn, target_kind)
     }
 
     // Search for the LCA (Least Common Ancestor) state between the target and the source state. Also
     // proceed to eventually exiting every state in the handling state lineage before the LCA is found.
-    fn search_lca_state<'a>(
+    // The search stops when reaching the `container_state_fn` state, whose region is then left.
+    //
+    // Rather than listing the ancestors of the target, the lineage of the target is ascended
+    // alongside the lineage of the source, so that both compared states are at the same depth.
+    fn search_lca_state(
         user_state_machine: &mut denatured::OpaqueType,
-        last_state_link: &'a Link<'a>,
         source_state_fn: denatured::StateFn,
-    ) -> LCANature<'a> {
+        leaf_state_fn: denatured::StateFn,
+        container_state_fn: denatured::StateFn,
+        target_state_fn: denatured::StateFn,
+    ) -> Result<LCANature, ModelError> {
+        let target_depth = Self::get_depth(target_state_fn);
+        let top_state_path = Path {
+            top_state_fn: Self::TOP_STATE_FN,
+            bottom_state_fn: target_state_fn,
+            length: target_depth,
+        };
+
+        // Every active state has already been exited when the source is the top state
+        if Self::is_top_state(source_state_fn) {
+            return Ok(LCANature::TopState(top_state_path));
+        }
+
         let mut source_state_fn = source_state_fn;
-        loop {
-            let mut state_link = last_state_link;
+        let mut source_depth = Self::get_depth(source_state_fn);
+        let mut target_ancestor_state_fn = target_state_fn;
+        let mut target_ancestor_depth = target_depth;
 
-            while {
-                if state_link.state_fn == source_state_fn {
-                    return LCANature::State(state_link);
-                }
-                state_link.next_link.is_some()
-            } {
-                state_link = state_link.next_link.unwrap();
+        loop {
+            if Self::same_state(source_state_fn, container_state_fn) {
+                return Ok(LCANature::Container);
             }
 
-            Self::dispatch_exit_evt(user_state_machine, source_state_fn);
+            while target_ancestor_depth > source_depth {
+                target_ancestor_state_fn = Self::get_parent_state_fn(target_ancestor_state_fn);
+                target_ancestor_depth -= 1;
+            }
 
-            if let denatured::ParentState::Exists(parent_state_fn) =
-                Self::dispatch_get_super_state(user_state_machine, source_state_fn)
+            if target_ancestor_depth == source_depth
+                && Self::same_state(target_ancestor_state_fn, source_state_fn)
             {
-                source_state_fn = parent_state_fn;
-            } else {
-                return LCANature::TopState;
+                return Ok(LCANature::State(Path {
+                    top_state_fn: source_state_fn,
+                    bottom_state_fn: target_state_fn,
+                    length: target_depth - source_depth,
+                }));
+            }
+
+            match Self::exit_state(user_state_machine, source_state_fn, leaf_state_fn)? {
+                denatured::ParentState::Exists(parent_state_fn) => {
+                    source_state_fn = parent_state_fn;
+                    source_depth -= 1;
+                }
+                denatured::ParentState::TopReached => {
+                    return Ok(LCANature::TopState(top_state_path))
+                }
             }
         }
     }
@@ -255,12 +1182,7 @@ pub trait SMBusinessLogic {
         user_state_machine: &mut denatured::OpaqueType,
         state_fn: denatured::StateFn,
     ) -> denatured::InitResult {
-        let init_evt = denatured::CoreEvt::InitEvt;
-        let init_result = state_fn(user_state_machine, &init_evt);
-        match init_result {
-            denatured::CoreHandleResult::InitResult(init_result) => return init_result,
-            _ => panic!("Variant returned by state fn is not InitResult"),
-        }
+        (state_fn.init)(user_state_machine)
     }
 
     // Reach the first state of the state machine by descending from init conditions into init
@@ -268,13 +1190,15 @@ pub trait SMBusinessLogic {
     fn init(
         user_state_machine: &mut denatured::OpaqueType,
         init_result: &denatured::InitResult,
-    ) -> denatured::StateFn {
+    ) -> Result<denatured::StateRef, InitError> {
         match init_result {
             denatured::InitResult::TargetState(topmost_init_target_state_fn) => {
-                Self::dispatch_entry_evt(user_state_machine, *topmost_init_target_state_fn);
-                Self::reach_init_target(user_state_machine, *topmost_init_target_state_fn)
+                Self::dispatch_entry_evt(user_state_machine, topmost_init_target_state_fn);
+                let current_state_fn =
+                    Self::reach_init_target(user_state_machine, topmost_init_target_state_fn)?;
+                Ok(Self::dispatch_get_state_ref(current_state_fn))
             }
-            denatured::InitResult::NotImplemented => panic!("Topmost Init should return a state"),
+            denatured::InitResult::NotImplemented => Err(InitError::MissingTopInitTarget),
         }
     }
 }
@@ -1,30 +1,60 @@
+use core::any::TypeId;
+use core::marker::PhantomData;
+
+use crate::history::History;
+use crate::lineage::Lineage;
 use crate::proto_state_machine::TopState;
+use crate::regions::RegionRecord;
 
+//For doc
 #[allow(unused_imports)]
 use crate::state_machine::StateMachine;
 
 // These subsitute types are used to prevent exploding program size
 // induced by the user types which propagate in the original types.
 pub(crate) mod denatured {
+    use super::StateUid;
+    use crate::lineage::Lineage;
 
     pub struct OpaqueType {}
-    pub type StateFn = fn(*mut OpaqueType, &CoreEvt) -> CoreHandleResult;
-    pub type UserEvt = *const OpaqueType;
+    pub type StateFn = &'static StateVTable;
+    pub type UserEvt = OpaqueType;
+    pub type ActionFn = fn(&mut OpaqueType);
 
-    #[allow(unused)]
-    pub enum HandleResult {
-        Ignored,
-        Handled,
-        Transition(StateFn),
+    // Constant table of the functions of a state, through which the state machine calls exactly
+    // the function it needs. Each field matches one of the methods of the `State` trait.
+    #[derive(Clone, Copy)]
+    pub struct StateVTable {
+        pub init: fn(&mut OpaqueType) -> InitResult,
+        pub entry: ActionFn,
+        pub exit: ActionFn,
+        pub handle: fn(&mut OpaqueType, &UserEvt) -> HandleResult,
+        pub get_parent_state: fn() -> ParentState,
+        // Table of the parent state, recorded at compile time by the procedural macros so that
+        // the state machine ascends the hierarchy without calling `get_parent_state()`. It is
+        // `None` for the top state and for the states implemented without the macros.
+        pub parent: Option<StateFn>,
+        pub name: &'static str,
+        pub uid: fn() -> StateUid,
+        pub lineage: &'static Lineage,
+        pub shallow_history: fn(&mut OpaqueType) -> Option<&mut History>,
+        pub deep_history: fn(&mut OpaqueType) -> Option<&mut History>,
+        pub region_init: fn(usize) -> InitResult,
+        pub regions: fn(&OpaqueType) -> Option<&[RegionRecord]>,
+        pub regions_mut: fn(&mut OpaqueType) -> Option<&mut [RegionRecord]>,
+        pub choose: fn(&mut OpaqueType) -> Option<HandleResult>,
+        pub on_completion: fn(&mut OpaqueType) -> HandleResult,
+        pub is_final: bool,
     }
 
     #[allow(unused)]
-    pub enum CoreHandleResult {
-        Ignored(ParentState),
+    pub enum HandleResult {
+        Ignored,
         Handled,
-        Transition(StateFn),
-        GetParentStateResult(ParentState),
-        InitResult(InitResult),
+        Transition(StateFn, Option<ActionFn>),
+        HistoryTransition(StateFn, Option<ActionFn>),
+        Deferred,
+        Terminate,
     }
 
     #[allow(unused)]
@@ -39,36 +69,97 @@ pub(crate) mod denatured {
         TargetState(StateFn),
     }
 
+    #[repr(transparent)]
+    pub struct History {
+        pub state_fn: Option<StateFn>,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    pub struct StateRef {
+        pub state_fn: StateFn,
+        pub uid: StateUid,
+    }
+
+    #[repr(C)]
+    pub struct RegionRecord {
+        pub active_state: Option<StateRef>,
+        pub last_state: Option<StateRef>,
+    }
+
     #[allow(unused)]
-    pub enum CoreEvt<'a> {
-        InitEvt,
-        EntryEvt,
-        ExitEvt,
-        GetParentStateEvt,
-        UserEvt { user_evt: &'a UserEvt },
+    pub enum DispatchOutcome {
+        Handled { handled_by: StateRef },
+        Ignored,
+        Transition { source: StateRef, target: StateRef },
+        Deferred { deferred_by: StateRef },
+        Terminated { terminated_by: StateRef },
     }
 }
 
-pub type StateFn<UserStateMachineT> = fn(
-    &mut UserStateMachineT,
-    &CoreEvt<<UserStateMachineT as TopState>::Evt>,
-) -> CoreHandleResult<UserStateMachineT>;
+/// Handle on a state of the `UserStateMachine` state machine, referring to the constant table of
+/// its functions.
+#[doc(hidden)]
+#[repr(transparent)]
+pub struct StateFn<UserStateMachine: ?Sized> {
+    pub(crate) vtable: denatured::StateFn,
+    _user_state_machine: PhantomData<fn(&mut UserStateMachine)>,
+}
+
+impl<UserStateMachine: ?Sized> StateFn<UserStateMachine> {
+    pub(crate) const fn new(vtable: denatured::StateFn) -> StateFn<UserStateMachine> {
+        StateFn {
+            vtable,
+            _user_state_machine: PhantomData,
+        }
+    }
+}
+
+impl<UserStateMachine: ?Sized> StateFn<UserStateMachine> {
+    /// Return a handle on the state tagged `Tag`, which can be stored in a constant.
+    pub const fn of<Tag: 'static>() -> StateFn<UserStateMachine>
+    where
+        UserStateMachine: State<Tag> + Sized,
+    {
+        StateFn::new(&<UserStateMachine as HasStateVTable<Tag>>::STATE_VTABLE)
+    }
+
+    /// Return a handle on the top state, which can be stored in a constant.
+    pub const fn top() -> StateFn<UserStateMachine>
+    where
+        UserStateMachine: TopState + Sized,
+    {
+        StateFn::new(&<UserStateMachine as HasTopStateVTable>::TOP_STATE_VTABLE)
+    }
+}
+
+impl<UserStateMachine: ?Sized> Clone for StateFn<UserStateMachine> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<UserStateMachine: ?Sized> Copy for StateFn<UserStateMachine> {}
 
 /// Returned by the user-defined [`State::handle()`] method to order the state machine to either
 /// ignore the event and dispatch it to the parent state (`Ignored`), do nothing special (`Handled`),
-/// or trigger a transition to another state (`Transition`).
+/// trigger a transition to another state (`Transition`) or to the history of another state
+/// (`HistoryTransition`), optionally executing an action between the exit and the entry phases of
+/// the transition, keep the event until the next transition (`Deferred`) or
+/// terminate the state machine (`Terminate`).
 pub enum HandleResult<UserStateMachineT: TopState + ?Sized> {
     Ignored,
     Handled,
-    Transition(StateFn<UserStateMachineT>),
-}
-
-pub enum CoreHandleResult<UserStateMachineT: TopState + ?Sized> {
-    Ignored(ParentState<UserStateMachineT>),
-    Handled,
-    Transition(StateFn<UserStateMachineT>),
-    GetParentStateResult(ParentState<UserStateMachineT>),
-    InitResult(InitResult<UserStateMachineT>),
+    Transition(
+        StateFn<UserStateMachineT>,
+        Option<fn(&mut UserStateMachineT)>,
+    ),
+    HistoryTransition(
+        StateFn<UserStateMachineT>,
+        Option<fn(&mut UserStateMachineT)>,
+    ),
+    Deferred,
+    Terminate,
 }
 
 /// Returned by the user-defined [`State::get_parent_state()`] method to either indicate
@@ -87,14 +178,6 @@ pub enum InitResult<UserStateMachine: TopState + ?Sized> {
     TargetState(StateFn<UserStateMachine>),
 }
 
-pub enum CoreEvt<'a, UserEvtT> {
-    InitEvt,
-    EntryEvt,
-    ExitEvt,
-    GetParentStateEvt,
-    UserEvt { user_evt: &'a UserEvtT },
-}
-
 /// Generic trait which must be implemented on the state machine structure for defining each of its states.
 ///
 /// The `tag` argument, which corresponds to the state name, has no other purpose than to create a
@@ -179,6 +262,152 @@ pub enum CoreEvt<'a, UserEvtT> {
 /// ```
 /// *Note: It is recommended to use the `#[state()]` procedural macro before the state implementation
 /// in order to limit code verbosity.*
+/// # Hierarchy validation
+/// The hierarchy of the states implemented with the `#[state()]` procedural macro is validated at
+/// compile time. Compilation fails if a state is its own ancestor, if a state having substates
+/// implements neither [`State::init()`] nor regions, or if [`State::init()`] targets a state which
+/// is not one of its substates.
+/// ```compile_fail
+///# use kaori_hsm::*;
+///# enum BasicEvt{}
+///# struct BasicStateMachine{}
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+/// #[state(super_state= S1)]
+/// impl State<S0> for BasicStateMachine{
+///     fn init(&mut self) -> InitResult<Self> {
+///         init_transition!(S1)
+///     }
+///
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// // Error: `S0` is both the parent and a substate of `S1`
+/// #[state(super_state= S0)]
+/// impl State<S1> for BasicStateMachine{
+///     fn init(&mut self) -> InitResult<Self> {
+///         init_transition!(S0)
+///     }
+///
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+/// ```
+/// ```compile_fail
+///# use kaori_hsm::*;
+///# enum BasicEvt{}
+///# struct BasicStateMachine{}
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+/// // Error: `S0` has the substate `S1` but no initial transition
+/// #[state(super_state= Top)]
+/// impl State<S0> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// #[state(super_state= S0)]
+/// impl State<S1> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+/// ```
+/// ```compile_fail
+///# use kaori_hsm::*;
+///# enum BasicEvt{}
+///# struct BasicStateMachine{}
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+/// #[state(super_state= Top)]
+/// impl State<S0> for BasicStateMachine{
+///     fn init(&mut self) -> InitResult<Self> {
+///         // Error: `S2` is not a substate of `S0`
+///         init_transition!(S2)
+///     }
+///
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///#
+///# #[state(super_state= S0)]
+///# impl State<S1> for BasicStateMachine{
+///#     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///#         ignored!()
+///#     }
+///# }
+///#
+///# #[state(super_state= Top)]
+///# impl State<S2> for BasicStateMachine{
+///#     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///#         ignored!()
+///#     }
+///# }
+/// ```
+/// The states are told apart by the path of their tag, so that states of different modules may
+/// share the same name.
+/// ```compile_fail
+///# use kaori_hsm::*;
+///# enum BasicEvt{}
+///# struct BasicStateMachine{}
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+/// #[state(super_state= Top)]
+/// impl State<S0> for BasicStateMachine{
+///     fn init(&mut self) -> InitResult<Self> {
+///         // Error: `S1` is a substate of `other::S0`, not of `S0`
+///         init_transition!(S1)
+///     }
+///
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// mod other {
+///     use kaori_hsm::*;
+///     use super::{BasicEvt, BasicStateMachine};
+///
+///     #[state(super_state= Top, vis= pub)]
+///     impl State<S0> for BasicStateMachine{
+///         fn init(&mut self) -> InitResult<Self> {
+///             init_transition!(super::S1)
+///         }
+///
+///         fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///             ignored!()
+///         }
+///     }
+/// }
+///
+/// #[state(super_state= other::S0)]
+/// impl State<S1> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+/// ```
 pub trait State<Tag>
 where
     Self: TopState,
@@ -192,6 +421,15 @@ where
     /// *Note: This method is automatically implemented if you use the `#[state()]` procedural macro*
     fn get_parent_state() -> ParentState<Self>;
 
+    /// Name of the state, as returned by [`StateMachine::current_state_name()`].
+    ///
+    ///# Implementation policy
+    /// The default value is `"Unnamed"`.
+    ///
+    /// *Note: This constant is automatically defined with the name of the state tag if you use
+    /// the `#[state()]` procedural macro*
+    const NAME: &'static str = "Unnamed";
+
     /// Define the operations to perform when the initial transition of a state is triggered.
     /// Is called when a transition targets the present state, after its entry statement has been executed.
     ///
@@ -229,38 +467,432 @@ where
     /// This method implementation is typically a `match` statement on the event variant.
     /// The handling of each event may return either:
     /// - [`HandleResult::Transition`]: Immediately trigger a transition to the target state, which may
-    /// become the next current state of the state machine.
+    ///   become the next current state of the state machine.
     /// - [`HandleResult::Handled`]: The event is handled without transition.
     /// - [`HandleResult::Ignored`]: the event is dispatched to the parent state.  
+    /// - [`HandleResult::Deferred`]: the event is kept in the defer queue of the state machine
+    ///   and dispatched again after the next transition.
+    /// - [`HandleResult::Terminate`]: every active state is exited and the state machine
+    ///   terminates, see [`StateMachine::is_terminated()`].
+    ///
     /// *Note: It is recommended to use the provided `transition!()`, `handled!()` and `ignored!()` macros instead
     /// of assembling manually the enum variants of `HandleResult`*
+    ///
+    /// *Note: If the `#[state()]` procedural macro is used, the fallible
+    /// [`FallibleState::try_handle()`](crate::FallibleState::try_handle) method may be
+    /// implemented instead of this one.*
     fn handle(&mut self, evt: &<Self as TopState>::Evt) -> HandleResult<Self>;
 
+    /// Called once the state is completed, that is once one of its final substates has been
+    /// reached, or once each of its regions has reached one of its final states if it is an
+    /// orthogonal state. See [`FinalState`](crate::FinalState).
+    ///
+    ///# Implementation policy
+    /// The implementation of this method is optional. It may return the same results as
+    /// [`State::handle()`], typically `transition!()` for the completion transition of the state.
+    /// The default implementation returns [`HandleResult::Ignored`], in which case the state
+    /// machine stays in the final state.
+    fn on_completion(&mut self) -> HandleResult<Self> {
+        HandleResult::Ignored
+    }
+
     #[doc(hidden)]
-    fn core_handle(
-        &mut self,
-        evt: &CoreEvt<<Self as TopState>::Evt>,
-    ) -> CoreHandleResult<Self> {
-        match evt {
-            CoreEvt::InitEvt => {
-                return CoreHandleResult::InitResult(<Self as State<Tag>>::init(self));
-            }
-            CoreEvt::EntryEvt => {
-                <Self as State<Tag>>::entry(self);
-                return CoreHandleResult::Handled;
+    const LINEAGE: Lineage = Lineage::Unknown;
+
+    #[doc(hidden)]
+    const PARENT_STATE_FN: Option<StateFn<Self>> = None;
+
+    #[doc(hidden)]
+    const HAS_INIT: bool = true;
+
+    #[doc(hidden)]
+    const IS_FINAL: bool = false;
+
+    // Compile-time assertions on the position of the state in the hierarchy, evaluated along
+    // with the table of functions of the state
+    #[doc(hidden)]
+    const HIERARCHY_CHECK: () = ();
+
+    #[doc(hidden)]
+    fn shallow_history(&mut self) -> Option<&mut History<Self>> {
+        None
+    }
+
+    #[doc(hidden)]
+    fn deep_history(&mut self) -> Option<&mut History<Self>> {
+        None
+    }
+
+    #[doc(hidden)]
+    fn region_init(_region: usize) -> InitResult<Self> {
+        InitResult::NotImplemented
+    }
+
+    #[doc(hidden)]
+    fn regions(&self) -> Option<&[RegionRecord<Self>]> {
+        None
+    }
+
+    #[doc(hidden)]
+    fn regions_mut(&mut self) -> Option<&mut [RegionRecord<Self>]> {
+        None
+    }
+
+    #[doc(hidden)]
+    fn choose(&mut self) -> Option<HandleResult<Self>> {
+        None
+    }
+
+    #[doc(hidden)]
+    fn state_fn() -> StateFn<Self>
+    where
+        Self: Sized,
+        Tag: 'static,
+    {
+        StateFn::of::<Tag>()
+    }
+}
+
+// Constant table of the functions of the state tagged `Tag`, built from its implementation of
+// the `State` trait.
+trait HasStateVTable<Tag> {
+    const STATE_VTABLE: denatured::StateVTable;
+}
+
+impl<UserStateMachine: State<Tag>, Tag: 'static> HasStateVTable<Tag> for UserStateMachine {
+    const STATE_VTABLE: denatured::StateVTable = unsafe {
+        let () = <UserStateMachine as State<Tag>>::HIERARCHY_CHECK;
+        denatured::StateVTable {
+            init: core::mem::transmute::<
+                fn(&mut UserStateMachine) -> InitResult<UserStateMachine>,
+                fn(&mut denatured::OpaqueType) -> denatured::InitResult,
+            >(<UserStateMachine as State<Tag>>::init),
+            entry: core::mem::transmute::<fn(&mut UserStateMachine), denatured::ActionFn>(
+                <UserStateMachine as State<Tag>>::entry,
+            ),
+            exit: core::mem::transmute::<fn(&mut UserStateMachine), denatured::ActionFn>(
+                <UserStateMachine as State<Tag>>::exit,
+            ),
+            handle: core::mem::transmute::<
+                fn(
+                    &mut UserStateMachine,
+                    &<UserStateMachine as TopState>::Evt,
+                ) -> HandleResult<UserStateMachine>,
+                fn(&mut denatured::OpaqueType, &denatured::UserEvt) -> denatured::HandleResult,
+            >(<UserStateMachine as State<Tag>>::handle),
+            get_parent_state: core::mem::transmute::<
+                fn() -> ParentState<UserStateMachine>,
+                fn() -> denatured::ParentState,
+            >(<UserStateMachine as State<Tag>>::get_parent_state),
+            parent: match <UserStateMachine as State<Tag>>::PARENT_STATE_FN {
+                Some(parent_state_fn) => Some(parent_state_fn.vtable),
+                None => None,
+            },
+            name: <UserStateMachine as State<Tag>>::NAME,
+            uid: StateUid::of::<Tag>,
+            lineage: &<UserStateMachine as State<Tag>>::LINEAGE,
+            shallow_history: core::mem::transmute::<
+                fn(&mut UserStateMachine) -> Option<&mut History<UserStateMachine>>,
+                fn(&mut denatured::OpaqueType) -> Option<&mut denatured::History>,
+            >(<UserStateMachine as State<Tag>>::shallow_history),
+            deep_history: core::mem::transmute::<
+                fn(&mut UserStateMachine) -> Option<&mut History<UserStateMachine>>,
+                fn(&mut denatured::OpaqueType) -> Option<&mut denatured::History>,
+            >(<UserStateMachine as State<Tag>>::deep_history),
+            region_init: core::mem::transmute::<
+                fn(usize) -> InitResult<UserStateMachine>,
+                fn(usize) -> denatured::InitResult,
+            >(<UserStateMachine as State<Tag>>::region_init),
+            regions: core::mem::transmute::<
+                fn(&UserStateMachine) -> Option<&[RegionRecord<UserStateMachine>]>,
+                fn(&denatured::OpaqueType) -> Option<&[denatured::RegionRecord]>,
+            >(<UserStateMachine as State<Tag>>::regions),
+            regions_mut: core::mem::transmute::<
+                fn(&mut UserStateMachine) -> Option<&mut [RegionRecord<UserStateMachine>]>,
+                fn(&mut denatured::OpaqueType) -> Option<&mut [denatured::RegionRecord]>,
+            >(<UserStateMachine as State<Tag>>::regions_mut),
+            choose: core::mem::transmute::<
+                fn(&mut UserStateMachine) -> Option<HandleResult<UserStateMachine>>,
+                fn(&mut denatured::OpaqueType) -> Option<denatured::HandleResult>,
+            >(<UserStateMachine as State<Tag>>::choose),
+            on_completion: core::mem::transmute::<
+                fn(&mut UserStateMachine) -> HandleResult<UserStateMachine>,
+                fn(&mut denatured::OpaqueType) -> denatured::HandleResult,
+            >(<UserStateMachine as State<Tag>>::on_completion),
+            is_final: <UserStateMachine as State<Tag>>::IS_FINAL,
+        }
+    };
+}
+
+// Constant table of the functions of the top state, whose `handle()` function is
+// `TopState::on_unhandled()`.
+pub(crate) trait HasTopStateVTable {
+    const TOP_STATE_VTABLE: denatured::StateVTable;
+}
+
+impl<UserStateMachine: TopState> HasTopStateVTable for UserStateMachine {
+    const TOP_STATE_VTABLE: denatured::StateVTable = denatured::StateVTable {
+        init: |_| denatured::InitResult::NotImplemented,
+        entry: |_| {},
+        exit: |_| {},
+        handle: unsafe {
+            core::mem::transmute::<
+                fn(
+                    &mut UserStateMachine,
+                    &<UserStateMachine as TopState>::Evt,
+                ) -> HandleResult<UserStateMachine>,
+                fn(&mut denatured::OpaqueType, &denatured::UserEvt) -> denatured::HandleResult,
+            >(UserStateMachine::on_unhandled)
+        },
+        get_parent_state: || denatured::ParentState::TopReached,
+        parent: None,
+        name: "Top",
+        uid: StateUid::top,
+        lineage: &Lineage::Top,
+        shallow_history: |_| None,
+        deep_history: |_| None,
+        region_init: |_| denatured::InitResult::NotImplemented,
+        regions: |_| None,
+        regions_mut: |_| None,
+        choose: |_| None,
+        on_completion: |_| denatured::HandleResult::Ignored,
+        is_final: false,
+    };
+}
+
+// Tag of the top state, which cannot be named outside of this module.
+enum TopTag {}
+
+/// Unique identifier of a state, derived from the type of its tag. The state machine compares
+/// states through their identifier, not through the address of their table of functions.
+#[doc(hidden)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct StateUid(TypeId);
+
+impl StateUid {
+    /// Return the identifier of the state tagged `Tag`.
+    pub fn of<Tag: 'static>() -> StateUid {
+        StateUid(TypeId::of::<Tag>())
+    }
+
+    /// Return the identifier of the top state.
+    pub fn top() -> StateUid {
+        StateUid::of::<TopTag>()
+    }
+}
+
+/// Lightweight handle on one of the user-defined states of a state machine, as yielded by
+/// [`StateMachine::active_states()`].
+#[repr(C)]
+pub struct StateRef<UserStateMachine: TopState + ?Sized> {
+    pub(crate) state_fn: StateFn<UserStateMachine>,
+    pub(crate) uid: StateUid,
+}
+
+impl<UserStateMachine: TopState + ?Sized> StateRef<UserStateMachine> {
+    /// Return `true` if the referred state is the one tagged `Tag`.
+    pub fn is<Tag: 'static>(&self) -> bool
+    where
+        UserStateMachine: State<Tag>,
+    {
+        self.uid == StateUid::of::<Tag>()
+    }
+
+    /// Return `true` if the referred state is the top state, which is the case when an event
+    /// has been caught by [`TopState::on_unhandled()`].
+    pub fn is_top(&self) -> bool {
+        self.uid == StateUid::top()
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized> Clone for StateRef<UserStateMachine> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized> Copy for StateRef<UserStateMachine> {}
+
+/// Two references are equal if they refer to the same state, whatever the address of the table
+/// of functions through which they refer to it.
+impl<UserStateMachine: TopState + ?Sized> PartialEq for StateRef<UserStateMachine> {
+    fn eq(&self, other: &Self) -> bool {
+        self.uid == other.uid
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized> Eq for StateRef<UserStateMachine> {}
+
+/// Returned by [`StateMachine::dispatch()`] to report how the event has been processed by the
+/// state machine.
+pub enum DispatchOutcome<UserStateMachine: TopState + ?Sized> {
+    /// The event has been handled by the `handled_by` state without triggering a transition.
+    Handled {
+        handled_by: StateRef<UserStateMachine>,
+    },
+    /// No active state handled the event, which has been dropped after reaching the top state.
+    Ignored,
+    /// The event has been handled by the `source` state, which triggered a transition to the
+    /// `target` state.
+    Transition {
+        source: StateRef<UserStateMachine>,
+        target: StateRef<UserStateMachine>,
+    },
+    /// The event has been deferred by the `deferred_by` state. It is kept in the defer queue of
+    /// the state machine until the next transition.
+    Deferred {
+        deferred_by: StateRef<UserStateMachine>,
+    },
+    /// The event has been handled by the `terminated_by` state, which terminated the state
+    /// machine after exiting every active state.
+    Terminated {
+        terminated_by: StateRef<UserStateMachine>,
+    },
+}
+
+impl<UserStateMachine: TopState + ?Sized> Clone for DispatchOutcome<UserStateMachine> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<UserStateMachine: TopState + ?Sized> Copy for DispatchOutcome<UserStateMachine> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{init_transition, transition, InitStateMachine, StateMachine};
+
+    enum Evt {
+        Restart,
+        Work,
+        Abort,
+    }
+
+    struct DeviceStateMachine {
+        entry_count: usize,
+        exit_count: usize,
+    }
+
+    enum Operating {}
+    enum Idle {}
+    enum Busy {}
+
+    // Copy of the table of functions of `Operating`, at another address than the constant
+    // table of the state, as a codegen unit duplicating the constant would do.
+    static OPERATING_VTABLE_COPY: denatured::StateVTable =
+        <DeviceStateMachine as HasStateVTable<Operating>>::STATE_VTABLE;
+
+    impl TopState for DeviceStateMachine {
+        type Evt = Evt;
+
+        fn init(&mut self) -> InitResult<Self> {
+            init_transition!(Operating)
+        }
+    }
+
+    impl State<Operating> for DeviceStateMachine {
+        fn get_parent_state() -> ParentState<Self> {
+            ParentState::TopReached
+        }
+
+        fn init(&mut self) -> InitResult<Self> {
+            init_transition!(Idle)
+        }
+
+        fn entry(&mut self) {
+            self.entry_count += 1;
+        }
+
+        fn exit(&mut self) {
+            self.exit_count += 1;
+        }
+
+        fn handle(&mut self, evt: &Evt) -> HandleResult<Self> {
+            match evt {
+                Evt::Restart => transition!(Operating),
+                _ => HandleResult::Ignored,
             }
-            CoreEvt::ExitEvt => {
-                <Self as State<Tag>>::exit(self);
-                return CoreHandleResult::Handled;
+        }
+
+        // Transitions target the copy of the table, while the substates refer to the constant
+        // table as their parent
+        fn state_fn() -> StateFn<Self> {
+            StateFn::new(&OPERATING_VTABLE_COPY)
+        }
+    }
+
+    impl State<Idle> for DeviceStateMachine {
+        fn get_parent_state() -> ParentState<Self> {
+            ParentState::Exists(StateFn::of::<Operating>())
+        }
+
+        fn handle(&mut self, evt: &Evt) -> HandleResult<Self> {
+            match evt {
+                Evt::Work => transition!(Busy),
+                _ => HandleResult::Ignored,
             }
-            CoreEvt::GetParentStateEvt => {
-                return CoreHandleResult::GetParentStateResult(Self::get_parent_state());
+        }
+    }
+
+    impl State<Busy> for DeviceStateMachine {
+        fn get_parent_state() -> ParentState<Self> {
+            ParentState::Exists(StateFn::of::<Operating>())
+        }
+
+        fn handle(&mut self, evt: &Evt) -> HandleResult<Self> {
+            match evt {
+                Evt::Abort => transition!(Operating),
+                _ => HandleResult::Ignored,
             }
-            CoreEvt::UserEvt { user_evt } => match <Self as State<Tag>>::handle(self, user_evt) {
-                HandleResult::Ignored => return CoreHandleResult::Ignored(Self::get_parent_state()),
-                HandleResult::Handled => return CoreHandleResult::Handled,
-                HandleResult::Transition(state_fn) => CoreHandleResult::Transition(state_fn),
-            },
         }
     }
+
+    fn dispatch_transition(
+        sm: &mut StateMachine<DeviceStateMachine>,
+        evt: &Evt,
+    ) -> (StateRef<DeviceStateMachine>, StateRef<DeviceStateMachine>) {
+        match sm.dispatch(evt) {
+            DispatchOutcome::Transition { source, target } => (source, target),
+            _ => panic!("Transition expected"),
+        }
+    }
+
+    // Return the number of times `Operating` has been entered and exited
+    fn counts(sm: &StateMachine<DeviceStateMachine>) -> (usize, usize) {
+        let user_state_machine = &sm.user_state_machine;
+        (
+            user_state_machine.entry_count,
+            user_state_machine.exit_count,
+        )
+    }
+
+    // The state machine recognizes a state through its identifier, whatever the address of the
+    // table of functions through which it refers to the state
+    #[test]
+    fn state_identity_across_vtable_copies() {
+        let operating_vtable = StateFn::<DeviceStateMachine>::of::<Operating>().vtable;
+        assert!(!core::ptr::eq(operating_vtable, &OPERATING_VTABLE_COPY));
+
+        let ism = InitStateMachine::from(DeviceStateMachine {
+            entry_count: 0,
+            exit_count: 0,
+        });
+        let mut sm = ism.init();
+
+        dispatch_transition(&mut sm, &Evt::Work);
+        assert!(sm.is_in::<Busy>());
+
+        // The target is recognized as the LCA, which is not exited
+        let (source, target) = dispatch_transition(&mut sm, &Evt::Abort);
+        assert!(source.is::<Busy>() && target.is::<Operating>());
+        assert!(sm.is_in::<Idle>());
+        assert_eq!(counts(&sm), (1, 0));
+
+        // The target is recognized as the handling state, making it a self-transition
+        let (source, target) = dispatch_transition(&mut sm, &Evt::Restart);
+        assert!(source == target);
+        assert!(sm.is_in::<Idle>());
+        assert_eq!(counts(&sm), (2, 1));
+    }
 }
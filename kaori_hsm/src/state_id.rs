@@ -0,0 +1,171 @@
+use core::marker::PhantomData;
+
+use crate::proto_state_machine::TopState;
+use crate::state::StateRef;
+
+//For doc
+#[allow(unused_imports)]
+use crate::{State, StateMachine};
+
+/// Give a value-level identifier to each state of the state machine, in the form of a generated
+/// enum having one variant per state, named after the state machine structure followed by
+/// `StateId`.
+///
+/// The trait must be implemented with the `#[state_machine()]` procedural macro placed on the
+/// state machine structure, which takes the list of its states as `states` argument. The macro
+/// also implements `From<StateId>` for `&'static str`, which returns the name of the state tag,
+/// as does [`State::NAME`] for the states implemented with the `#[state()]` procedural macro.
+/// The enum may be renamed with the optional `state_id` argument. The variant of a state may be
+/// renamed as well, as in `states = [Idle, motor::Idle as MotorIdle]`, which is required when the
+/// tags of two listed states share the same name.
+///
+/// Every state implemented with the `#[state()]` or `#[final_state()]` procedural macro checks
+/// at compile time that it is listed in `states`, so that [`StateMachine::current_state_id()`]
+/// never misses a state. The check is not performed for a state machine having generic
+/// parameters.
+/// # Example
+/// ```
+///# use kaori_hsm::*;
+///# enum BasicEvt{A}
+/// #[state_machine(states = [S0, S1])]
+/// struct BasicStateMachine{
+/// }
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+///
+/// #[state(super_state= Top)]
+/// impl State<S0> for BasicStateMachine{
+///     fn handle(&mut self, evt: & BasicEvt) -> HandleResult<Self> {
+///         match evt{
+///             BasicEvt::A => transition!(S1)
+///         }
+///     }
+/// }
+///
+/// #[state(super_state= Top)]
+/// impl State<S1> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+///
+/// let ism = InitStateMachine::from(BasicStateMachine{});
+/// let mut sm = ism.init();
+/// assert_eq!(sm.current_state_id(), Some(BasicStateMachineStateId::S0));
+///
+/// sm.dispatch(&BasicEvt::A);
+/// let state_id = sm.current_state_id().unwrap();
+/// assert_eq!(state_id, BasicStateMachineStateId::S1);
+/// assert_eq!(<&str>::from(state_id), "S1");
+/// ```
+/// ```compile_fail
+///# use kaori_hsm::*;
+///# enum BasicEvt{}
+/// #[state_machine(states = [S0])]
+/// struct BasicStateMachine{
+/// }
+///#
+///# impl TopState for BasicStateMachine{
+///#   type Evt = BasicEvt;
+///#
+///#   fn init(&mut self) -> InitResult<Self> {
+///#     init_transition!(S0)
+///#   }
+///# }
+///#
+///# #[state(super_state= Top)]
+///# impl State<S0> for BasicStateMachine{
+///#     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///#         ignored!()
+///#     }
+///# }
+///
+/// // Error: `S1` is not listed in `states`
+/// #[state(super_state= Top)]
+/// impl State<S1> for BasicStateMachine{
+///     fn handle(&mut self, _evt: & BasicEvt) -> HandleResult<Self> {
+///         ignored!()
+///     }
+/// }
+/// ```
+pub trait HasStateId: TopState {
+    /// Enum having one variant per state of the state machine, named after its tag unless it
+    /// is renamed.
+    type StateId: Copy;
+
+    #[doc(hidden)]
+    fn state_id(state: StateRef<Self>) -> Option<Self::StateId>;
+}
+
+impl<UserStateMachine: HasStateId> StateRef<UserStateMachine> {
+    /// Return the identifier of the referred state, or `None` if it is the top state or a state
+    /// which is not listed by the `#[state_machine()]` procedural macro.
+    pub fn id(&self) -> Option<UserStateMachine::StateId> {
+        UserStateMachine::state_id(*self)
+    }
+}
+
+// Compile-time check, emitted along with each state, that a state machine implementing
+// `HasStateId` lists the state in the `states` argument of the `#[state_machine()]` procedural
+// macro. The method resolution of `(&&StateIdProbe::default()).probe()` picks `ProbeStateId` if
+// the state machine implements `HasStateId`, in which case `check_listed_state()` requires the
+// state to be listed, and falls back on `ProbeNoStateId` otherwise.
+#[doc(hidden)]
+pub trait ListedState<Tag> {}
+
+#[doc(hidden)]
+pub struct StateIdProbe<UserStateMachine: ?Sized, Tag>(PhantomData<fn(&UserStateMachine) -> Tag>);
+
+impl<UserStateMachine: ?Sized, Tag> Default for StateIdProbe<UserStateMachine, Tag> {
+    fn default() -> Self {
+        StateIdProbe(PhantomData)
+    }
+}
+
+#[doc(hidden)]
+pub struct ListedStateRequired<UserStateMachine: ?Sized, Tag>(
+    PhantomData<fn(&UserStateMachine) -> Tag>,
+);
+
+#[doc(hidden)]
+pub struct NoStateId;
+
+#[doc(hidden)]
+pub trait StateIdCheck {}
+
+impl<UserStateMachine: ListedState<Tag> + ?Sized, Tag> StateIdCheck
+    for ListedStateRequired<UserStateMachine, Tag>
+{
+}
+
+impl StateIdCheck for NoStateId {}
+
+#[doc(hidden)]
+pub trait ProbeStateId<UserStateMachine: ?Sized, Tag> {
+    fn probe(&self) -> ListedStateRequired<UserStateMachine, Tag> {
+        ListedStateRequired(PhantomData)
+    }
+}
+
+impl<UserStateMachine: HasStateId + ?Sized, Tag> ProbeStateId<UserStateMachine, Tag>
+    for &StateIdProbe<UserStateMachine, Tag>
+{
+}
+
+#[doc(hidden)]
+pub trait ProbeNoStateId {
+    fn probe(&self) -> NoStateId {
+        NoStateId
+    }
+}
+
+impl<UserStateMachine: ?Sized, Tag> ProbeNoStateId for StateIdProbe<UserStateMachine, Tag> {}
+
+#[doc(hidden)]
+pub fn check_listed_state<Check: StateIdCheck>(_check: Check) {}
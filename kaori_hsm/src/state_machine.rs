@@ -1,31 +1,493 @@
+use core::marker::PhantomData;
+
+use crate::error::{DispatchError, ModelError};
+use crate::evt_queue::EvtQueue;
 use crate::proto_state_machine::TopState;
 use crate::sm_business_logic::SMBusinessLogic;
-use crate::state::{CoreEvt, StateFn};
+use crate::state::{
+    denatured, DispatchOutcome, HasTopStateVTable, ParentState, StateFn, StateRef, StateUid,
+};
+use crate::state_id::HasStateId;
 
 //For doc
 #[allow(unused_imports)]
-use crate::{InitStateMachine, State};
+use crate::{HandleResult, InitStateMachine, Poster, Regions, State};
 
 /// Represent a fully functional state machine, which already transitioned to its
-/// first state. It may be only created from a call to [`InitStateMachine::init()`].
-pub struct StateMachine<UserStateMachine: TopState> {
+/// first state. It may be only created from a call to [`InitStateMachine::init()`] or
+/// [`InitStateMachine::init_with_defer_queue()`].
+///
+/// The `DEFER_CAPACITY` parameter is the maximum number of events which can be kept in the
+/// defer queue of the state machine at the same time (see [`HandleResult::Deferred`]). The
+/// events posted by the states are kept by their [`Poster`].
+pub struct StateMachine<UserStateMachine: TopState, const DEFER_CAPACITY: usize = 0> {
     pub(crate) user_state_machine: UserStateMachine,
-    pub(crate) curr_state: StateFn<UserStateMachine>,
+    pub(crate) curr_state: StateRef<UserStateMachine>,
+    pub(crate) defer_queue: EvtQueue<<UserStateMachine as TopState>::Evt, DEFER_CAPACITY>,
 }
 
-impl<UserStateMachine: TopState> StateMachine<UserStateMachine> {
+impl<UserStateMachine: TopState, const DEFER_CAPACITY: usize>
+    StateMachine<UserStateMachine, DEFER_CAPACITY>
+{
     /// Dispatch an event to the state machine. The event is of the type you have set
     /// in [`TopState::Evt`].
-    pub fn dispatch(&mut self, user_evt: &<UserStateMachine as TopState>::Evt) {
-        let evt = CoreEvt::UserEvt { user_evt };
-        unsafe {
-            <Self as SMBusinessLogic>::dispatch_evt_to_current_state(
-                core::mem::transmute(&mut self.user_state_machine),
-                core::mem::transmute(&mut self.curr_state),
-                core::mem::transmute(&evt),
-            )
+    /// The returned [`DispatchOutcome`] tells which state handled the event and whether it
+    /// triggered a transition.
+    ///
+    /// A deferred event is copied into the defer queue. Every time a transition is taken, the
+    /// deferred events are recalled, that is dispatched again in their order of arrival.
+    ///
+    /// The events posted by the states through their [`Poster`] are then dispatched in their
+    /// order of arrival before this method returns.
+    ///
+    /// Once the state machine is terminated, the events are dropped without being dispatched and
+    /// [`DispatchOutcome::Ignored`] is returned.
+    ///
+    /// # Panics
+    /// Panics if an event is deferred while the defer queue is full, or if the state machine is
+    /// found to be inconsistent. Use [`StateMachine::try_dispatch()`] for handling those errors.
+    ///
+    /// # Stack usage
+    /// The state hierarchy is walked iteratively, the states to enter being collected into a
+    /// fixed buffer of 8 state references, so that the stack space used for dispatching an event
+    /// and taking the transitions it triggers does not depend on the depth of the hierarchy. Only
+    /// orthogonal states lead to recursion: an event is dispatched to the regions of an orthogonal
+    /// state, and the regions are entered and exited, through the same functions as the region
+    /// containing the orthogonal state. The stack usage is therefore bounded by
+    /// `S0 + k * Sr + U`, where:
+    /// - `k` is the nesting level of the orthogonal states, that is the largest number of
+    ///   orthogonal states found in the lineage of a state, the state itself included.
+    /// - `S0` is the stack usage of the state machine without regions.
+    /// - `Sr` is the stack usage added by each level of nesting, the largest of the frames added
+    ///   for dispatching an event to a region, for entering a region and for exiting a region.
+    /// - `U` is the stack usage of the deepest user-defined method called, such as a
+    ///   [`State::handle()`] method, an entry or exit action, an action or a guard.
+    ///
+    /// The levels of nesting only add up along the lineage of a single state: the regions of an
+    /// orthogonal state are processed one after the other, and the entry phase of a transition
+    /// only starts once its exit phase is over.
+    ///
+    /// `S0` and `Sr` vary with the target, the compiler version and the optimization level, and
+    /// should be measured on the target with the `stack_usage` example. It reports the stack space
+    /// used from the caller of `dispatch()` down to the deepest user-defined method, including the
+    /// small frame of the measuring function. On x86_64 in release mode with rustc 1.95:
+    ///
+    /// | Dispatch                                          | `k` | Stack usage |
+    /// |---------------------------------------------------|-----|-------------|
+    /// | Handled by the current state                      | 0   | 1264 bytes  |
+    /// | Transition exiting and entering 1 level           | 0   | 1808 bytes  |
+    /// | Transition entering 19 levels                     | 0   | 1856 bytes  |
+    /// | Transition exiting 19 levels                      | 0   | 1856 bytes  |
+    /// | Handled in a region                               | 1   | 2112 bytes  |
+    /// | Transition entering an orthogonal state           | 1   | 2224 bytes  |
+    /// | Transition exiting an orthogonal state            | 1   | 2672 bytes  |
+    /// | Handled in a region of a region                   | 2   | 2976 bytes  |
+    /// | Transition entering 2 nested orthogonal states    | 2   | 2448 bytes  |
+    /// | Transition exiting 2 nested orthogonal states     | 2   | 3536 bytes  |
+    ///
+    /// On this target, `S0` is 1856 bytes, the largest usage without regions, and `Sr` is 864
+    /// bytes, the largest increase from one level of nesting to the next. The stack usage is thus
+    /// bounded by `1856 + k * 864` bytes, plus the stack usage of the user-defined methods.
+    ///
+    /// # Complexity
+    /// The hierarchy is ascended through the parent of each state, recorded in a constant table.
+    /// Exiting `n` states and searching for the LCA of a transition take `O(n)` steps. The states
+    /// to enter are collected 8 at a time, each time through an ascent from the target state.
+    /// Entering `n` states thus takes `O(n)` steps up to 8 states, and about `n² / 16` steps
+    /// beyond.
+    pub fn dispatch(
+        &mut self,
+        user_evt: &<UserStateMachine as TopState>::Evt,
+    ) -> DispatchOutcome<UserStateMachine> {
+        match self.try_dispatch(user_evt) {
+            Ok(dispatch_outcome) => dispatch_outcome,
+            Err(DispatchError::Terminated) => DispatchOutcome::Ignored,
+            Err(dispatch_error) => panic!("{}", dispatch_error),
+        }
+    }
+
+    /// Same as [`StateMachine::dispatch()`], except that errors are returned instead of causing
+    /// a panic. An error is also returned if the state machine is already terminated.
+    ///
+    /// The events posted by the states are all dispatched even if an error occurs, the first
+    /// error being returned. After a [`ModelError`], the state machine is terminated.
+    pub fn try_dispatch(
+        &mut self,
+        user_evt: &<UserStateMachine as TopState>::Evt,
+    ) -> Result<DispatchOutcome<UserStateMachine>, DispatchError> {
+        if self.is_terminated() {
+            return Err(DispatchError::Terminated);
+        }
+
+        let dispatch_result = self.process_evt(user_evt);
+        let posted_evts_result = self.process_posted_evts();
+        let dispatch_outcome = dispatch_result?;
+        posted_evts_result?;
+        Ok(dispatch_outcome)
+    }
+
+    // Dispatch every event posted by the states, including those posted in the meantime. Return
+    // the first error encountered.
+    pub(crate) fn process_posted_evts(&mut self) -> Result<(), DispatchError> {
+        let mut result = Ok(());
+        while let Some(user_evt) = pop_posted_evt(&mut self.user_state_machine) {
+            if let Err(dispatch_error) = self.process_evt(&user_evt) {
+                result = result.and(Err(dispatch_error));
+            }
+        }
+        result
+    }
+
+    // Run-to-completion step of the state machine, made of the dispatch of `user_evt` followed by
+    // either its deferral or the recall of the deferred events.
+    fn process_evt(
+        &mut self,
+        user_evt: &<UserStateMachine as TopState>::Evt,
+    ) -> Result<DispatchOutcome<UserStateMachine>, DispatchError> {
+        let dispatch_outcome = self.dispatch_evt(user_evt)?;
+
+        match dispatch_outcome {
+            DispatchOutcome::Deferred { .. } if !self.defer_queue.push_copy(user_evt) => {
+                return Err(DispatchError::DeferQueueFull)
+            }
+            DispatchOutcome::Transition { .. } => self.recall_deferred_evts()?,
+            _ => {}
+        }
+        Ok(dispatch_outcome)
+    }
+
+    /// Return the number of events currently kept in the defer queue.
+    pub fn deferred_evt_count(&self) -> usize {
+        self.defer_queue.len()
+    }
+
+    // Dispatch again every deferred event in their order of arrival. Every transition taken
+    // during the process leads the events deferred again to be recalled once more.
+    fn recall_deferred_evts(&mut self) -> Result<(), ModelError> {
+        let mut remaining_evt_count = self.defer_queue.len();
+
+        while remaining_evt_count > 0 {
+            remaining_evt_count -= 1;
+
+            let Some(user_evt) = self.defer_queue.pop() else {
+                break;
+            };
+            match self.dispatch_evt(&user_evt)? {
+                // The event popped just before leaves room for it
+                DispatchOutcome::Deferred { .. } => self.defer_queue.push(user_evt),
+                DispatchOutcome::Transition { .. } => {
+                    remaining_evt_count = self.defer_queue.len();
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // Dispatch `user_evt` to the current state. The state machine is terminated if found to be
+    // inconsistent.
+    fn dispatch_evt(
+        &mut self,
+        user_evt: &<UserStateMachine as TopState>::Evt,
+    ) -> Result<DispatchOutcome<UserStateMachine>, ModelError> {
+        if self.is_terminated() {
+            return Ok(DispatchOutcome::Ignored);
+        }
+
+        enable_poster(&mut self.user_state_machine);
+
+        let dispatch_result = unsafe {
+            let dispatch_result =
+                <StateMachine<UserStateMachine> as SMBusinessLogic>::dispatch_evt_to_current_state(
+                    core::mem::transmute::<&mut UserStateMachine, &mut denatured::OpaqueType>(
+                        &mut self.user_state_machine,
+                    ),
+                    core::mem::transmute::<&mut StateRef<UserStateMachine>, &mut denatured::StateRef>(
+                        &mut self.curr_state,
+                    ),
+                    core::mem::transmute::<&<UserStateMachine as TopState>::Evt, &denatured::UserEvt>(
+                        user_evt,
+                    ),
+                );
+            dispatch_result.map(|dispatch_outcome| {
+                core::mem::transmute::<denatured::DispatchOutcome, DispatchOutcome<UserStateMachine>>(
+                    dispatch_outcome,
+                )
+            })
+        };
+
+        disable_poster(&mut self.user_state_machine);
+
+        match dispatch_result {
+            // The deferred events will never be recalled
+            Ok(DispatchOutcome::Terminated { .. }) => self.defer_queue.clear(),
+            Err(_) => {
+                self.curr_state = StateRef {
+                    state_fn: StateFn::new(
+                        <StateMachine<UserStateMachine> as SMBusinessLogic>::TOP_STATE_FN,
+                    ),
+                    uid: StateUid::top(),
+                };
+                self.defer_queue.clear();
+            }
+            _ => {}
+        }
+        dispatch_result
+    }
+
+    /// Return `true` if the state machine has been terminated by a state returning
+    /// [`HandleResult::Terminate`], or by reaching a final state of the topmost region (see
+    /// [`FinalState`](crate::FinalState)). A terminated state machine has no active state left.
+    pub fn is_terminated(&self) -> bool {
+        self.curr_state.is_top()
+    }
+
+    /// Return `true` if the state tagged `Tag` is active, that is if it is either the current
+    /// state of the state machine, one of its ancestors or an active state of one of the regions
+    /// of an orthogonal state (see [`Regions`]).
+    pub fn is_in<Tag: 'static>(&self) -> bool
+    where
+        UserStateMachine: State<Tag>,
+    {
+        is_in_lineage::<UserStateMachine, Tag>(&self.user_state_machine, self.curr_state.state_fn)
+    }
+
+    /// Return the name of the current state of the state machine, which is `"Top"` once the state
+    /// machine is terminated. See [`State::NAME`].
+    pub fn current_state_name(&self) -> &'static str {
+        get_state_name(self.curr_state.state_fn)
+    }
+
+    /// Return the identifier of the current state of the state machine, or `None` once the state
+    /// machine is terminated. See [`HasStateId`].
+    pub fn current_state_id(&self) -> Option<UserStateMachine::StateId>
+    where
+        UserStateMachine: HasStateId,
+    {
+        self.curr_state.id()
+    }
+
+    /// Return the name of the `state` sent as argument. See [`State::NAME`].
+    pub fn state_name(&self, state: StateRef<UserStateMachine>) -> &'static str {
+        get_state_name(state.state_fn)
+    }
+
+    /// Write the names of all the active states into `writer`, from the topmost active state
+    /// down to the current state, separated by `/` (e.g. `S1/S12/S121`).
+    pub fn write_active_state_path<W: core::fmt::Write>(
+        &self,
+        writer: &mut W,
+    ) -> core::fmt::Result {
+        write_state_path(self.curr_state.state_fn, writer)
+    }
+
+    /// Return an iterator over the active states of the state machine, starting from the current
+    /// state and ascending the state hierarchy up to the state whose parent is the top state.
+    /// The active states of the regions of an orthogonal current state are not part of the
+    /// iteration, they may be queried through its [`Regions`] field.
+    pub fn active_states(&self) -> ActiveStates<'_, UserStateMachine> {
+        let next_state_fn = if self.is_terminated() {
+            None
+        } else {
+            Some(self.curr_state.state_fn)
+        };
+        ActiveStates {
+            _user_state_machine: PhantomData,
+            next_state_fn,
+        }
+    }
+}
+
+// Only implemented for the default defer capacity, so that the top state has a single table of
+// functions whatever the capacity.
+impl<UserStateMachine: TopState> SMBusinessLogic for StateMachine<UserStateMachine> {
+    const TOP_STATE_FN: denatured::StateFn =
+        &<UserStateMachine as HasTopStateVTable>::TOP_STATE_VTABLE;
+}
+
+/// Iterator over the active states of a [`StateMachine`], from the current state up to the
+/// topmost active state. Created by [`StateMachine::active_states()`].
+pub struct ActiveStates<'a, UserStateMachine: TopState> {
+    _user_state_machine: PhantomData<&'a UserStateMachine>,
+    next_state_fn: Option<StateFn<UserStateMachine>>,
+}
+
+impl<'a, UserStateMachine: TopState> Iterator for ActiveStates<'a, UserStateMachine> {
+    type Item = StateRef<UserStateMachine>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let state_fn = self.next_state_fn.take()?;
+
+        if let ParentState::Exists(parent_state_fn) = get_parent_state(state_fn) {
+            self.next_state_fn = Some(parent_state_fn);
         }
+
+        Some(StateRef {
+            state_fn,
+            uid: get_state_uid(state_fn),
+        })
+    }
+}
+
+// Let the states post events through the poster of the state machine, if it has one, until it
+// is disabled again.
+pub(crate) fn enable_poster<UserStateMachine: TopState>(user_state_machine: &mut UserStateMachine) {
+    if let Some(poster) = user_state_machine.poster() {
+        poster.enable();
     }
 }
 
-impl<UserStateMachine: TopState> SMBusinessLogic for StateMachine<UserStateMachine> {}
+pub(crate) fn disable_poster<UserStateMachine: TopState>(
+    user_state_machine: &mut UserStateMachine,
+) {
+    if let Some(poster) = user_state_machine.poster() {
+        poster.disable();
+    }
+}
+
+fn pop_posted_evt<UserStateMachine: TopState>(
+    user_state_machine: &mut UserStateMachine,
+) -> Option<<UserStateMachine as TopState>::Evt> {
+    user_state_machine.poster().and_then(|poster| poster.pop())
+}
+
+fn get_parent_state<UserStateMachine: TopState>(
+    state_fn: StateFn<UserStateMachine>,
+) -> ParentState<UserStateMachine> {
+    let parent_state =
+        <StateMachine<UserStateMachine> as SMBusinessLogic>::dispatch_get_super_state(
+            state_fn.vtable,
+        );
+    unsafe {
+        core::mem::transmute::<denatured::ParentState, ParentState<UserStateMachine>>(parent_state)
+    }
+}
+
+fn get_region_leaf<UserStateMachine: TopState>(
+    user_state_machine: &UserStateMachine,
+    state_fn: StateFn<UserStateMachine>,
+    region: usize,
+) -> Option<StateFn<UserStateMachine>> {
+    let leaf_state_fn = unsafe {
+        <StateMachine<UserStateMachine> as SMBusinessLogic>::dispatch_get_region_leaf(
+            core::mem::transmute::<&UserStateMachine, &denatured::OpaqueType>(user_state_machine),
+            state_fn.vtable,
+            region,
+        )
+    };
+    leaf_state_fn.map(StateFn::new)
+}
+
+// Return the region state of the region at index `region` of the `state_fn` state, if it has
+// such a region.
+fn get_region_state<UserStateMachine: TopState>(
+    state_fn: StateFn<UserStateMachine>,
+    region: usize,
+) -> Option<StateFn<UserStateMachine>> {
+    match <StateMachine<UserStateMachine> as SMBusinessLogic>::dispatch_get_region_init(
+        state_fn.vtable,
+        region,
+    ) {
+        denatured::InitResult::TargetState(region_state_fn) => Some(StateFn::new(region_state_fn)),
+        denatured::InitResult::NotImplemented => None,
+    }
+}
+
+// Return the `state_fn` state followed by its ancestors, up to the state whose parent is the top
+// state.
+fn get_lineage<'a, UserStateMachine: TopState>(
+    state_fn: StateFn<UserStateMachine>,
+) -> ActiveStates<'a, UserStateMachine> {
+    ActiveStates {
+        _user_state_machine: PhantomData,
+        next_state_fn: Some(state_fn),
+    }
+}
+
+// Return `true` if the state tagged `Tag` is active, `state_fn` being the current state of the
+// state machine. The tagged state is active if it is an ancestor of the current state of its
+// region, and if the orthogonal state owning the region is itself active, which is checked the
+// same way. The hierarchy is thus ascended from the tagged state rather than descended through
+// the regions, so that no recursion is needed.
+fn is_in_lineage<UserStateMachine: State<Tag>, Tag: 'static>(
+    user_state_machine: &UserStateMachine,
+    state_fn: StateFn<UserStateMachine>,
+) -> bool {
+    let mut searched_state_fn = <UserStateMachine as State<Tag>>::state_fn();
+
+    loop {
+        // Ascend to the orthogonal state owning the region of the searched state, if any
+        let mut region_state_fn = searched_state_fn;
+        let orthogonal_state_fn = loop {
+            match get_parent_state(region_state_fn) {
+                ParentState::Exists(parent_state_fn) if has_regions(parent_state_fn) => {
+                    break Some(parent_state_fn)
+                }
+                ParentState::Exists(parent_state_fn) => region_state_fn = parent_state_fn,
+                ParentState::TopReached => break None,
+            }
+        };
+
+        let leaf_state_fn = match orthogonal_state_fn {
+            Some(orthogonal_state_fn) => {
+                let mut region = 0;
+                let leaf_state_fn = loop {
+                    match get_region_state(orthogonal_state_fn, region) {
+                        Some(state_fn)
+                            if get_state_uid(state_fn) == get_state_uid(region_state_fn) =>
+                        {
+                            break get_region_leaf(user_state_machine, orthogonal_state_fn, region)
+                        }
+                        Some(_) => region += 1,
+                        None => break None,
+                    }
+                };
+                match leaf_state_fn {
+                    Some(leaf_state_fn) => leaf_state_fn,
+                    None => return false,
+                }
+            }
+            None => state_fn,
+        };
+
+        let searched_uid = get_state_uid(searched_state_fn);
+        if !get_lineage(leaf_state_fn).any(|state| state.uid == searched_uid) {
+            return false;
+        }
+        match orthogonal_state_fn {
+            Some(orthogonal_state_fn) => searched_state_fn = orthogonal_state_fn,
+            None => return true,
+        }
+    }
+}
+
+fn has_regions<UserStateMachine: TopState>(state_fn: StateFn<UserStateMachine>) -> bool {
+    <StateMachine<UserStateMachine> as SMBusinessLogic>::has_regions(state_fn.vtable)
+}
+
+fn get_state_uid<UserStateMachine: TopState>(state_fn: StateFn<UserStateMachine>) -> StateUid {
+    <StateMachine<UserStateMachine> as SMBusinessLogic>::dispatch_get_uid(state_fn.vtable)
+}
+
+fn get_state_name<UserStateMachine: TopState>(state_fn: StateFn<UserStateMachine>) -> &'static str {
+    <StateMachine<UserStateMachine> as SMBusinessLogic>::dispatch_get_name(state_fn.vtable)
+}
+
+// Write the ancestors of `state_fn` before `state_fn` itself, so that the path reads from the
+// topmost state down to `state_fn`. The lineage of `state_fn` is ascended again for every written
+// state, so that no recursion is needed.
+fn write_state_path<UserStateMachine: TopState, W: core::fmt::Write>(
+    state_fn: StateFn<UserStateMachine>,
+    writer: &mut W,
+) -> core::fmt::Result {
+    let depth = get_lineage(state_fn).count();
+    for generations in (0..depth).rev() {
+        if let Some(state) = get_lineage(state_fn).nth(generations) {
+            writer.write_str(get_state_name(state.state_fn))?;
+        }
+        if generations > 0 {
+            writer.write_char('/')?;
+        }
+    }
+    Ok(())
+}
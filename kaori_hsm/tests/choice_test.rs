@@ -0,0 +1,256 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum ThermostatEvt {
+    Measure(i8),
+    Restart,
+}
+
+struct ThermostatStateMachine {
+    sender: Sender<String>,
+    temperature: i8,
+}
+
+impl ThermostatStateMachine {
+    pub fn new(sender: Sender<String>) -> ThermostatStateMachine {
+        ThermostatStateMachine {
+            sender,
+            temperature: 20,
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for ThermostatStateMachine {
+    type Evt = ThermostatEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Operating)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Operating> for ThermostatStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("OPERATING-INIT");
+        init_transition!(Idle)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("OPERATING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("OPERATING-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &ThermostatEvt) -> HandleResult<Self> {
+        match evt {
+            ThermostatEvt::Measure(temperature) => {
+                self.post_string("OPERATING-HANDLES-MEASURE");
+                self.temperature = *temperature;
+                transition!(TemperatureChoice)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[choice(super_state = Operating, targets = [Shutdown, Cooling, Heating, Idle])]
+impl Choice<TemperatureChoice> for ThermostatStateMachine {
+    fn choose(&mut self) -> HandleResult<Self> {
+        self.post_string("TEMPERATURE-CHOICE");
+        if self.temperature > 80 {
+            transition!(Shutdown)
+        } else if self.temperature > 25 {
+            transition!(Cooling)
+        } else if self.temperature < 15 {
+            transition!(Heating)
+        } else {
+            transition!(Idle)
+        }
+    }
+}
+
+#[state(super_state = Operating)]
+impl State<Idle> for ThermostatStateMachine {
+    fn exit(&mut self) {
+        self.post_string("IDLE-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("IDLE-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &ThermostatEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Operating)]
+impl State<Heating> for ThermostatStateMachine {
+    fn exit(&mut self) {
+        self.post_string("HEATING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("HEATING-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &ThermostatEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Operating)]
+impl State<Cooling> for ThermostatStateMachine {
+    fn exit(&mut self) {
+        self.post_string("COOLING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("COOLING-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &ThermostatEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Shutdown> for ThermostatStateMachine {
+    fn exit(&mut self) {
+        self.post_string("SHUTDOWN-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("SHUTDOWN-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &ThermostatEvt) -> HandleResult<Self> {
+        match evt {
+            ThermostatEvt::Restart => {
+                self.post_string("SHUTDOWN-HANDLES-RESTART");
+                transition!(RestartChoice)
+            }
+            _ => handled!(),
+        }
+    }
+}
+
+// Choice selecting another choice
+#[choice(super_state = Top)]
+impl Choice<RestartChoice> for ThermostatStateMachine {
+    fn choose(&mut self) -> HandleResult<Self> {
+        self.post_string("RESTART-CHOICE");
+        transition!(TemperatureChoice)
+    }
+}
+
+#[test]
+fn choice_test() {
+    let (sender, mut receiver) = channel();
+
+    let thermostat_state_machine = ThermostatStateMachine::new(sender);
+    let ism = InitStateMachine::from(thermostat_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "TOP_INIT",
+            "OPERATING-ENTRY",
+            "OPERATING-INIT",
+            "IDLE-ENTRY",
+        ],
+    );
+
+    // The guards are evaluated once the states below the LCA have been exited
+    match sm.dispatch(&ThermostatEvt::Measure(10)) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Operating>());
+            assert!(target.is::<Heating>());
+        }
+        _ => panic!("Expected a transition to the chosen state"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "OPERATING-HANDLES-MEASURE",
+            "IDLE-EXIT",
+            "TEMPERATURE-CHOICE",
+            "HEATING-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Heating>());
+    assert!(!sm.is_in::<TemperatureChoice>());
+
+    sm.dispatch(&ThermostatEvt::Measure(30));
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "OPERATING-HANDLES-MEASURE",
+            "HEATING-EXIT",
+            "TEMPERATURE-CHOICE",
+            "COOLING-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&ThermostatEvt::Measure(20));
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "OPERATING-HANDLES-MEASURE",
+            "COOLING-EXIT",
+            "TEMPERATURE-CHOICE",
+            "IDLE-ENTRY",
+        ],
+    );
+
+    // The chosen target may require exiting states above the LCA
+    sm.dispatch(&ThermostatEvt::Measure(90));
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "OPERATING-HANDLES-MEASURE",
+            "IDLE-EXIT",
+            "TEMPERATURE-CHOICE",
+            "OPERATING-EXIT",
+            "SHUTDOWN-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Shutdown>());
+
+    // A choice may select another choice, whose target states are entered from the top state
+    match sm.dispatch(&ThermostatEvt::Restart) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Shutdown>());
+            assert!(target.is::<Shutdown>());
+        }
+        _ => panic!("Expected a transition to the chosen state"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "SHUTDOWN-HANDLES-RESTART",
+            "SHUTDOWN-EXIT",
+            "RESTART-CHOICE",
+            "TEMPERATURE-CHOICE",
+            "SHUTDOWN-ENTRY",
+        ],
+    );
+
+    assert_eq!(
+        <ThermostatStateMachine as Choice<TemperatureChoice>>::TARGETS,
+        ["Shutdown", "Cooling", "Heating", "Idle"]
+    );
+    assert!(<ThermostatStateMachine as Choice<RestartChoice>>::TARGETS.is_empty());
+}
@@ -0,0 +1,60 @@
+// Helpers shared by the integration tests. The state machines under test report their activity
+// as strings sent through a channel, whose receiving end is checked against the expected output.
+#![allow(dead_code)]
+
+pub use std::sync::mpsc::channel;
+pub use std::sync::mpsc::Receiver;
+pub use std::sync::mpsc::Sender;
+use std::sync::mpsc::TryRecvError;
+
+use kaori_hsm::{InitStateMachine, StateMachine, TopState};
+
+pub fn collect_sm_output(receiver: &mut Receiver<String>) -> String {
+    receiver.try_recv().unwrap_or_else(|err| match err {
+        TryRecvError::Empty => panic!("Too many expectations for the SM output"),
+        TryRecvError::Disconnected => panic!("Disconnected"),
+    })
+}
+
+pub fn expect_output_series(receiver: &mut Receiver<String>, expectations: Vec<&str>) {
+    for (index, expectation) in expectations.into_iter().enumerate() {
+        let sm_output = collect_sm_output(receiver);
+        if expectation != sm_output {
+            panic!(
+                "Expectation index {},  expected : {},  got: {}",
+                index, expectation, sm_output
+            )
+        }
+    }
+
+    // Check that we have expected all the output of the SM
+    match receiver.try_recv().err() {
+        Some(TryRecvError::Empty) => { /* OK */ }
+        Some(TryRecvError::Disconnected) => {
+            panic!(" Sender is dead")
+        }
+        None => {
+            panic!("Too few expectations for the SM output")
+        }
+    }
+}
+
+pub fn test_evt_injection<UserStateMachine: TopState, const DEFER_CAPACITY: usize>(
+    sm: &mut StateMachine<UserStateMachine, DEFER_CAPACITY>,
+    receiver: &mut Receiver<String>,
+    evt: <UserStateMachine as TopState>::Evt,
+    expectations: Vec<&str>,
+) {
+    sm.dispatch(&evt);
+    expect_output_series(receiver, expectations);
+}
+
+pub fn test_sm_init<UserStateMachine: TopState>(
+    ism: InitStateMachine<UserStateMachine>,
+    receiver: &mut Receiver<String>,
+    expectations: Vec<&str>,
+) -> StateMachine<UserStateMachine> {
+    let sm = ism.init();
+    expect_output_series(receiver, expectations);
+    sm
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use common::*;
+
+// Facade re-exporting the library, as done by the frameworks wrapping it
+mod facade {
+    pub use ::kaori_hsm::*;
+}
+
+// Shadow the name of the library, so that the code generated by the macros can only reach it
+// through the facade
+#[allow(dead_code)]
+mod kaori_hsm {}
+
+use facade::*;
+
+// Evt definition
+#[derive(Debug)]
+enum BasicEvt {
+    A,
+    B,
+    C,
+}
+
+#[state_machine(states = [S1, S11, S12], crate = crate::facade)]
+struct BasicStateMachine {
+    sender: Sender<String>,
+    level: u8,
+}
+
+impl BasicStateMachine {
+    pub fn new(sender: Sender<String>) -> BasicStateMachine {
+        BasicStateMachine { sender, level: 0 }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for BasicStateMachine {
+    type Evt = BasicEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(S1)
+    }
+}
+
+#[state(super_state = Top, crate = crate::facade)]
+impl State<S1> for BasicStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(S11)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("S1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S1-EXIT");
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::A => {
+                self.level += 1;
+                transition!(LevelChoice)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = S1, crate = crate::facade)]
+impl State<S11> for BasicStateMachine {
+    fn entry(&mut self) {
+        self.post_string("S11-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S11-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = S1, crate = crate::facade)]
+impl State<S12> for BasicStateMachine {
+    fn entry(&mut self) {
+        self.post_string("S12-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S12-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[choice(super_state = S1, targets = [S11, S12], crate = crate::facade)]
+impl Choice<LevelChoice> for BasicStateMachine {
+    fn choose(&mut self) -> HandleResult<Self> {
+        if self.level > 1 {
+            transition!(S12)
+        } else {
+            transition!(S11)
+        }
+    }
+}
+
+// State machine declared through the `hsm!` macro
+struct DslStateMachine {
+    sender: Sender<String>,
+}
+
+impl DslStateMachine {
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+
+    fn on_d1_entry(&mut self) {
+        self.post_string("D1-ENTRY");
+    }
+
+    fn on_d2_entry(&mut self) {
+        self.post_string("D2-ENTRY");
+    }
+}
+
+hsm! {
+    machine DslStateMachine {
+        crate = crate::facade;
+        type Evt = BasicEvt;
+        init => D1;
+    }
+
+    state D1(super_state = Top) {
+        entry / Self::on_d1_entry;
+        BasicEvt::B => D2;
+    }
+
+    state D2(super_state = Top) {
+        entry / Self::on_d2_entry;
+        BasicEvt::C => D1;
+    }
+}
+
+#[test]
+fn crate_path_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "S1-ENTRY", "S11-ENTRY"]);
+
+    sm.dispatch(&BasicEvt::A);
+    expect_output_series(&mut receiver, vec!["S11-EXIT", "S11-ENTRY"]);
+    assert_eq!(sm.current_state_id(), Some(BasicStateMachineStateId::S11));
+
+    sm.dispatch(&BasicEvt::A);
+    expect_output_series(&mut receiver, vec!["S11-EXIT", "S12-ENTRY"]);
+    assert_eq!(sm.current_state_id(), Some(BasicStateMachineStateId::S12));
+}
+
+#[test]
+fn crate_path_hsm_dsl_test() {
+    let (sender, mut receiver) = channel();
+
+    let ism = InitStateMachine::from(DslStateMachine { sender });
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["D1-ENTRY"]);
+
+    sm.dispatch(&BasicEvt::B);
+    expect_output_series(&mut receiver, vec!["D2-ENTRY"]);
+
+    sm.dispatch(&BasicEvt::C);
+    expect_output_series(&mut receiver, vec!["D1-ENTRY"]);
+    assert_eq!(sm.current_state_name(), "D1");
+}
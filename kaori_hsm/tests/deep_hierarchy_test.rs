@@ -0,0 +1,184 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum DiveEvt {
+    Dive,
+    Surface,
+}
+
+struct DiveStateMachine {
+    sender: Sender<String>,
+}
+
+impl DiveStateMachine {
+    pub fn new(sender: Sender<String>) -> DiveStateMachine {
+        DiveStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for DiveStateMachine {
+    type Evt = DiveEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Shore)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Shore> for DiveStateMachine {
+    fn entry(&mut self) {
+        self.post_string("SHORE-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("SHORE-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DiveEvt) -> HandleResult<Self> {
+        match evt {
+            DiveEvt::Dive => transition!(D19),
+            _ => ignored!(),
+        }
+    }
+}
+
+// Composite state `$tag`, nested in `$super_state`, whose initial transition targets `$child`
+macro_rules! depth_state {
+    ($tag:ident, $super_state:ident, $child:ident) => {
+        #[state(super_state = $super_state)]
+        impl State<$tag> for DiveStateMachine {
+            fn init(&mut self) -> InitResult<Self> {
+                init_transition!($child)
+            }
+
+            fn entry(&mut self) {
+                self.post_string(concat!(stringify!($tag), "-ENTRY"));
+            }
+
+            fn exit(&mut self) {
+                self.post_string(concat!(stringify!($tag), "-EXIT"));
+            }
+
+            fn handle(&mut self, _evt: &DiveEvt) -> HandleResult<Self> {
+                ignored!()
+            }
+        }
+    };
+}
+
+depth_state!(D1, Top, D2);
+depth_state!(D2, D1, D3);
+depth_state!(D3, D2, D4);
+depth_state!(D4, D3, D5);
+depth_state!(D5, D4, D6);
+depth_state!(D6, D5, D7);
+depth_state!(D7, D6, D8);
+depth_state!(D8, D7, D9);
+depth_state!(D9, D8, D10);
+depth_state!(D10, D9, D11);
+depth_state!(D11, D10, D12);
+depth_state!(D12, D11, D13);
+depth_state!(D13, D12, D14);
+depth_state!(D14, D13, D15);
+depth_state!(D15, D14, D16);
+depth_state!(D16, D15, D17);
+depth_state!(D17, D16, D18);
+depth_state!(D18, D17, D19);
+
+#[state(super_state = D18)]
+impl State<D19> for DiveStateMachine {
+    fn entry(&mut self) {
+        self.post_string("D19-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("D19-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DiveEvt) -> HandleResult<Self> {
+        match evt {
+            DiveEvt::Surface => transition!(Shore),
+            _ => ignored!(),
+        }
+    }
+}
+
+// The entry path of a transition is longer than the number of states collected through a single
+// ascent of the hierarchy
+#[test]
+fn deep_hierarchy_test() {
+    let (sender, mut receiver) = channel();
+
+    let dive_state_machine = DiveStateMachine::new(sender);
+    let ism = InitStateMachine::from(dive_state_machine);
+
+    let mut sm = test_sm_init(ism, &mut receiver, vec!["TOP_INIT", "SHORE-ENTRY"]);
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        DiveEvt::Dive,
+        vec![
+            "SHORE-EXIT",
+            "D1-ENTRY",
+            "D2-ENTRY",
+            "D3-ENTRY",
+            "D4-ENTRY",
+            "D5-ENTRY",
+            "D6-ENTRY",
+            "D7-ENTRY",
+            "D8-ENTRY",
+            "D9-ENTRY",
+            "D10-ENTRY",
+            "D11-ENTRY",
+            "D12-ENTRY",
+            "D13-ENTRY",
+            "D14-ENTRY",
+            "D15-ENTRY",
+            "D16-ENTRY",
+            "D17-ENTRY",
+            "D18-ENTRY",
+            "D19-ENTRY",
+        ],
+    );
+    assert_eq!(sm.current_state_name(), "D19");
+    assert_eq!(sm.active_states().count(), 19);
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        DiveEvt::Surface,
+        vec![
+            "D19-EXIT",
+            "D18-EXIT",
+            "D17-EXIT",
+            "D16-EXIT",
+            "D15-EXIT",
+            "D14-EXIT",
+            "D13-EXIT",
+            "D12-EXIT",
+            "D11-EXIT",
+            "D10-EXIT",
+            "D9-EXIT",
+            "D8-EXIT",
+            "D7-EXIT",
+            "D6-EXIT",
+            "D5-EXIT",
+            "D4-EXIT",
+            "D3-EXIT",
+            "D2-EXIT",
+            "D1-EXIT",
+            "SHORE-ENTRY",
+        ],
+    );
+    assert_eq!(sm.current_state_name(), "Shore");
+}
@@ -0,0 +1,158 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug, Clone)]
+enum JobEvt {
+    Request(u8),
+    Done,
+}
+
+struct JobStateMachine {
+    sender: Sender<String>,
+}
+
+impl JobStateMachine {
+    pub fn new(sender: Sender<String>) -> JobStateMachine {
+        JobStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for JobStateMachine {
+    type Evt = JobEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Idle)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Idle> for JobStateMachine {
+    fn entry(&mut self) {
+        self.post_string("IDLE-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("IDLE-EXIT");
+    }
+
+    fn handle(&mut self, evt: &JobEvt) -> HandleResult<Self> {
+        match evt {
+            JobEvt::Request(id) => {
+                self.post_string(&format!("IDLE-HANDLES-REQUEST-{}", id));
+                transition!(Busy)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Busy> for JobStateMachine {
+    fn entry(&mut self) {
+        self.post_string("BUSY-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("BUSY-EXIT");
+    }
+
+    fn handle(&mut self, evt: &JobEvt) -> HandleResult<Self> {
+        match evt {
+            JobEvt::Request(id) => {
+                self.post_string(&format!("BUSY-DEFERS-REQUEST-{}", id));
+                deferred!()
+            }
+            JobEvt::Done => {
+                self.post_string("BUSY-HANDLES-DONE");
+                transition!(Idle)
+            }
+        }
+    }
+}
+
+#[test]
+fn defer_test() {
+    let (sender, mut receiver) = channel();
+
+    let job_state_machine = JobStateMachine::new(sender);
+    let ism = InitStateMachine::from(job_state_machine);
+
+    let mut sm: StateMachine<_, 2> = ism.init_with_defer_queue();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "IDLE-ENTRY"]);
+
+    sm.dispatch(&JobEvt::Request(1));
+    expect_output_series(
+        &mut receiver,
+        vec!["IDLE-HANDLES-REQUEST-1", "IDLE-EXIT", "BUSY-ENTRY"],
+    );
+
+    match sm.dispatch(&JobEvt::Request(2)) {
+        DispatchOutcome::Deferred { deferred_by } => assert!(deferred_by.is::<Busy>()),
+        _ => panic!("Expected the event to be deferred"),
+    }
+    sm.dispatch(&JobEvt::Request(3));
+    expect_output_series(
+        &mut receiver,
+        vec!["BUSY-DEFERS-REQUEST-2", "BUSY-DEFERS-REQUEST-3"],
+    );
+    assert_eq!(sm.deferred_evt_count(), 2);
+
+    // The deferred events are recalled in their order of arrival after the transition, the
+    // second one being deferred again
+    match sm.dispatch(&JobEvt::Done) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Busy>());
+            assert!(target.is::<Idle>());
+        }
+        _ => panic!("Expected a transition"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "BUSY-HANDLES-DONE",
+            "BUSY-EXIT",
+            "IDLE-ENTRY",
+            "IDLE-HANDLES-REQUEST-2",
+            "IDLE-EXIT",
+            "BUSY-ENTRY",
+            "BUSY-DEFERS-REQUEST-3",
+        ],
+    );
+    assert_eq!(sm.deferred_evt_count(), 1);
+
+    sm.dispatch(&JobEvt::Done);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "BUSY-HANDLES-DONE",
+            "BUSY-EXIT",
+            "IDLE-ENTRY",
+            "IDLE-HANDLES-REQUEST-3",
+            "IDLE-EXIT",
+            "BUSY-ENTRY",
+        ],
+    );
+    assert_eq!(sm.deferred_evt_count(), 0);
+    assert!(sm.is_in::<Busy>());
+}
+
+#[test]
+#[should_panic(expected = "Deferred event does not fit in the defer queue")]
+fn defer_queue_full_test() {
+    let (sender, _receiver) = channel();
+
+    let ism = InitStateMachine::from(JobStateMachine::new(sender));
+    let mut sm: StateMachine<_, 1> = ism.init_with_defer_queue();
+
+    sm.dispatch(&JobEvt::Request(1));
+    sm.dispatch(&JobEvt::Request(2));
+    sm.dispatch(&JobEvt::Request(3));
+}
@@ -0,0 +1,192 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug, Clone)]
+enum BasicEvt {
+    Wait,
+    Pick,
+    Stray,
+    Stop,
+}
+
+struct BasicStateMachine {
+    sender: Sender<String>,
+    has_init_target: bool,
+    s1_regions: Regions<Self, 1>,
+}
+
+impl BasicStateMachine {
+    pub fn new(sender: Sender<String>, has_init_target: bool) -> BasicStateMachine {
+        BasicStateMachine {
+            sender,
+            has_init_target,
+            s1_regions: Regions::new(),
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for BasicStateMachine {
+    type Evt = BasicEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        if self.has_init_target {
+            init_transition!(S0)
+        } else {
+            InitResult::NotImplemented
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<S0> for BasicStateMachine {
+    fn exit(&mut self) {
+        self.post_string("S0-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("S0-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::Wait => {
+                self.post_string("S0-DEFERS-WAIT");
+                deferred!()
+            }
+            BasicEvt::Pick => {
+                self.post_string("S0-HANDLES-PICK");
+                transition!(FaultyChoice)
+            }
+            BasicEvt::Stray => {
+                self.post_string("S0-HANDLES-STRAY");
+                transition!(S12)
+            }
+            BasicEvt::Stop => {
+                self.post_string("S0-HANDLES-STOP");
+                terminate!()
+            }
+        }
+    }
+}
+
+#[choice(super_state = Top)]
+impl Choice<FaultyChoice> for BasicStateMachine {
+    fn choose(&mut self) -> HandleResult<Self> {
+        self.post_string("FAULTY_CHOICE");
+        handled!()
+    }
+}
+
+// Orthogonal state whose substate S12 is not listed as a region state
+#[state(super_state = Top, regions = [S11] in s1_regions)]
+impl State<S1> for BasicStateMachine {
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = S1)]
+impl State<S11> for BasicStateMachine {
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = S1)]
+impl State<S12> for BasicStateMachine {
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[test]
+fn init_error_test() {
+    let (sender, mut receiver) = channel();
+
+    // The state machine is dropped on failure, keep the channel open
+    let basic_state_machine = BasicStateMachine::new(sender.clone(), false);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    assert!(matches!(
+        ism.try_init(),
+        Err(InitError::MissingTopInitTarget)
+    ));
+    expect_output_series(&mut receiver, vec!["TOP_INIT"]);
+}
+
+#[test]
+fn dispatch_error_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender, true);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = ism.try_init_with_defer_queue::<1>().unwrap();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "S0-ENTRY"]);
+
+    assert!(sm.try_dispatch(&BasicEvt::Wait).is_ok());
+    expect_output_series(&mut receiver, vec!["S0-DEFERS-WAIT"]);
+
+    assert_eq!(
+        sm.try_dispatch(&BasicEvt::Wait).err(),
+        Some(DispatchError::DeferQueueFull)
+    );
+    expect_output_series(&mut receiver, vec!["S0-DEFERS-WAIT"]);
+    assert_eq!(sm.deferred_evt_count(), 1);
+
+    // The state machine is terminated once found inconsistent
+    assert_eq!(
+        sm.try_dispatch(&BasicEvt::Pick).err(),
+        Some(DispatchError::Model(ModelError::ChoiceWithoutTransition))
+    );
+    expect_output_series(
+        &mut receiver,
+        vec!["S0-HANDLES-PICK", "S0-EXIT", "FAULTY_CHOICE"],
+    );
+    assert!(sm.is_terminated());
+    assert_eq!(sm.deferred_evt_count(), 0);
+
+    assert_eq!(
+        sm.try_dispatch(&BasicEvt::Stop).err(),
+        Some(DispatchError::Terminated)
+    );
+    expect_output_series(&mut receiver, vec![]);
+}
+
+#[test]
+fn region_error_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender, true);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = ism.try_init().unwrap();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "S0-ENTRY"]);
+
+    assert_eq!(
+        sm.try_dispatch(&BasicEvt::Stray).err(),
+        Some(DispatchError::Model(ModelError::TargetOutsideRegions))
+    );
+    expect_output_series(&mut receiver, vec!["S0-HANDLES-STRAY", "S0-EXIT"]);
+    assert!(sm.is_terminated());
+}
+
+#[test]
+#[should_panic(expected = "Choice pseudostate did not select any transition")]
+fn dispatch_panic_test() {
+    let (sender, _receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender, true);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = ism.init();
+    sm.dispatch(&BasicEvt::Pick);
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum SensorEvt {
+    Sample,
+    Calibrate,
+    Reset,
+}
+
+#[derive(Debug)]
+enum BusError {
+    Nack,
+}
+
+#[derive(Debug)]
+enum SensorError {
+    Bus(BusError),
+    OutOfRange(u8),
+}
+
+impl From<BusError> for SensorError {
+    fn from(bus_error: BusError) -> Self {
+        SensorError::Bus(bus_error)
+    }
+}
+
+struct SensorStateMachine {
+    sender: Sender<String>,
+    bus_replies: Vec<Result<u8, BusError>>,
+    calibration_attempts: u8,
+}
+
+impl SensorStateMachine {
+    pub fn new(sender: Sender<String>, bus_replies: Vec<Result<u8, BusError>>) -> Self {
+        SensorStateMachine {
+            sender,
+            bus_replies,
+            calibration_attempts: 0,
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+
+    fn read_bus(&mut self) -> Result<u8, BusError> {
+        self.bus_replies.remove(0)
+    }
+}
+
+impl TopState for SensorStateMachine {
+    type Evt = SensorEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Operating)
+    }
+}
+
+impl FallibleTopState for SensorStateMachine {
+    type Error = SensorError;
+
+    fn on_error(&mut self, error: SensorError) -> HandleResult<Self> {
+        match error {
+            SensorError::Bus(BusError::Nack) => self.post_string("TOP-ON-ERROR-BUS"),
+            SensorError::OutOfRange(value) => {
+                self.post_string(&format!("TOP-ON-ERROR-OUT-OF-RANGE-{}", value))
+            }
+        }
+        transition!(Fault)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Operating> for SensorStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("OPERATING-INIT");
+        init_transition!(Sampling)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("OPERATING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("OPERATING-ENTRY");
+    }
+
+    fn try_handle(&mut self, evt: &SensorEvt) -> Result<HandleResult<Self>, SensorError> {
+        match evt {
+            SensorEvt::Calibrate => {
+                self.post_string("OPERATING-HANDLES-CALIBRATE");
+                Ok(transition!(Calibrating))
+            }
+            _ => Ok(ignored!()),
+        }
+    }
+}
+
+// Errors are handled by the top-level error handler
+#[state(super_state = Operating)]
+impl State<Sampling> for SensorStateMachine {
+    fn exit(&mut self) {
+        self.post_string("SAMPLING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("SAMPLING-ENTRY");
+    }
+
+    fn try_handle(&mut self, evt: &SensorEvt) -> Result<HandleResult<Self>, SensorError> {
+        match evt {
+            SensorEvt::Sample => {
+                self.post_string("SAMPLING-HANDLES-SAMPLE");
+                let value = self.read_bus()?;
+                if value > 100 {
+                    return Err(SensorError::OutOfRange(value));
+                }
+                Ok(handled!())
+            }
+            _ => Ok(ignored!()),
+        }
+    }
+}
+
+// Errors are handled by the state itself
+#[state(super_state = Operating)]
+impl State<Calibrating> for SensorStateMachine {
+    fn exit(&mut self) {
+        self.post_string("CALIBRATING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("CALIBRATING-ENTRY");
+        self.calibration_attempts = 0;
+    }
+
+    fn try_handle(&mut self, evt: &SensorEvt) -> Result<HandleResult<Self>, SensorError> {
+        match evt {
+            SensorEvt::Calibrate => {
+                self.post_string("CALIBRATING-HANDLES-CALIBRATE");
+                self.calibration_attempts += 1;
+                self.read_bus()?;
+                Ok(transition!(Sampling))
+            }
+            _ => Ok(ignored!()),
+        }
+    }
+
+    fn on_error(&mut self, _error: SensorError) -> HandleResult<Self> {
+        self.post_string("CALIBRATING-ON-ERROR");
+        if self.calibration_attempts < 2 {
+            handled!()
+        } else {
+            ignored!()
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Fault> for SensorStateMachine {
+    fn exit(&mut self) {
+        self.post_string("FAULT-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("FAULT-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &SensorEvt) -> HandleResult<Self> {
+        match evt {
+            SensorEvt::Reset => {
+                self.post_string("FAULT-HANDLES-RESET");
+                transition!(Operating)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn fallible_test() {
+    let (sender, mut receiver) = channel();
+
+    let bus_replies = vec![
+        Ok(42),
+        Err(BusError::Nack),
+        Err(BusError::Nack),
+        Ok(0),
+        Ok(101),
+    ];
+    let sensor_state_machine = SensorStateMachine::new(sender, bus_replies);
+    let ism = InitStateMachine::from(sensor_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "TOP_INIT",
+            "OPERATING-ENTRY",
+            "OPERATING-INIT",
+            "SAMPLING-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&SensorEvt::Sample);
+    expect_output_series(&mut receiver, vec!["SAMPLING-HANDLES-SAMPLE"]);
+
+    sm.dispatch(&SensorEvt::Calibrate);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "OPERATING-HANDLES-CALIBRATE",
+            "SAMPLING-EXIT",
+            "CALIBRATING-ENTRY",
+        ],
+    );
+
+    // The error of the first attempt is handled by the state
+    sm.dispatch(&SensorEvt::Calibrate);
+    expect_output_series(
+        &mut receiver,
+        vec!["CALIBRATING-HANDLES-CALIBRATE", "CALIBRATING-ON-ERROR"],
+    );
+    assert!(sm.is_in::<Calibrating>());
+
+    // The error of the second attempt is ignored by the state, so the event reaches the parent
+    // state
+    sm.dispatch(&SensorEvt::Calibrate);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "CALIBRATING-HANDLES-CALIBRATE",
+            "CALIBRATING-ON-ERROR",
+            "OPERATING-HANDLES-CALIBRATE",
+            "CALIBRATING-EXIT",
+            "CALIBRATING-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&SensorEvt::Calibrate);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "CALIBRATING-HANDLES-CALIBRATE",
+            "CALIBRATING-EXIT",
+            "SAMPLING-ENTRY",
+        ],
+    );
+
+    // The error is handled by the top-level error handler
+    let outcome = sm.dispatch(&SensorEvt::Sample);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "SAMPLING-HANDLES-SAMPLE",
+            "TOP-ON-ERROR-OUT-OF-RANGE-101",
+            "SAMPLING-EXIT",
+            "OPERATING-EXIT",
+            "FAULT-ENTRY",
+        ],
+    );
+    assert!(matches!(
+        outcome,
+        DispatchOutcome::Transition { source, target } if source.is::<Sampling>() && target.is::<Fault>()
+    ));
+
+    sm.dispatch(&SensorEvt::Reset);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "FAULT-HANDLES-RESET",
+            "FAULT-EXIT",
+            "OPERATING-ENTRY",
+            "OPERATING-INIT",
+            "SAMPLING-ENTRY",
+        ],
+    );
+}
@@ -0,0 +1,327 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug, Clone)]
+enum FirmwareEvt {
+    Chunk { last: bool },
+    Valid,
+    Written,
+    Flash,
+}
+
+struct FirmwareStateMachine {
+    sender: Sender<String>,
+    update_regions: Regions<Self, 2>,
+    verified: bool,
+}
+
+impl FirmwareStateMachine {
+    pub fn new(sender: Sender<String>) -> FirmwareStateMachine {
+        FirmwareStateMachine {
+            sender,
+            update_regions: Regions::new(),
+            verified: false,
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for FirmwareStateMachine {
+    type Evt = FirmwareEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Update)
+    }
+}
+
+#[state(super_state = Top, regions = [Download, Verification] in update_regions)]
+impl State<Update> for FirmwareStateMachine {
+    fn exit(&mut self) {
+        self.post_string("UPDATE-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("UPDATE-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &FirmwareEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+
+    fn on_completion(&mut self) -> HandleResult<Self> {
+        self.post_string("UPDATE-COMPLETED");
+        transition!(Installing)
+    }
+}
+
+#[state(super_state = Update)]
+impl State<Download> for FirmwareStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Fetching)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("DOWNLOAD-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("DOWNLOAD-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &FirmwareEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+
+    fn on_completion(&mut self) -> HandleResult<Self> {
+        self.post_string("DOWNLOAD-COMPLETED");
+        handled!()
+    }
+}
+
+#[state(super_state = Download)]
+impl State<Fetching> for FirmwareStateMachine {
+    fn exit(&mut self) {
+        self.post_string("FETCHING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("FETCHING-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &FirmwareEvt) -> HandleResult<Self> {
+        match evt {
+            FirmwareEvt::Chunk { last: true } => {
+                self.post_string("FETCHING-HANDLES-LAST-CHUNK");
+                transition!(Downloaded)
+            }
+            FirmwareEvt::Chunk { last: false } => {
+                self.post_string("FETCHING-HANDLES-CHUNK");
+                handled!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[final_state(super_state = Download)]
+impl FinalState<Downloaded> for FirmwareStateMachine {}
+
+#[state(super_state = Update)]
+impl State<Verification> for FirmwareStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Checking)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("VERIFICATION-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("VERIFICATION-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &FirmwareEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Verification)]
+impl State<Checking> for FirmwareStateMachine {
+    fn exit(&mut self) {
+        self.post_string("CHECKING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("CHECKING-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &FirmwareEvt) -> HandleResult<Self> {
+        match evt {
+            FirmwareEvt::Valid => {
+                self.post_string("CHECKING-HANDLES-VALID");
+                transition!(Verified)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[final_state(super_state = Verification)]
+impl FinalState<Verified> for FirmwareStateMachine {}
+
+#[state(super_state = Top)]
+impl State<Installing> for FirmwareStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Writing)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("INSTALLING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("INSTALLING-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &FirmwareEvt) -> HandleResult<Self> {
+        match evt {
+            FirmwareEvt::Flash => {
+                self.post_string("INSTALLING-HANDLES-FLASH");
+                self.verified = true;
+                transition!(Writing)
+            }
+            _ => ignored!(),
+        }
+    }
+
+    fn on_completion(&mut self) -> HandleResult<Self> {
+        self.post_string("INSTALLING-COMPLETED");
+        if self.verified {
+            transition!(Done)
+        } else {
+            ignored!()
+        }
+    }
+}
+
+#[state(super_state = Installing)]
+impl State<Writing> for FirmwareStateMachine {
+    fn exit(&mut self) {
+        self.post_string("WRITING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("WRITING-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &FirmwareEvt) -> HandleResult<Self> {
+        match evt {
+            FirmwareEvt::Written => {
+                self.post_string("WRITING-HANDLES-WRITTEN");
+                transition!(Written)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[final_state(super_state = Installing)]
+impl FinalState<Written> for FirmwareStateMachine {}
+
+#[final_state(super_state = Top)]
+impl FinalState<Done> for FirmwareStateMachine {}
+
+#[test]
+fn final_state_test() {
+    let (sender, mut receiver) = channel();
+
+    let firmware_state_machine = FirmwareStateMachine::new(sender);
+    let ism = InitStateMachine::from(firmware_state_machine);
+
+    let mut sm = test_sm_init(
+        ism,
+        &mut receiver,
+        vec![
+            "TOP_INIT",
+            "UPDATE-ENTRY",
+            "DOWNLOAD-ENTRY",
+            "FETCHING-ENTRY",
+            "VERIFICATION-ENTRY",
+            "CHECKING-ENTRY",
+        ],
+    );
+
+    // A region reaching its final state does not complete the region state itself, nor the
+    // orthogonal state as long as another region is not completed
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        FirmwareEvt::Valid,
+        vec!["CHECKING-HANDLES-VALID", "CHECKING-EXIT"],
+    );
+    assert!(sm.is_in::<Verified>() && sm.is_in::<Fetching>());
+
+    // Final states ignore every event, which are handled by the other states as usual
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        FirmwareEvt::Chunk { last: false },
+        vec!["FETCHING-HANDLES-CHUNK"],
+    );
+
+    // The orthogonal state is completed once its last region reaches its final state, its
+    // completion transition being taken as part of the same dispatch
+    match sm.dispatch(&FirmwareEvt::Chunk { last: true }) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Update>());
+            assert!(target.is::<Installing>());
+        }
+        _ => panic!("Expected the completion transition of the orthogonal state"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "FETCHING-HANDLES-LAST-CHUNK",
+            "FETCHING-EXIT",
+            "UPDATE-COMPLETED",
+            "DOWNLOAD-EXIT",
+            "VERIFICATION-EXIT",
+            "UPDATE-EXIT",
+            "INSTALLING-ENTRY",
+            "WRITING-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Writing>());
+
+    // A completion ignored by the composite state leaves the state machine in the final state
+    match sm.dispatch(&FirmwareEvt::Written) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Writing>());
+            assert!(target.is::<Written>());
+        }
+        _ => panic!("Expected a transition to the final state"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "WRITING-HANDLES-WRITTEN",
+            "WRITING-EXIT",
+            "INSTALLING-COMPLETED",
+        ],
+    );
+    assert_eq!(sm.current_state_name(), "Written");
+    assert!(!sm.is_terminated());
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        FirmwareEvt::Flash,
+        vec!["INSTALLING-HANDLES-FLASH", "WRITING-ENTRY"],
+    );
+
+    // Reaching a final state of the topmost region terminates the state machine
+    match sm.dispatch(&FirmwareEvt::Written) {
+        DispatchOutcome::Terminated { terminated_by } => {
+            assert!(terminated_by.is::<Done>());
+        }
+        _ => panic!("Expected the state machine to be terminated"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "WRITING-HANDLES-WRITTEN",
+            "WRITING-EXIT",
+            "INSTALLING-COMPLETED",
+            "INSTALLING-EXIT",
+        ],
+    );
+    assert!(sm.is_terminated());
+    assert_eq!(sm.active_states().count(), 0);
+}
@@ -0,0 +1,283 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum DriverEvt {
+    Connect,
+    Send,
+    Disconnect,
+}
+
+// Peripheral abstraction the drivers are generic over
+trait Uart {
+    fn write(&mut self, s: &str);
+}
+
+struct MockUart {
+    sender: Sender<String>,
+}
+
+impl Uart for MockUart {
+    fn write(&mut self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+// State machine generic over a type parameter, with a where clause
+struct Driver<T>
+where
+    T: Uart,
+{
+    uart: T,
+    history: History<Self>,
+}
+
+impl<T> Driver<T>
+where
+    T: Uart,
+{
+    pub fn new(uart: T) -> Driver<T> {
+        Driver {
+            uart,
+            history: History::new(),
+        }
+    }
+}
+
+impl<T> TopState for Driver<T>
+where
+    T: Uart,
+{
+    type Evt = DriverEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.uart.write("TOP_INIT");
+        init_transition!(Disconnected)
+    }
+}
+
+#[state(super_state = Top)]
+impl<T> State<Disconnected> for Driver<T>
+where
+    T: Uart,
+{
+    fn entry(&mut self) {
+        self.uart.write("DISCONNECTED-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.uart.write("DISCONNECTED-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DriverEvt) -> HandleResult<Self> {
+        match evt {
+            DriverEvt::Connect => transition_history!(Connected),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top, shallow_history = history)]
+impl<T: Uart> State<Connected> for Driver<T> {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Idle)
+    }
+
+    fn entry(&mut self) {
+        self.uart.write("CONNECTED-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.uart.write("CONNECTED-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DriverEvt) -> HandleResult<Self> {
+        match evt {
+            DriverEvt::Disconnect => transition!(Disconnected),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Connected)]
+impl<T: Uart> State<Idle> for Driver<T> {
+    fn entry(&mut self) {
+        self.uart.write("IDLE-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.uart.write("IDLE-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DriverEvt) -> HandleResult<Self> {
+        match evt {
+            DriverEvt::Send => transition!(Sending),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Connected)]
+impl<T: Uart> State<Sending> for Driver<T> {
+    fn entry(&mut self) {
+        self.uart.write("SENDING-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.uart.write("SENDING-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DriverEvt) -> HandleResult<Self> {
+        match evt {
+            DriverEvt::Send => handled!(),
+            _ => ignored!(),
+        }
+    }
+}
+
+// State machine borrowing its peripheral
+struct Ctrl<'a> {
+    uart: &'a mut MockUart,
+}
+
+impl<'a> TopState for Ctrl<'a> {
+    type Evt = DriverEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.uart.write("TOP_INIT");
+        init_transition!(Off)
+    }
+}
+
+#[state(super_state = Top)]
+impl<'a> State<Off> for Ctrl<'a> {
+    fn entry(&mut self) {
+        self.uart.write("OFF-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.uart.write("OFF-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DriverEvt) -> HandleResult<Self> {
+        match evt {
+            DriverEvt::Connect => transition!(On),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<On> for Ctrl<'_> {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Transmitting)
+    }
+
+    fn entry(&mut self) {
+        self.uart.write("ON-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.uart.write("ON-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DriverEvt) -> HandleResult<Self> {
+        match evt {
+            DriverEvt::Disconnect => transition!(Off),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = On)]
+impl<'a> State<Transmitting> for Ctrl<'a> {
+    fn entry(&mut self) {
+        self.uart.write("TRANSMITTING-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.uart.write("TRANSMITTING-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DriverEvt) -> HandleResult<Self> {
+        match evt {
+            DriverEvt::Send => {
+                self.uart.write("TRANSMITTING-SEND");
+                handled!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn generic_state_machine_test() {
+    let (sender, mut receiver) = channel();
+
+    let driver = Driver::new(MockUart { sender });
+    let ism = InitStateMachine::from(driver);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "DISCONNECTED-ENTRY"]);
+
+    sm.dispatch(&DriverEvt::Connect);
+    expect_output_series(
+        &mut receiver,
+        vec!["DISCONNECTED-EXIT", "CONNECTED-ENTRY", "IDLE-ENTRY"],
+    );
+
+    sm.dispatch(&DriverEvt::Send);
+    expect_output_series(&mut receiver, vec!["IDLE-EXIT", "SENDING-ENTRY"]);
+
+    sm.dispatch(&DriverEvt::Disconnect);
+    expect_output_series(
+        &mut receiver,
+        vec!["SENDING-EXIT", "CONNECTED-EXIT", "DISCONNECTED-ENTRY"],
+    );
+
+    // The history of the connection is restored
+    sm.dispatch(&DriverEvt::Connect);
+    expect_output_series(
+        &mut receiver,
+        vec!["DISCONNECTED-EXIT", "CONNECTED-ENTRY", "SENDING-ENTRY"],
+    );
+    assert!(sm.is_in::<Connected>());
+    assert_eq!(sm.current_state_name(), "Sending");
+}
+
+#[test]
+fn lifetime_state_machine_test() {
+    let (sender, mut receiver) = channel();
+    let mut uart = MockUart { sender };
+
+    {
+        let ctrl = Ctrl { uart: &mut uart };
+        let ism = InitStateMachine::from(ctrl);
+
+        let mut sm = ism.init();
+        expect_output_series(&mut receiver, vec!["TOP_INIT", "OFF-ENTRY"]);
+
+        sm.dispatch(&DriverEvt::Connect);
+        expect_output_series(
+            &mut receiver,
+            vec!["OFF-EXIT", "ON-ENTRY", "TRANSMITTING-ENTRY"],
+        );
+
+        sm.dispatch(&DriverEvt::Send);
+        expect_output_series(&mut receiver, vec!["TRANSMITTING-SEND"]);
+
+        sm.dispatch(&DriverEvt::Disconnect);
+        expect_output_series(
+            &mut receiver,
+            vec!["TRANSMITTING-EXIT", "ON-EXIT", "OFF-ENTRY"],
+        );
+        assert_eq!(sm.current_state_name(), "Off");
+    }
+
+    // The peripheral is available again once the state machine is dropped
+    uart.write("RELEASED");
+    expect_output_series(&mut receiver, vec!["RELEASED"]);
+}
@@ -0,0 +1,501 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum PlayerEvt {
+    Play,
+    Next,
+    Flash,
+    Pause,
+    Resume,
+}
+
+// Declare the player state machine, the history of the Playing state being of the
+// `$history_kind` kind. The song is made of two regions, the lyrics and the lights.
+macro_rules! player_state_machine {
+    ($history_kind:ident) => {
+        pub(super) struct PlayerStateMachine {
+            sender: Sender<String>,
+            playing_history: History<Self>,
+            song_regions: Regions<Self, 2>,
+        }
+
+        impl PlayerStateMachine {
+            pub(super) fn new(sender: Sender<String>) -> PlayerStateMachine {
+                PlayerStateMachine {
+                    sender,
+                    playing_history: History::new(),
+                    song_regions: Regions::new(),
+                }
+            }
+        }
+
+        impl TopState for PlayerStateMachine {
+            type Evt = PlayerEvt;
+
+            fn init(&mut self) -> InitResult<Self> {
+                self.sender.send(String::from("TOP_INIT")).unwrap();
+                init_transition!(Stopped)
+            }
+        }
+
+        #[state(super_state = Top, vis = pub(super))]
+        impl State<Stopped> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("STOPPED-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("STOPPED-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, evt: &PlayerEvt) -> HandleResult<Self> {
+                match evt {
+                    PlayerEvt::Play => {
+                        self.sender
+                            .send(String::from("STOPPED-HANDLES-PLAY"))
+                            .unwrap();
+                        transition_history!(Playing)
+                    }
+                    _ => ignored!(),
+                }
+            }
+        }
+
+        #[state(super_state = Top, vis = pub(super))]
+        impl State<Paused> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("PAUSED-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("PAUSED-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, evt: &PlayerEvt) -> HandleResult<Self> {
+                match evt {
+                    PlayerEvt::Resume => {
+                        self.sender
+                            .send(String::from("PAUSED-HANDLES-RESUME"))
+                            .unwrap();
+                        transition_history!(Playing)
+                    }
+                    _ => ignored!(),
+                }
+            }
+        }
+
+        #[state(super_state = Top, $history_kind = playing_history, vis = pub(super))]
+        impl State<Playing> for PlayerStateMachine {
+            fn init(&mut self) -> InitResult<Self> {
+                self.sender.send(String::from("PLAYING-INIT")).unwrap();
+                init_transition!(Intro)
+            }
+
+            fn exit(&mut self) {
+                self.sender.send(String::from("PLAYING-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("PLAYING-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, evt: &PlayerEvt) -> HandleResult<Self> {
+                match evt {
+                    PlayerEvt::Pause => {
+                        self.sender
+                            .send(String::from("PLAYING-HANDLES-PAUSE"))
+                            .unwrap();
+                        transition!(Paused)
+                    }
+                    _ => ignored!(),
+                }
+            }
+        }
+
+        #[state(super_state = Playing, vis = pub(super))]
+        impl State<Intro> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("INTRO-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("INTRO-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, evt: &PlayerEvt) -> HandleResult<Self> {
+                match evt {
+                    PlayerEvt::Next => {
+                        self.sender
+                            .send(String::from("INTRO-HANDLES-NEXT"))
+                            .unwrap();
+                        transition!(Song)
+                    }
+                    _ => ignored!(),
+                }
+            }
+        }
+
+        #[state(
+            super_state = Playing,
+            regions = [Lyrics, Lights] in song_regions,
+            vis = pub(super)
+        )]
+        impl State<Song> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("SONG-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("SONG-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, _evt: &PlayerEvt) -> HandleResult<Self> {
+                ignored!()
+            }
+        }
+
+        #[state(super_state = Song, vis = pub(super))]
+        impl State<Lyrics> for PlayerStateMachine {
+            fn init(&mut self) -> InitResult<Self> {
+                self.sender.send(String::from("LYRICS-INIT")).unwrap();
+                init_transition!(Verse)
+            }
+
+            fn exit(&mut self) {
+                self.sender.send(String::from("LYRICS-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("LYRICS-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, _evt: &PlayerEvt) -> HandleResult<Self> {
+                ignored!()
+            }
+        }
+
+        #[state(super_state = Lyrics, vis = pub(super))]
+        impl State<Verse> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("VERSE-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("VERSE-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, evt: &PlayerEvt) -> HandleResult<Self> {
+                match evt {
+                    PlayerEvt::Next => {
+                        self.sender
+                            .send(String::from("VERSE-HANDLES-NEXT"))
+                            .unwrap();
+                        transition!(Chorus)
+                    }
+                    _ => ignored!(),
+                }
+            }
+        }
+
+        #[state(super_state = Lyrics, vis = pub(super))]
+        impl State<Chorus> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("CHORUS-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("CHORUS-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, evt: &PlayerEvt) -> HandleResult<Self> {
+                match evt {
+                    PlayerEvt::Next => {
+                        self.sender
+                            .send(String::from("CHORUS-HANDLES-NEXT"))
+                            .unwrap();
+                        transition_history!(Playing)
+                    }
+                    _ => ignored!(),
+                }
+            }
+        }
+
+        #[state(super_state = Song, vis = pub(super))]
+        impl State<Lights> for PlayerStateMachine {
+            fn init(&mut self) -> InitResult<Self> {
+                self.sender.send(String::from("LIGHTS-INIT")).unwrap();
+                init_transition!(Dim)
+            }
+
+            fn exit(&mut self) {
+                self.sender.send(String::from("LIGHTS-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("LIGHTS-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, _evt: &PlayerEvt) -> HandleResult<Self> {
+                ignored!()
+            }
+        }
+
+        #[state(super_state = Lights, vis = pub(super))]
+        impl State<Dim> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("DIM-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("DIM-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, evt: &PlayerEvt) -> HandleResult<Self> {
+                match evt {
+                    PlayerEvt::Flash => {
+                        self.sender
+                            .send(String::from("DIM-HANDLES-FLASH"))
+                            .unwrap();
+                        transition!(Bright)
+                    }
+                    _ => ignored!(),
+                }
+            }
+        }
+
+        #[state(super_state = Lights, vis = pub(super))]
+        impl State<Bright> for PlayerStateMachine {
+            fn exit(&mut self) {
+                self.sender.send(String::from("BRIGHT-EXIT")).unwrap();
+            }
+
+            fn entry(&mut self) {
+                self.sender.send(String::from("BRIGHT-ENTRY")).unwrap();
+            }
+
+            fn handle(&mut self, _evt: &PlayerEvt) -> HandleResult<Self> {
+                ignored!()
+            }
+        }
+    };
+}
+
+mod shallow {
+    use super::*;
+
+    player_state_machine!(shallow_history);
+}
+
+mod deep {
+    use super::*;
+
+    player_state_machine!(deep_history);
+}
+
+// Start playing, then pause the player during the chorus of the song, the lights being bright.
+fn pause_during_chorus<UserStateMachine: TopState<Evt = PlayerEvt>>(
+    sm: &mut StateMachine<UserStateMachine>,
+    receiver: &mut Receiver<String>,
+) {
+    // Playing has never been exited, its initial transition is taken
+    test_evt_injection(
+        sm,
+        receiver,
+        PlayerEvt::Play,
+        vec![
+            "STOPPED-HANDLES-PLAY",
+            "STOPPED-EXIT",
+            "PLAYING-ENTRY",
+            "PLAYING-INIT",
+            "INTRO-ENTRY",
+        ],
+    );
+    test_evt_injection(
+        sm,
+        receiver,
+        PlayerEvt::Pause,
+        vec![
+            "PLAYING-HANDLES-PAUSE",
+            "INTRO-EXIT",
+            "PLAYING-EXIT",
+            "PAUSED-ENTRY",
+        ],
+    );
+    test_evt_injection(
+        sm,
+        receiver,
+        PlayerEvt::Resume,
+        vec![
+            "PAUSED-HANDLES-RESUME",
+            "PAUSED-EXIT",
+            "PLAYING-ENTRY",
+            "INTRO-ENTRY",
+        ],
+    );
+    assert_eq!(sm.current_state_name(), "Intro");
+
+    test_evt_injection(
+        sm,
+        receiver,
+        PlayerEvt::Next,
+        vec![
+            "INTRO-HANDLES-NEXT",
+            "INTRO-EXIT",
+            "SONG-ENTRY",
+            "LYRICS-ENTRY",
+            "LYRICS-INIT",
+            "VERSE-ENTRY",
+            "LIGHTS-ENTRY",
+            "LIGHTS-INIT",
+            "DIM-ENTRY",
+        ],
+    );
+    test_evt_injection(
+        sm,
+        receiver,
+        PlayerEvt::Next,
+        vec!["VERSE-HANDLES-NEXT", "VERSE-EXIT", "CHORUS-ENTRY"],
+    );
+    test_evt_injection(
+        sm,
+        receiver,
+        PlayerEvt::Flash,
+        vec!["DIM-HANDLES-FLASH", "DIM-EXIT", "BRIGHT-ENTRY"],
+    );
+    test_evt_injection(
+        sm,
+        receiver,
+        PlayerEvt::Pause,
+        vec![
+            "PLAYING-HANDLES-PAUSE",
+            "CHORUS-EXIT",
+            "LYRICS-EXIT",
+            "BRIGHT-EXIT",
+            "LIGHTS-EXIT",
+            "SONG-EXIT",
+            "PLAYING-EXIT",
+            "PAUSED-ENTRY",
+        ],
+    );
+}
+
+#[test]
+fn shallow_history_test() {
+    let (sender, mut receiver) = channel();
+
+    let player_state_machine = shallow::PlayerStateMachine::new(sender);
+    let ism = InitStateMachine::from(player_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "STOPPED-ENTRY"]);
+
+    pause_during_chorus(&mut sm, &mut receiver);
+
+    // Shallow history only restores the direct substate of Playing, whose regions then go
+    // through their initial transitions
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        PlayerEvt::Resume,
+        vec![
+            "PAUSED-HANDLES-RESUME",
+            "PAUSED-EXIT",
+            "PLAYING-ENTRY",
+            "SONG-ENTRY",
+            "LYRICS-ENTRY",
+            "LYRICS-INIT",
+            "VERSE-ENTRY",
+            "LIGHTS-ENTRY",
+            "LIGHTS-INIT",
+            "DIM-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<shallow::Verse>() && sm.is_in::<shallow::Dim>());
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        PlayerEvt::Next,
+        vec!["VERSE-HANDLES-NEXT", "VERSE-EXIT", "CHORUS-ENTRY"],
+    );
+
+    // History transition from inside the composite state to itself
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        PlayerEvt::Next,
+        vec![
+            "CHORUS-HANDLES-NEXT",
+            "CHORUS-EXIT",
+            "LYRICS-EXIT",
+            "DIM-EXIT",
+            "LIGHTS-EXIT",
+            "SONG-EXIT",
+            "SONG-ENTRY",
+            "LYRICS-ENTRY",
+            "LYRICS-INIT",
+            "VERSE-ENTRY",
+            "LIGHTS-ENTRY",
+            "LIGHTS-INIT",
+            "DIM-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<shallow::Verse>() && sm.is_in::<shallow::Dim>());
+}
+
+#[test]
+fn deep_history_test() {
+    let (sender, mut receiver) = channel();
+
+    let player_state_machine = deep::PlayerStateMachine::new(sender);
+    let ism = InitStateMachine::from(player_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "STOPPED-ENTRY"]);
+
+    pause_during_chorus(&mut sm, &mut receiver);
+
+    // Deep history restores the state each region was left in, without going through the
+    // initial transitions
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        PlayerEvt::Resume,
+        vec![
+            "PAUSED-HANDLES-RESUME",
+            "PAUSED-EXIT",
+            "PLAYING-ENTRY",
+            "SONG-ENTRY",
+            "LYRICS-ENTRY",
+            "CHORUS-ENTRY",
+            "LIGHTS-ENTRY",
+            "BRIGHT-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<deep::Chorus>() && sm.is_in::<deep::Bright>());
+
+    // History transition from inside the composite state to itself
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        PlayerEvt::Next,
+        vec![
+            "CHORUS-HANDLES-NEXT",
+            "CHORUS-EXIT",
+            "LYRICS-EXIT",
+            "BRIGHT-EXIT",
+            "LIGHTS-EXIT",
+            "SONG-EXIT",
+            "SONG-ENTRY",
+            "LYRICS-ENTRY",
+            "CHORUS-ENTRY",
+            "LIGHTS-ENTRY",
+            "BRIGHT-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<deep::Chorus>() && sm.is_in::<deep::Bright>());
+}
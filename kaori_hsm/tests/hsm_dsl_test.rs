@@ -0,0 +1,150 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug, Clone)]
+enum DoorEvt {
+    Code(u16),
+    Open,
+    Close,
+    Lock,
+    Break,
+}
+
+struct DoorStateMachine {
+    sender: Sender<String>,
+    closed_history: History<Self>,
+}
+
+impl DoorStateMachine {
+    pub fn new(sender: Sender<String>) -> DoorStateMachine {
+        DoorStateMachine {
+            sender,
+            closed_history: History::new(),
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+
+    fn on_top_init(&mut self) {
+        self.post_string("TOP_INIT");
+    }
+
+    fn on_closed_init(&mut self) {
+        self.post_string("CLOSED-INIT");
+    }
+
+    fn on_closed_entry(&mut self) {
+        self.post_string("CLOSED-ENTRY");
+    }
+
+    fn on_closed_exit(&mut self) {
+        self.post_string("CLOSED-EXIT");
+    }
+
+    fn on_valid_code(&mut self) {
+        self.post_string("VALID-CODE");
+    }
+
+    fn on_invalid_code(&mut self) {
+        self.post_string("INVALID-CODE");
+    }
+
+    fn on_close(&mut self) {
+        self.post_string("CLOSE");
+    }
+}
+
+hsm! {
+    machine DoorStateMachine {
+        type Evt = DoorEvt;
+        init => Closed / Self::on_top_init;
+    }
+
+    state Closed(super_state = Top, shallow_history = closed_history) {
+        init => Locked / Self::on_closed_init;
+        entry / Self::on_closed_entry;
+        exit / Self::on_closed_exit;
+        DoorEvt::Break => terminate;
+    }
+
+    state Locked(super_state = Closed) {
+        entry / |sm: &mut Self| sm.post_string("LOCKED-ENTRY");
+        exit / |sm: &mut Self| sm.post_string("LOCKED-EXIT");
+        DoorEvt::Code(code) if *code == 1234 => Unlocked / Self::on_valid_code;
+        DoorEvt::Code(_) => handled / Self::on_invalid_code;
+        DoorEvt::Open => deferred;
+    }
+
+    state Unlocked(super_state = Closed) {
+        entry / |sm: &mut Self| sm.post_string("UNLOCKED-ENTRY");
+        exit / |sm: &mut Self| sm.post_string("UNLOCKED-EXIT");
+        DoorEvt::Open => Opened;
+        DoorEvt::Lock => Locked;
+    }
+
+    state Opened(super_state = Top) {
+        entry / |sm: &mut Self| sm.post_string("OPENED-ENTRY");
+        exit / |sm: &mut Self| sm.post_string("OPENED-EXIT");
+        DoorEvt::Close => history Closed / Self::on_close;
+    }
+}
+
+#[test]
+fn hsm_dsl_test() {
+    let (sender, mut receiver) = channel();
+
+    let door_state_machine = DoorStateMachine::new(sender);
+    let ism = InitStateMachine::from(door_state_machine);
+
+    let mut sm = ism.init_with_defer_queue::<1>();
+    expect_output_series(
+        &mut receiver,
+        vec!["TOP_INIT", "CLOSED-ENTRY", "CLOSED-INIT", "LOCKED-ENTRY"],
+    );
+
+    // Deferred until the door is unlocked
+    sm.dispatch(&DoorEvt::Open);
+    expect_output_series(&mut receiver, vec![]);
+
+    // Rejected by the guard
+    sm.dispatch(&DoorEvt::Code(4321));
+    expect_output_series(&mut receiver, vec!["INVALID-CODE"]);
+    assert!(sm.is_in::<Locked>());
+
+    sm.dispatch(&DoorEvt::Code(1234));
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "LOCKED-EXIT",
+            "VALID-CODE",
+            "UNLOCKED-ENTRY",
+            "UNLOCKED-EXIT",
+            "CLOSED-EXIT",
+            "OPENED-ENTRY",
+        ],
+    );
+    assert_eq!(sm.current_state_name(), "Opened");
+
+    // Ignored by every state
+    sm.dispatch(&DoorEvt::Lock);
+    expect_output_series(&mut receiver, vec![]);
+
+    // The action of a history transition is executed between the exit and the entry phases
+    sm.dispatch(&DoorEvt::Close);
+    expect_output_series(
+        &mut receiver,
+        vec!["OPENED-EXIT", "CLOSE", "CLOSED-ENTRY", "UNLOCKED-ENTRY"],
+    );
+
+    sm.dispatch(&DoorEvt::Lock);
+    expect_output_series(&mut receiver, vec!["UNLOCKED-EXIT", "LOCKED-ENTRY"]);
+
+    sm.dispatch(&DoorEvt::Break);
+    expect_output_series(&mut receiver, vec!["LOCKED-EXIT", "CLOSED-EXIT"]);
+    assert!(sm.is_terminated());
+}
@@ -1,8 +1,6 @@
-use std::sync::mpsc::channel;
-use std::sync::mpsc::Receiver;
-use std::sync::mpsc::Sender;
-use std::sync::mpsc::TryRecvError;
+mod common;
 
+use common::*;
 use kaori_hsm::*;
 
 // Evt definition
@@ -181,56 +179,6 @@ impl State<S122> for BasicStateMachine {
     }
 }
 
-fn collect_sm_output(receiver: &mut Receiver<String>) -> String {
-    receiver.try_recv().unwrap_or_else(|err| match err {
-        TryRecvError::Empty => panic!("Too many expectations for the SM output"),
-        TryRecvError::Disconnected => panic!("Disconnected"),
-    })
-}
-
-fn expect_output_series(receiver: &mut Receiver<String>, expectations: Vec<&str>) {
-    for (index, expectation) in expectations.into_iter().enumerate() {
-        let sm_output = collect_sm_output(receiver);
-        if expectation != sm_output {
-            panic!(
-                "Expectation index {},  expected : {},  got: {}",
-                index, expectation, sm_output
-            )
-        }
-    }
-
-    // Check that we have expected all the output of the SM
-    match receiver.try_recv().err() {
-        Some(TryRecvError::Empty) => { /* OK */ }
-        Some(TryRecvError::Disconnected) => {
-            panic!(" Sender is dead")
-        }
-        None => {
-            panic!("Too few expectations for the SM output")
-        }
-    }
-}
-
-fn test_evt_injection(
-    sm: &mut StateMachine<BasicStateMachine>,
-    receiver: &mut Receiver<String>,
-    evt: BasicEvt,
-    expectations: Vec<&str>,
-) {
-    sm.dispatch(&evt);
-    expect_output_series(receiver, expectations);
-}
-
-fn test_sm_init(
-    ism: InitStateMachine<BasicStateMachine>,
-    receiver: &mut Receiver<String>,
-    expectations: Vec<&str>,
-) -> StateMachine<BasicStateMachine> {
-    let sm = ism.init();
-    expect_output_series(receiver, expectations);
-    sm
-}
-
 #[test]
 fn hsm_test() {
     let (sender, mut receiver) = channel();
@@ -349,3 +297,132 @@ fn hsm_test() {
         ],
     );
 }
+
+#[test]
+fn active_states_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = test_sm_init(
+        ism,
+        &mut receiver,
+        vec!["TOP_INIT", "S1-ENTRY", "S1-INIT", "S11-ENTRY"],
+    );
+    assert!(sm.is_in::<S11>());
+    assert!(sm.is_in::<S1>());
+    assert!(!sm.is_in::<S12>());
+    assert!(!sm.is_in::<S121>());
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        BasicEvt::A,
+        vec!["S11-HANDLES-A", "S11-EXIT", "S12-ENTRY", "S121-ENTRY"],
+    );
+    assert!(sm.is_in::<S121>());
+    assert!(sm.is_in::<S12>());
+    assert!(sm.is_in::<S1>());
+    assert!(!sm.is_in::<S11>());
+    assert!(!sm.is_in::<S122>());
+
+    let mut active_states = sm.active_states();
+    assert!(active_states.next().unwrap().is::<S121>());
+    assert!(active_states.next().unwrap().is::<S12>());
+    assert!(active_states.next().unwrap().is::<S1>());
+    assert!(active_states.next().is_none());
+}
+
+#[test]
+fn state_name_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = test_sm_init(
+        ism,
+        &mut receiver,
+        vec!["TOP_INIT", "S1-ENTRY", "S1-INIT", "S11-ENTRY"],
+    );
+    assert_eq!(sm.current_state_name(), "S11");
+    assert_eq!(<BasicStateMachine as State<S122>>::NAME, "S122");
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        BasicEvt::A,
+        vec!["S11-HANDLES-A", "S11-EXIT", "S12-ENTRY", "S121-ENTRY"],
+    );
+    assert_eq!(sm.current_state_name(), "S121");
+
+    let mut active_state_path = String::new();
+    sm.write_active_state_path(&mut active_state_path).unwrap();
+    assert_eq!(active_state_path, "S1/S12/S121");
+
+    let topmost_state = sm.active_states().last().unwrap();
+    assert_eq!(sm.state_name(topmost_state), "S1");
+}
+
+#[test]
+fn dispatch_outcome_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = test_sm_init(
+        ism,
+        &mut receiver,
+        vec!["TOP_INIT", "S1-ENTRY", "S1-INIT", "S11-ENTRY"],
+    );
+
+    assert!(matches!(
+        sm.dispatch(&BasicEvt::D),
+        DispatchOutcome::Ignored
+    ));
+    expect_output_series(&mut receiver, vec![]);
+
+    match sm.dispatch(&BasicEvt::B) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<S11>());
+            assert!(target.is::<S12>());
+        }
+        _ => panic!("Expected a transition"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "S11-HANDLES-B",
+            "S11-EXIT",
+            "S12-ENTRY",
+            "S12-INIT",
+            "S121-ENTRY",
+        ],
+    );
+
+    match sm.dispatch(&BasicEvt::A) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<S121>());
+            assert!(target.is::<S122>());
+        }
+        _ => panic!("Expected a transition"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec!["S121-HANDLES-A", "S121-EXIT", "S122-ENTRY"],
+    );
+
+    match sm.dispatch(&BasicEvt::B) {
+        DispatchOutcome::Handled { handled_by } => assert!(handled_by.is::<S122>()),
+        _ => panic!("Expected the event to be handled"),
+    }
+    expect_output_series(&mut receiver, vec!["S122-HANDLES-B"]);
+
+    match sm.dispatch(&BasicEvt::A) {
+        DispatchOutcome::Handled { handled_by } => assert!(handled_by.is::<S1>()),
+        _ => panic!("Expected the event to be handled"),
+    }
+    expect_output_series(&mut receiver, vec!["S1-HANDLES-A"]);
+}
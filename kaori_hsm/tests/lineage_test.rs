@@ -0,0 +1,212 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum BasicEvt {
+    A,
+    B,
+    C,
+}
+
+struct BasicStateMachine {
+    sender: Sender<String>,
+}
+
+impl BasicStateMachine {
+    pub fn new(sender: Sender<String>) -> BasicStateMachine {
+        BasicStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for BasicStateMachine {
+    type Evt = BasicEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(S1)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<S1> for BasicStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(S11)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("S1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S1-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+// State implemented without the `#[state()]` procedural macro, whose lineage is unknown
+struct S11 {}
+
+impl State<S11> for BasicStateMachine {
+    fn get_parent_state() -> ParentState<Self> {
+        ParentState::Exists(State::<S1>::state_fn())
+    }
+
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(S111)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("S11-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S11-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = S11)]
+impl State<S111> for BasicStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(S1111)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("S111-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S111-EXIT");
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::C => transition!(S111),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = S111)]
+impl State<S1111> for BasicStateMachine {
+    fn entry(&mut self) {
+        self.post_string("S1111-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S1111-EXIT");
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::A => transition!(S21),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<S2> for BasicStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(S21)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("S2-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S2-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = S2)]
+impl State<S21> for BasicStateMachine {
+    fn entry(&mut self) {
+        self.post_string("S21-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S21-EXIT");
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::B => transition!(S1111),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn lineage_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "TOP_INIT",
+            "S1-ENTRY",
+            "S11-ENTRY",
+            "S111-ENTRY",
+            "S1111-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&BasicEvt::A);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "S1111-EXIT",
+            "S111-EXIT",
+            "S11-EXIT",
+            "S1-EXIT",
+            "S2-ENTRY",
+            "S21-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&BasicEvt::B);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "S21-EXIT",
+            "S2-EXIT",
+            "S1-ENTRY",
+            "S11-ENTRY",
+            "S111-ENTRY",
+            "S1111-ENTRY",
+        ],
+    );
+
+    let mut path = String::new();
+    sm.write_active_state_path(&mut path).unwrap();
+    assert_eq!(path, "S1/Unnamed/S111/S1111");
+
+    sm.dispatch(&BasicEvt::C);
+    expect_output_series(
+        &mut receiver,
+        vec!["S1111-EXIT", "S111-EXIT", "S111-ENTRY", "S1111-ENTRY"],
+    );
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum ConveyorEvt {
+    Start,
+    Ready,
+    Pause,
+    Stop,
+}
+
+#[state_machine(states = [Idle, Operating, motor::Starting, motor::Running, motor::Idle as MotorIdle])]
+struct ConveyorStateMachine {
+    sender: Sender<String>,
+}
+
+impl ConveyorStateMachine {
+    pub fn new(sender: Sender<String>) -> ConveyorStateMachine {
+        ConveyorStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for ConveyorStateMachine {
+    type Evt = ConveyorEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Idle)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Idle> for ConveyorStateMachine {
+    fn entry(&mut self) {
+        self.post_string("IDLE-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("IDLE-EXIT");
+    }
+
+    fn handle(&mut self, evt: &ConveyorEvt) -> HandleResult<Self> {
+        match evt {
+            ConveyorEvt::Start => transition!(Operating),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Operating> for ConveyorStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(motor::Starting)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("OPERATING-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("OPERATING-EXIT");
+    }
+
+    fn handle(&mut self, evt: &ConveyorEvt) -> HandleResult<Self> {
+        match evt {
+            ConveyorEvt::Stop => transition!(Idle),
+            _ => ignored!(),
+        }
+    }
+}
+
+// States of the motor, declared in their own module
+mod motor {
+    use crate::{ConveyorEvt, ConveyorStateMachine};
+    use kaori_hsm::*;
+
+    #[state(super_state = crate::Operating, vis = pub(crate))]
+    impl State<Starting> for ConveyorStateMachine {
+        fn entry(&mut self) {
+            self.post_string("STARTING-ENTRY");
+        }
+
+        fn exit(&mut self) {
+            self.post_string("STARTING-EXIT");
+        }
+
+        fn handle(&mut self, evt: &ConveyorEvt) -> HandleResult<Self> {
+            match evt {
+                ConveyorEvt::Ready => transition!(Running),
+                _ => ignored!(),
+            }
+        }
+    }
+
+    #[state(super_state = crate::Operating, vis = pub(crate))]
+    impl State<Running> for ConveyorStateMachine {
+        fn entry(&mut self) {
+            self.post_string("RUNNING-ENTRY");
+        }
+
+        fn exit(&mut self) {
+            self.post_string("RUNNING-EXIT");
+        }
+
+        fn handle(&mut self, evt: &ConveyorEvt) -> HandleResult<Self> {
+            match evt {
+                ConveyorEvt::Start => transition!(super::Operating),
+                ConveyorEvt::Pause => transition!(Idle),
+                _ => ignored!(),
+            }
+        }
+    }
+
+    // Shares its name with the `Idle` state of the parent module
+    #[state(super_state = crate::Operating, vis = pub(crate))]
+    impl State<Idle> for ConveyorStateMachine {
+        fn entry(&mut self) {
+            self.post_string("MOTOR-IDLE-ENTRY");
+        }
+
+        fn exit(&mut self) {
+            self.post_string("MOTOR-IDLE-EXIT");
+        }
+
+        fn handle(&mut self, evt: &ConveyorEvt) -> HandleResult<Self> {
+            match evt {
+                ConveyorEvt::Start => transition!(Running),
+                _ => ignored!(),
+            }
+        }
+    }
+}
+
+#[test]
+fn modules_test() {
+    let (sender, mut receiver) = channel();
+
+    let conveyor_state_machine = ConveyorStateMachine::new(sender);
+    let ism = InitStateMachine::from(conveyor_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "IDLE-ENTRY"]);
+
+    sm.dispatch(&ConveyorEvt::Start);
+    expect_output_series(
+        &mut receiver,
+        vec!["IDLE-EXIT", "OPERATING-ENTRY", "STARTING-ENTRY"],
+    );
+    assert_eq!(
+        sm.current_state_id(),
+        Some(ConveyorStateMachineStateId::Starting)
+    );
+
+    sm.dispatch(&ConveyorEvt::Ready);
+    expect_output_series(&mut receiver, vec!["STARTING-EXIT", "RUNNING-ENTRY"]);
+    assert!(sm.is_in::<motor::Running>());
+    assert_eq!(
+        sm.current_state_id(),
+        Some(ConveyorStateMachineStateId::Running)
+    );
+
+    sm.dispatch(&ConveyorEvt::Start);
+    expect_output_series(&mut receiver, vec!["RUNNING-EXIT", "STARTING-ENTRY"]);
+
+    sm.dispatch(&ConveyorEvt::Ready);
+    expect_output_series(&mut receiver, vec!["STARTING-EXIT", "RUNNING-ENTRY"]);
+
+    // States of different modules sharing the same name are told apart
+    sm.dispatch(&ConveyorEvt::Pause);
+    expect_output_series(&mut receiver, vec!["RUNNING-EXIT", "MOTOR-IDLE-ENTRY"]);
+    assert!(sm.is_in::<motor::Idle>() && !sm.is_in::<Idle>());
+    assert_eq!(
+        sm.current_state_id(),
+        Some(ConveyorStateMachineStateId::MotorIdle)
+    );
+    assert_eq!(<&str>::from(ConveyorStateMachineStateId::MotorIdle), "Idle");
+
+    sm.dispatch(&ConveyorEvt::Stop);
+    expect_output_series(
+        &mut receiver,
+        vec!["MOTOR-IDLE-EXIT", "OPERATING-EXIT", "IDLE-ENTRY"],
+    );
+    assert_eq!(
+        sm.current_state_id(),
+        Some(ConveyorStateMachineStateId::Idle)
+    );
+    assert_eq!(<&str>::from(ConveyorStateMachineStateId::Idle), "Idle");
+}
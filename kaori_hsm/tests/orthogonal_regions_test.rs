@@ -0,0 +1,863 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum KeyboardEvt {
+    PowerOn,
+    PowerOff,
+    CapsLock,
+    NumLock,
+    Toggle,
+    Reset,
+    Unplug,
+    NumLockOnPowerOn,
+}
+
+struct KeyboardStateMachine {
+    sender: Sender<String>,
+    on_regions: Regions<Self, 2>,
+}
+
+impl KeyboardStateMachine {
+    pub fn new(sender: Sender<String>) -> KeyboardStateMachine {
+        KeyboardStateMachine {
+            sender,
+            on_regions: Regions::new(),
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for KeyboardStateMachine {
+    type Evt = KeyboardEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Off)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Off> for KeyboardStateMachine {
+    fn entry(&mut self) {
+        self.post_string("OFF-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("OFF-EXIT");
+    }
+
+    fn handle(&mut self, evt: &KeyboardEvt) -> HandleResult<Self> {
+        match evt {
+            KeyboardEvt::PowerOn => {
+                self.post_string("OFF-HANDLES-POWERON");
+                transition!(On)
+            }
+            KeyboardEvt::NumLockOnPowerOn => {
+                self.post_string("OFF-HANDLES-NUMLOCKONPOWERON");
+                transition!(NumOn)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top, regions = [Caps, Num] in on_regions)]
+impl State<On> for KeyboardStateMachine {
+    fn entry(&mut self) {
+        self.post_string("ON-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("ON-EXIT");
+    }
+
+    fn handle(&mut self, evt: &KeyboardEvt) -> HandleResult<Self> {
+        match evt {
+            KeyboardEvt::PowerOff => {
+                self.post_string("ON-HANDLES-POWEROFF");
+                transition!(Off)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = On)]
+impl State<Caps> for KeyboardStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("CAPS-INIT");
+        init_transition!(CapsOff)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("CAPS-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("CAPS-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &KeyboardEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Caps)]
+impl State<CapsOff> for KeyboardStateMachine {
+    fn entry(&mut self) {
+        self.post_string("CAPSOFF-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("CAPSOFF-EXIT");
+    }
+
+    fn handle(&mut self, evt: &KeyboardEvt) -> HandleResult<Self> {
+        match evt {
+            KeyboardEvt::CapsLock => {
+                self.post_string("CAPSOFF-HANDLES-CAPSLOCK");
+                transition!(CapsOn)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Caps)]
+impl State<CapsOn> for KeyboardStateMachine {
+    fn entry(&mut self) {
+        self.post_string("CAPSON-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("CAPSON-EXIT");
+    }
+
+    fn handle(&mut self, evt: &KeyboardEvt) -> HandleResult<Self> {
+        match evt {
+            KeyboardEvt::CapsLock => {
+                self.post_string("CAPSON-HANDLES-CAPSLOCK");
+                transition!(CapsOff)
+            }
+            KeyboardEvt::Toggle => {
+                self.post_string("CAPSON-HANDLES-TOGGLE");
+                handled!()
+            }
+            KeyboardEvt::Reset => {
+                self.post_string("CAPSON-HANDLES-RESET");
+                transition!(On)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = On)]
+impl State<Num> for KeyboardStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("NUM-INIT");
+        init_transition!(NumOff)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("NUM-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("NUM-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &KeyboardEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Num)]
+impl State<NumOff> for KeyboardStateMachine {
+    fn entry(&mut self) {
+        self.post_string("NUMOFF-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("NUMOFF-EXIT");
+    }
+
+    fn handle(&mut self, evt: &KeyboardEvt) -> HandleResult<Self> {
+        match evt {
+            KeyboardEvt::NumLock | KeyboardEvt::Toggle => {
+                self.post_string("NUMOFF-HANDLES-NUMLOCK");
+                transition!(NumOn)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Num)]
+impl State<NumOn> for KeyboardStateMachine {
+    fn entry(&mut self) {
+        self.post_string("NUMON-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("NUMON-EXIT");
+    }
+
+    fn handle(&mut self, evt: &KeyboardEvt) -> HandleResult<Self> {
+        match evt {
+            KeyboardEvt::NumLock => {
+                self.post_string("NUMON-HANDLES-NUMLOCK");
+                transition!(NumOff)
+            }
+            KeyboardEvt::Unplug => {
+                self.post_string("NUMON-HANDLES-UNPLUG");
+                transition!(Off)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn orthogonal_regions_test() {
+    let (sender, mut receiver) = channel();
+
+    let keyboard_state_machine = KeyboardStateMachine::new(sender);
+    let ism = InitStateMachine::from(keyboard_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "OFF-ENTRY"]);
+
+    // Entering the orthogonal state enters each of its regions in turn
+    sm.dispatch(&KeyboardEvt::PowerOn);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "OFF-HANDLES-POWERON",
+            "OFF-EXIT",
+            "ON-ENTRY",
+            "CAPS-ENTRY",
+            "CAPS-INIT",
+            "CAPSOFF-ENTRY",
+            "NUM-ENTRY",
+            "NUM-INIT",
+            "NUMOFF-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<On>() && sm.is_in::<Caps>() && sm.is_in::<CapsOff>());
+    assert!(sm.is_in::<Num>() && sm.is_in::<NumOff>());
+    assert!(!sm.is_in::<Off>() && !sm.is_in::<CapsOn>());
+    assert_eq!(sm.current_state_name(), "On");
+
+    // A transition inside a region does not affect the other region
+    sm.dispatch(&KeyboardEvt::CapsLock);
+    expect_output_series(
+        &mut receiver,
+        vec!["CAPSOFF-HANDLES-CAPSLOCK", "CAPSOFF-EXIT", "CAPSON-ENTRY"],
+    );
+    assert!(sm.is_in::<CapsOn>() && sm.is_in::<NumOff>());
+
+    // Every region receives the event
+    match sm.dispatch(&KeyboardEvt::Toggle) {
+        DispatchOutcome::Handled { handled_by } => assert!(handled_by.is::<CapsOn>()),
+        _ => panic!("Expected the event to be handled by the first region"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "CAPSON-HANDLES-TOGGLE",
+            "NUMOFF-HANDLES-NUMLOCK",
+            "NUMOFF-EXIT",
+            "NUMON-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<CapsOn>() && sm.is_in::<NumOn>());
+
+    sm.dispatch(&KeyboardEvt::NumLock);
+    expect_output_series(
+        &mut receiver,
+        vec!["NUMON-HANDLES-NUMLOCK", "NUMON-EXIT", "NUMOFF-ENTRY"],
+    );
+    sm.dispatch(&KeyboardEvt::NumLock);
+    expect_output_series(
+        &mut receiver,
+        vec!["NUMOFF-HANDLES-NUMLOCK", "NUMOFF-EXIT", "NUMON-ENTRY"],
+    );
+    assert!(sm.is_in::<CapsOn>() && sm.is_in::<NumOn>());
+
+    // A transition from a region to its orthogonal state exits and re-enters every region, but
+    // not the orthogonal state itself
+    match sm.dispatch(&KeyboardEvt::Reset) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<CapsOn>());
+            assert!(target.is::<On>());
+        }
+        _ => panic!("Expected a transition"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "CAPSON-HANDLES-RESET",
+            "CAPSON-EXIT",
+            "CAPS-EXIT",
+            "NUMON-EXIT",
+            "NUM-EXIT",
+            "CAPS-ENTRY",
+            "CAPS-INIT",
+            "CAPSOFF-ENTRY",
+            "NUM-ENTRY",
+            "NUM-INIT",
+            "NUMOFF-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<CapsOff>() && sm.is_in::<NumOff>());
+
+    // Events ignored by every region reach the orthogonal state
+    sm.dispatch(&KeyboardEvt::PowerOff);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "ON-HANDLES-POWEROFF",
+            "CAPSOFF-EXIT",
+            "CAPS-EXIT",
+            "NUMOFF-EXIT",
+            "NUM-EXIT",
+            "ON-EXIT",
+            "OFF-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Off>() && !sm.is_in::<On>());
+
+    // Targeting a state inside a region enters the other regions through their initial
+    // transitions
+    sm.dispatch(&KeyboardEvt::NumLockOnPowerOn);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "OFF-HANDLES-NUMLOCKONPOWERON",
+            "OFF-EXIT",
+            "ON-ENTRY",
+            "CAPS-ENTRY",
+            "CAPS-INIT",
+            "CAPSOFF-ENTRY",
+            "NUM-ENTRY",
+            "NUMON-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<CapsOff>() && sm.is_in::<NumOn>());
+
+    // A transition leaving the orthogonal state from a region exits every region
+    sm.dispatch(&KeyboardEvt::Unplug);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "NUMON-HANDLES-UNPLUG",
+            "NUMON-EXIT",
+            "NUM-EXIT",
+            "CAPSOFF-EXIT",
+            "CAPS-EXIT",
+            "ON-EXIT",
+            "OFF-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Off>());
+
+    assert!(matches!(
+        sm.dispatch(&KeyboardEvt::CapsLock),
+        DispatchOutcome::Ignored
+    ));
+    expect_output_series(&mut receiver, vec![]);
+}
+
+#[derive(Debug)]
+enum RestoreEvt {
+    B,
+    Leave,
+    Escape,
+    Back,
+}
+
+struct RestoreStateMachine {
+    sender: Sender<String>,
+    s1_history: History<Self>,
+    s11_regions: Regions<Self, 2>,
+}
+
+impl RestoreStateMachine {
+    pub fn new(sender: Sender<String>) -> RestoreStateMachine {
+        RestoreStateMachine {
+            sender,
+            s1_history: History::new(),
+            s11_regions: Regions::new(),
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for RestoreStateMachine {
+    type Evt = RestoreEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(S1)
+    }
+}
+
+#[state(super_state = Top, deep_history = s1_history)]
+impl State<S1> for RestoreStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(S11)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("S1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S1-EXIT");
+    }
+
+    fn handle(&mut self, evt: &RestoreEvt) -> HandleResult<Self> {
+        match evt {
+            RestoreEvt::Leave => transition!(S2),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = S1, regions = [RA, RB] in s11_regions)]
+impl State<S11> for RestoreStateMachine {
+    fn entry(&mut self) {
+        self.post_string("S11-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S11-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &RestoreEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = S11)]
+impl State<RA> for RestoreStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(RA1)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("RA-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("RA-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &RestoreEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = RA)]
+impl State<RA1> for RestoreStateMachine {
+    fn entry(&mut self) {
+        self.post_string("RA1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("RA1-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &RestoreEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = S11)]
+impl State<RB> for RestoreStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(RB1)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("RB-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("RB-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &RestoreEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = RB)]
+impl State<RB1> for RestoreStateMachine {
+    fn entry(&mut self) {
+        self.post_string("RB1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("RB1-EXIT");
+    }
+
+    fn handle(&mut self, evt: &RestoreEvt) -> HandleResult<Self> {
+        match evt {
+            RestoreEvt::B => transition!(RB2),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = RB)]
+impl State<RB2> for RestoreStateMachine {
+    fn entry(&mut self) {
+        self.post_string("RB2-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("RB2-EXIT");
+    }
+
+    fn handle(&mut self, evt: &RestoreEvt) -> HandleResult<Self> {
+        match evt {
+            RestoreEvt::Escape => transition!(S2),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<S2> for RestoreStateMachine {
+    fn entry(&mut self) {
+        self.post_string("S2-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("S2-EXIT");
+    }
+
+    fn handle(&mut self, evt: &RestoreEvt) -> HandleResult<Self> {
+        match evt {
+            RestoreEvt::Back => transition_history!(S1),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn deep_history_regions_test() {
+    let (sender, mut receiver) = channel();
+
+    let ism = InitStateMachine::from(RestoreStateMachine::new(sender));
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "S1-ENTRY",
+            "S11-ENTRY",
+            "RA-ENTRY",
+            "RA1-ENTRY",
+            "RB-ENTRY",
+            "RB1-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&RestoreEvt::B);
+    expect_output_series(&mut receiver, vec!["RB1-EXIT", "RB2-ENTRY"]);
+
+    sm.dispatch(&RestoreEvt::Leave);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "RA1-EXIT", "RA-EXIT", "RB2-EXIT", "RB-EXIT", "S11-EXIT", "S1-EXIT", "S2-ENTRY",
+        ],
+    );
+
+    // The deep history of S1 restores the state each region of S11 was left in
+    sm.dispatch(&RestoreEvt::Back);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "S2-EXIT",
+            "S1-ENTRY",
+            "S11-ENTRY",
+            "RA-ENTRY",
+            "RA1-ENTRY",
+            "RB-ENTRY",
+            "RB2-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<RA1>() && sm.is_in::<RB2>());
+    assert!(!sm.is_in::<RB1>());
+
+    // The regions are also recorded when left by a transition escaping one of them
+    sm.dispatch(&RestoreEvt::Escape);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "RB2-EXIT", "RB-EXIT", "RA1-EXIT", "RA-EXIT", "S11-EXIT", "S1-EXIT", "S2-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&RestoreEvt::Back);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "S2-EXIT",
+            "S1-ENTRY",
+            "S11-ENTRY",
+            "RA-ENTRY",
+            "RA1-ENTRY",
+            "RB-ENTRY",
+            "RB2-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<RA1>() && sm.is_in::<RB2>());
+}
+
+#[derive(Debug)]
+enum NestedEvt {
+    Leave,
+    Back,
+}
+
+struct NestedStateMachine {
+    sender: Sender<String>,
+    outer_regions: Regions<Self, 2>,
+    inner_regions: Regions<Self, 2>,
+}
+
+impl NestedStateMachine {
+    pub fn new(sender: Sender<String>) -> NestedStateMachine {
+        NestedStateMachine {
+            sender,
+            outer_regions: Regions::new(),
+            inner_regions: Regions::new(),
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for NestedStateMachine {
+    type Evt = NestedEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Outer)
+    }
+}
+
+#[state(super_state = Top, regions = [OuterA, OuterB] in outer_regions)]
+impl State<Outer> for NestedStateMachine {
+    fn entry(&mut self) {
+        self.post_string("OUTER-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("OUTER-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Outer)]
+impl State<OuterA> for NestedStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Inner)
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = OuterA, regions = [InnerA, InnerB] in inner_regions)]
+impl State<Inner> for NestedStateMachine {
+    fn entry(&mut self) {
+        self.post_string("INNER-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("INNER-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Inner)]
+impl State<InnerA> for NestedStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(InnerA1)
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = InnerA)]
+impl State<InnerA1> for NestedStateMachine {
+    fn entry(&mut self) {
+        self.post_string("INNERA1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("INNERA1-EXIT");
+    }
+
+    fn handle(&mut self, evt: &NestedEvt) -> HandleResult<Self> {
+        match evt {
+            NestedEvt::Leave => transition!(Away),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Inner)]
+impl State<InnerB> for NestedStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(InnerB1)
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = InnerB)]
+impl State<InnerB1> for NestedStateMachine {
+    fn entry(&mut self) {
+        self.post_string("INNERB1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("INNERB1-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Outer)]
+impl State<OuterB> for NestedStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(OuterB1)
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = OuterB)]
+impl State<OuterB1> for NestedStateMachine {
+    fn entry(&mut self) {
+        self.post_string("OUTERB1-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("OUTERB1-EXIT");
+    }
+
+    fn handle(&mut self, _evt: &NestedEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Away> for NestedStateMachine {
+    fn entry(&mut self) {
+        self.post_string("AWAY-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("AWAY-EXIT");
+    }
+
+    fn handle(&mut self, evt: &NestedEvt) -> HandleResult<Self> {
+        match evt {
+            NestedEvt::Back => transition!(InnerB1),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn nested_regions_test() {
+    let (sender, mut receiver) = channel();
+
+    let ism = InitStateMachine::from(NestedStateMachine::new(sender));
+
+    let mut sm = test_sm_init(
+        ism,
+        &mut receiver,
+        vec![
+            "OUTER-ENTRY",
+            "INNER-ENTRY",
+            "INNERA1-ENTRY",
+            "INNERB1-ENTRY",
+            "OUTERB1-ENTRY",
+        ],
+    );
+
+    // The states of the regions of an orthogonal state nested into a region are active
+    assert!(sm.is_in::<Outer>() && sm.is_in::<OuterA>() && sm.is_in::<Inner>());
+    assert!(sm.is_in::<InnerA1>() && sm.is_in::<InnerB1>() && sm.is_in::<OuterB1>());
+    assert!(!sm.is_in::<Away>());
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        NestedEvt::Leave,
+        vec![
+            "INNERA1-EXIT",
+            "INNERB1-EXIT",
+            "INNER-EXIT",
+            "OUTERB1-EXIT",
+            "OUTER-EXIT",
+            "AWAY-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Away>());
+    assert!(!sm.is_in::<Outer>() && !sm.is_in::<Inner>());
+    assert!(!sm.is_in::<InnerA1>() && !sm.is_in::<InnerB1>() && !sm.is_in::<OuterB1>());
+
+    test_evt_injection(
+        &mut sm,
+        &mut receiver,
+        NestedEvt::Back,
+        vec![
+            "AWAY-EXIT",
+            "OUTER-ENTRY",
+            "INNER-ENTRY",
+            "INNERA1-ENTRY",
+            "INNERB1-ENTRY",
+            "OUTERB1-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<InnerA1>() && sm.is_in::<InnerB1>() && sm.is_in::<OuterB1>());
+
+    let mut path = String::new();
+    sm.write_active_state_path(&mut path).unwrap();
+    assert_eq!(path, "Outer");
+}
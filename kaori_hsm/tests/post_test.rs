@@ -0,0 +1,195 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum ProcessingEvt {
+    Start,
+    Step(u8),
+    Done,
+    Ready,
+    Flood,
+    Stash,
+}
+
+struct ProcessingStateMachine {
+    sender: Sender<String>,
+    poster: Poster<Self, 2>,
+    stashed_poster: Option<Poster<Self, 2>>,
+}
+
+impl ProcessingStateMachine {
+    pub fn new(sender: Sender<String>) -> ProcessingStateMachine {
+        ProcessingStateMachine {
+            sender,
+            poster: Poster::new(),
+            stashed_poster: None,
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for ProcessingStateMachine {
+    type Evt = ProcessingEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Idle)
+    }
+
+    fn poster(&mut self) -> Option<&mut dyn PostQueue<ProcessingEvt>> {
+        Some(&mut self.poster)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Idle> for ProcessingStateMachine {
+    fn entry(&mut self) {
+        self.post_string("IDLE-ENTRY");
+        self.poster.post(ProcessingEvt::Ready).unwrap();
+    }
+
+    fn exit(&mut self) {
+        self.post_string("IDLE-EXIT");
+    }
+
+    fn handle(&mut self, evt: &ProcessingEvt) -> HandleResult<Self> {
+        match evt {
+            ProcessingEvt::Start => {
+                self.post_string("IDLE-HANDLES-START");
+                transition!(Processing)
+            }
+            ProcessingEvt::Ready => {
+                self.post_string("IDLE-HANDLES-READY");
+                handled!()
+            }
+            ProcessingEvt::Flood => {
+                self.post_string("IDLE-HANDLES-FLOOD");
+                for _ in 0..3 {
+                    if self.poster.post(ProcessingEvt::Ready).is_err() {
+                        self.post_string("IDLE-POST-REJECTED");
+                    }
+                }
+                handled!()
+            }
+            ProcessingEvt::Stash => {
+                self.post_string("IDLE-HANDLES-STASH");
+                // The stashed poster has been enabled by the state machine, but has been moved
+                let stashed_poster = core::mem::replace(&mut self.poster, Poster::new());
+                let stashed_poster = self.stashed_poster.insert(stashed_poster);
+                if stashed_poster.post(ProcessingEvt::Ready).is_err() {
+                    self.post_string("IDLE-STASHED-POST-REJECTED");
+                }
+                handled!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Processing> for ProcessingStateMachine {
+    fn entry(&mut self) {
+        self.post_string("PROCESSING-ENTRY");
+        self.poster.post(ProcessingEvt::Step(1)).unwrap();
+    }
+
+    fn exit(&mut self) {
+        self.post_string("PROCESSING-EXIT");
+    }
+
+    fn handle(&mut self, evt: &ProcessingEvt) -> HandleResult<Self> {
+        match evt {
+            ProcessingEvt::Step(step) => {
+                self.post_string(&format!("PROCESSING-HANDLES-STEP-{}", step));
+                let next_evt = if *step < 3 {
+                    ProcessingEvt::Step(step + 1)
+                } else {
+                    ProcessingEvt::Done
+                };
+                self.poster.post(next_evt).unwrap();
+                handled!()
+            }
+            ProcessingEvt::Done => {
+                self.post_string("PROCESSING-HANDLES-DONE");
+                transition!(Idle)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn post_test() {
+    let (sender, mut receiver) = channel();
+
+    let processing_state_machine = ProcessingStateMachine::new(sender);
+    let ism = InitStateMachine::from(processing_state_machine);
+
+    // The events posted during the initialization are processed before it completes
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec!["TOP_INIT", "IDLE-ENTRY", "IDLE-HANDLES-READY"],
+    );
+
+    // The posted events are processed one after the other before dispatch() returns
+    match sm.dispatch(&ProcessingEvt::Start) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Idle>());
+            assert!(target.is::<Processing>());
+        }
+        _ => panic!("Expected a transition"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "IDLE-HANDLES-START",
+            "IDLE-EXIT",
+            "PROCESSING-ENTRY",
+            "PROCESSING-HANDLES-STEP-1",
+            "PROCESSING-HANDLES-STEP-2",
+            "PROCESSING-HANDLES-STEP-3",
+            "PROCESSING-HANDLES-DONE",
+            "PROCESSING-EXIT",
+            "IDLE-ENTRY",
+            "IDLE-HANDLES-READY",
+        ],
+    );
+    assert!(sm.is_in::<Idle>());
+
+    // Events posted while the post queue is full are given back
+    sm.dispatch(&ProcessingEvt::Flood);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "IDLE-HANDLES-FLOOD",
+            "IDLE-POST-REJECTED",
+            "IDLE-HANDLES-READY",
+            "IDLE-HANDLES-READY",
+        ],
+    );
+
+    // A poster moved out of the state machine does not accept events, neither during the
+    // dispatch nor after it
+    sm.dispatch(&ProcessingEvt::Stash);
+    expect_output_series(
+        &mut receiver,
+        vec!["IDLE-HANDLES-STASH", "IDLE-STASHED-POST-REJECTED"],
+    );
+    sm.dispatch(&ProcessingEvt::Flood);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "IDLE-HANDLES-FLOOD",
+            "IDLE-POST-REJECTED",
+            "IDLE-HANDLES-READY",
+            "IDLE-HANDLES-READY",
+        ],
+    );
+}
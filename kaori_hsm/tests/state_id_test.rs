@@ -0,0 +1,129 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum PumpEvt {
+    Start,
+    Prime,
+    Stop,
+    Shutdown,
+}
+
+#[state_machine(states = [Stopped, Running, Priming, Pumping], state_id = PumpStateId)]
+struct PumpStateMachine {
+    sender: Sender<String>,
+}
+
+impl PumpStateMachine {
+    pub fn new(sender: Sender<String>) -> PumpStateMachine {
+        PumpStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for PumpStateMachine {
+    type Evt = PumpEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Stopped)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Stopped> for PumpStateMachine {
+    fn entry(&mut self) {
+        self.post_string("STOPPED-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &PumpEvt) -> HandleResult<Self> {
+        match evt {
+            PumpEvt::Start => transition!(Running),
+            PumpEvt::Shutdown => terminate!(),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Running> for PumpStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Pumping)
+    }
+
+    fn handle(&mut self, evt: &PumpEvt) -> HandleResult<Self> {
+        match evt {
+            PumpEvt::Stop => transition!(Stopped),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Running)]
+impl State<Priming> for PumpStateMachine {
+    fn handle(&mut self, _evt: &PumpEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Running)]
+impl State<Pumping> for PumpStateMachine {
+    fn handle(&mut self, evt: &PumpEvt) -> HandleResult<Self> {
+        match evt {
+            PumpEvt::Prime => transition!(Priming),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn state_id_test() {
+    let (sender, mut receiver) = channel();
+
+    let pump_state_machine = PumpStateMachine::new(sender);
+    let ism = InitStateMachine::from(pump_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "STOPPED-ENTRY"]);
+    assert_eq!(sm.current_state_id(), Some(PumpStateId::Stopped));
+
+    sm.dispatch(&PumpEvt::Start);
+    assert_eq!(sm.current_state_id(), Some(PumpStateId::Pumping));
+
+    let active_state_ids: Vec<PumpStateId> =
+        sm.active_states().filter_map(|state| state.id()).collect();
+    assert_eq!(
+        active_state_ids,
+        vec![PumpStateId::Pumping, PumpStateId::Running]
+    );
+
+    match sm.dispatch(&PumpEvt::Prime) {
+        DispatchOutcome::Transition { source, target } => {
+            assert_eq!(source.id(), Some(PumpStateId::Pumping));
+            assert_eq!(target.id(), Some(PumpStateId::Priming));
+        }
+        _ => panic!("Transition expected"),
+    }
+    assert_eq!(<&str>::from(PumpStateId::Priming), "Priming");
+    assert_eq!(
+        <&str>::from(sm.current_state_id().unwrap()),
+        sm.current_state_name()
+    );
+
+    match sm.dispatch(&PumpEvt::Stop) {
+        DispatchOutcome::Transition { source, .. } => {
+            assert_eq!(source.id(), Some(PumpStateId::Running));
+        }
+        _ => panic!("Transition expected"),
+    }
+    expect_output_series(&mut receiver, vec!["STOPPED-ENTRY"]);
+
+    sm.dispatch(&PumpEvt::Shutdown);
+    assert_eq!(sm.current_state_id(), None);
+}
@@ -0,0 +1,165 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum DeviceEvt {
+    Restart,
+    Work,
+    Abort,
+}
+
+#[state_machine(states = [Operating, Idle, Busy])]
+struct DeviceStateMachine {
+    sender: Sender<String>,
+}
+
+impl DeviceStateMachine {
+    pub fn new(sender: Sender<String>) -> DeviceStateMachine {
+        DeviceStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for DeviceStateMachine {
+    type Evt = DeviceEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Operating)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Operating> for DeviceStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("OPERATING-INIT");
+        init_transition!(Idle)
+    }
+
+    fn entry(&mut self) {
+        self.post_string("OPERATING-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("OPERATING-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DeviceEvt) -> HandleResult<Self> {
+        match evt {
+            DeviceEvt::Restart => transition!(Operating),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Operating)]
+impl State<Idle> for DeviceStateMachine {
+    fn entry(&mut self) {
+        self.post_string("IDLE-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("IDLE-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DeviceEvt) -> HandleResult<Self> {
+        match evt {
+            DeviceEvt::Work => transition!(Busy),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Operating)]
+impl State<Busy> for DeviceStateMachine {
+    fn entry(&mut self) {
+        self.post_string("BUSY-ENTRY");
+    }
+
+    fn exit(&mut self) {
+        self.post_string("BUSY-EXIT");
+    }
+
+    fn handle(&mut self, evt: &DeviceEvt) -> HandleResult<Self> {
+        match evt {
+            DeviceEvt::Abort => transition!(Operating),
+            _ => ignored!(),
+        }
+    }
+}
+
+#[test]
+fn state_uid_test() {
+    let (sender, mut receiver) = channel();
+
+    let device_state_machine = DeviceStateMachine::new(sender);
+    let ism = InitStateMachine::from(device_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "TOP_INIT",
+            "OPERATING-ENTRY",
+            "OPERATING-INIT",
+            "IDLE-ENTRY",
+        ],
+    );
+
+    // References to the same state are equal
+    let operating = sm.active_states().last().unwrap();
+    assert!(operating.is::<Operating>());
+    assert!(operating == sm.active_states().last().unwrap());
+    assert_eq!(operating.id(), Some(DeviceStateMachineStateId::Operating));
+
+    match sm.dispatch(&DeviceEvt::Work) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Idle>());
+            assert!(target.is::<Busy>());
+            assert!(source != target);
+            assert!(target == sm.active_states().next().unwrap());
+            assert_eq!(source.id(), Some(DeviceStateMachineStateId::Idle));
+            assert_eq!(target.id(), Some(DeviceStateMachineStateId::Busy));
+        }
+        _ => panic!("Transition expected"),
+    }
+    expect_output_series(&mut receiver, vec!["IDLE-EXIT", "BUSY-ENTRY"]);
+    assert!(sm.is_in::<Busy>() && sm.is_in::<Operating>());
+    assert_eq!(sm.current_state_name(), "Busy");
+    assert!(operating == sm.active_states().last().unwrap());
+
+    // The target is recognized as the LCA, which is not exited
+    sm.dispatch(&DeviceEvt::Abort);
+    expect_output_series(
+        &mut receiver,
+        vec!["BUSY-EXIT", "OPERATING-INIT", "IDLE-ENTRY"],
+    );
+
+    // The target is recognized as the handling state, making it a self-transition
+    match sm.dispatch(&DeviceEvt::Restart) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is::<Operating>());
+            assert!(target.is::<Operating>());
+            assert!(source == target);
+            assert_eq!(target.id(), Some(DeviceStateMachineStateId::Operating));
+        }
+        _ => panic!("Transition expected"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "IDLE-EXIT",
+            "OPERATING-EXIT",
+            "OPERATING-ENTRY",
+            "OPERATING-INIT",
+            "IDLE-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Idle>());
+}
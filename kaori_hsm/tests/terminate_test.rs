@@ -0,0 +1,317 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug, Clone)]
+enum HandshakeEvt {
+    Hello,
+    Data,
+    Close,
+    Abort,
+}
+
+struct HandshakeStateMachine {
+    sender: Sender<String>,
+}
+
+impl HandshakeStateMachine {
+    pub fn new(sender: Sender<String>) -> HandshakeStateMachine {
+        HandshakeStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for HandshakeStateMachine {
+    type Evt = HandshakeEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Connecting)
+    }
+
+    fn on_unhandled(&mut self, evt: &HandshakeEvt) -> HandleResult<Self> {
+        match evt {
+            HandshakeEvt::Abort => {
+                self.post_string("TOP-HANDLES-ABORT");
+                terminate!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Connecting> for HandshakeStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("CONNECTING-INIT");
+        init_transition!(WaitingHello)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("CONNECTING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("CONNECTING-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &HandshakeEvt) -> HandleResult<Self> {
+        match evt {
+            HandshakeEvt::Data => {
+                self.post_string("CONNECTING-DEFERS-DATA");
+                deferred!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Connecting)]
+impl State<WaitingHello> for HandshakeStateMachine {
+    fn exit(&mut self) {
+        self.post_string("WAITING_HELLO-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("WAITING_HELLO-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &HandshakeEvt) -> HandleResult<Self> {
+        match evt {
+            HandshakeEvt::Hello => {
+                self.post_string("WAITING_HELLO-HANDLES-HELLO");
+                transition!(WaitingAck)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Connecting)]
+impl State<WaitingAck> for HandshakeStateMachine {
+    fn exit(&mut self) {
+        self.post_string("WAITING_ACK-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("WAITING_ACK-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &HandshakeEvt) -> HandleResult<Self> {
+        match evt {
+            HandshakeEvt::Close => {
+                self.post_string("WAITING_ACK-HANDLES-CLOSE");
+                terminate!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+// Orthogonal state machine whose regions may terminate it
+struct UpdateStateMachine {
+    sender: Sender<String>,
+    updating_regions: Regions<Self, 2>,
+}
+
+impl UpdateStateMachine {
+    pub fn new(sender: Sender<String>) -> UpdateStateMachine {
+        UpdateStateMachine {
+            sender,
+            updating_regions: Regions::new(),
+        }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+}
+
+impl TopState for UpdateStateMachine {
+    type Evt = HandshakeEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(Updating)
+    }
+}
+
+#[state(super_state = Top, regions = [Download, Watchdog] in updating_regions)]
+impl State<Updating> for UpdateStateMachine {
+    fn exit(&mut self) {
+        self.post_string("UPDATING-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("UPDATING-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &HandshakeEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Updating)]
+impl State<Download> for UpdateStateMachine {
+    fn exit(&mut self) {
+        self.post_string("DOWNLOAD-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("DOWNLOAD-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &HandshakeEvt) -> HandleResult<Self> {
+        match evt {
+            HandshakeEvt::Close => {
+                self.post_string("DOWNLOAD-HANDLES-CLOSE");
+                terminate!()
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Updating)]
+impl State<Watchdog> for UpdateStateMachine {
+    fn exit(&mut self) {
+        self.post_string("WATCHDOG-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("WATCHDOG-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &HandshakeEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[test]
+fn terminate_test() {
+    let (sender, mut receiver) = channel();
+
+    let handshake_state_machine = HandshakeStateMachine::new(sender);
+    let ism = InitStateMachine::from(handshake_state_machine);
+
+    let mut sm = ism.init_with_defer_queue::<1>();
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "TOP_INIT",
+            "CONNECTING-ENTRY",
+            "CONNECTING-INIT",
+            "WAITING_HELLO-ENTRY",
+        ],
+    );
+    assert!(!sm.is_terminated());
+
+    sm.dispatch(&HandshakeEvt::Hello);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "WAITING_HELLO-HANDLES-HELLO",
+            "WAITING_HELLO-EXIT",
+            "WAITING_ACK-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&HandshakeEvt::Data);
+    expect_output_series(&mut receiver, vec!["CONNECTING-DEFERS-DATA"]);
+    assert_eq!(sm.deferred_evt_count(), 1);
+
+    match sm.dispatch(&HandshakeEvt::Close) {
+        DispatchOutcome::Terminated { terminated_by } => {
+            assert!(terminated_by.is::<WaitingAck>());
+        }
+        _ => panic!("Expected the state machine to be terminated"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "WAITING_ACK-HANDLES-CLOSE",
+            "WAITING_ACK-EXIT",
+            "CONNECTING-EXIT",
+        ],
+    );
+    assert!(sm.is_terminated());
+    assert!(!sm.is_in::<Connecting>());
+    assert_eq!(sm.active_states().count(), 0);
+    // The deferred events are never recalled
+    assert_eq!(sm.deferred_evt_count(), 0);
+
+    // Events are dropped once the state machine is terminated, even those the top state handles
+    assert!(matches!(
+        sm.dispatch(&HandshakeEvt::Abort),
+        DispatchOutcome::Ignored
+    ));
+    expect_output_series(&mut receiver, vec![]);
+}
+
+#[test]
+fn terminate_from_top_state_test() {
+    let (sender, mut receiver) = channel();
+
+    let handshake_state_machine = HandshakeStateMachine::new(sender);
+    let ism = InitStateMachine::from(handshake_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "TOP_INIT",
+            "CONNECTING-ENTRY",
+            "CONNECTING-INIT",
+            "WAITING_HELLO-ENTRY",
+        ],
+    );
+
+    match sm.dispatch(&HandshakeEvt::Abort) {
+        DispatchOutcome::Terminated { terminated_by } => {
+            assert!(terminated_by.is_top());
+        }
+        _ => panic!("Expected the state machine to be terminated"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec!["TOP-HANDLES-ABORT", "WAITING_HELLO-EXIT", "CONNECTING-EXIT"],
+    );
+    assert!(sm.is_terminated());
+}
+
+#[test]
+fn terminate_from_region_test() {
+    let (sender, mut receiver) = channel();
+
+    let update_state_machine = UpdateStateMachine::new(sender);
+    let ism = InitStateMachine::from(update_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec!["UPDATING-ENTRY", "DOWNLOAD-ENTRY", "WATCHDOG-ENTRY"],
+    );
+
+    // Every region is exited before the orthogonal state
+    match sm.dispatch(&HandshakeEvt::Close) {
+        DispatchOutcome::Terminated { terminated_by } => {
+            assert!(terminated_by.is::<Download>());
+        }
+        _ => panic!("Expected the state machine to be terminated"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "DOWNLOAD-HANDLES-CLOSE",
+            "DOWNLOAD-EXIT",
+            "WATCHDOG-EXIT",
+            "UPDATING-EXIT",
+        ],
+    );
+    assert!(sm.is_terminated());
+    assert!(!sm.is_in::<Watchdog>());
+}
@@ -0,0 +1,175 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum BasicEvt {
+    Start,
+    Count,
+    Reset,
+    Unknown,
+}
+
+struct BasicStateMachine {
+    sender: Sender<String>,
+    unhandled_count: u32,
+}
+
+impl BasicStateMachine {
+    pub fn new(sender: Sender<String>) -> BasicStateMachine {
+        BasicStateMachine {
+            sender,
+            unhandled_count: 0,
+        }
+    }
+}
+
+impl TopState for BasicStateMachine {
+    type Evt = BasicEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.sender.send(String::from("TOP_INIT")).unwrap();
+        init_transition!(Idle)
+    }
+
+    fn on_unhandled(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::Count => {
+                self.unhandled_count += 1;
+                self.sender.send(String::from("TOP-HANDLES-COUNT")).unwrap();
+                handled!()
+            }
+            BasicEvt::Reset => {
+                self.sender.send(String::from("TOP-HANDLES-RESET")).unwrap();
+                transition!(Idle)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Idle> for BasicStateMachine {
+    fn exit(&mut self) {
+        self.sender.send(String::from("IDLE-EXIT")).unwrap();
+    }
+
+    fn entry(&mut self) {
+        self.sender.send(String::from("IDLE-ENTRY")).unwrap();
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::Start => {
+                self.sender
+                    .send(String::from("IDLE-HANDLES-START"))
+                    .unwrap();
+                transition!(Running)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Running> for BasicStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.sender.send(String::from("RUNNING-INIT")).unwrap();
+        init_transition!(Stepping)
+    }
+
+    fn exit(&mut self) {
+        self.sender.send(String::from("RUNNING-EXIT")).unwrap();
+    }
+
+    fn entry(&mut self) {
+        self.sender.send(String::from("RUNNING-ENTRY")).unwrap();
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Running)]
+impl State<Stepping> for BasicStateMachine {
+    fn exit(&mut self) {
+        self.sender.send(String::from("STEPPING-EXIT")).unwrap();
+    }
+
+    fn entry(&mut self) {
+        self.sender.send(String::from("STEPPING-ENTRY")).unwrap();
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[test]
+fn top_state_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(&mut receiver, vec!["TOP_INIT", "IDLE-ENTRY"]);
+
+    match sm.dispatch(&BasicEvt::Count) {
+        DispatchOutcome::Handled { handled_by } => {
+            assert!(handled_by.is_top());
+            assert_eq!(sm.state_name(handled_by), "Top");
+        }
+        _ => panic!("Expected the event to be handled by the top state"),
+    }
+    expect_output_series(&mut receiver, vec!["TOP-HANDLES-COUNT"]);
+
+    assert!(matches!(
+        sm.dispatch(&BasicEvt::Unknown),
+        DispatchOutcome::Ignored
+    ));
+    expect_output_series(&mut receiver, vec![]);
+
+    sm.dispatch(&BasicEvt::Start);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "IDLE-HANDLES-START",
+            "IDLE-EXIT",
+            "RUNNING-ENTRY",
+            "RUNNING-INIT",
+            "STEPPING-ENTRY",
+        ],
+    );
+
+    match sm.dispatch(&BasicEvt::Reset) {
+        DispatchOutcome::Transition { source, target } => {
+            assert!(source.is_top());
+            assert!(target.is::<Idle>());
+        }
+        _ => panic!("Expected a transition from the top state"),
+    }
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "TOP-HANDLES-RESET",
+            "STEPPING-EXIT",
+            "RUNNING-EXIT",
+            "IDLE-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<Idle>());
+
+    // The top state may also transition to the state which is currently active
+    sm.dispatch(&BasicEvt::Reset);
+    expect_output_series(
+        &mut receiver,
+        vec!["TOP-HANDLES-RESET", "IDLE-EXIT", "IDLE-ENTRY"],
+    );
+
+    sm.dispatch(&BasicEvt::Count);
+    expect_output_series(&mut receiver, vec!["TOP-HANDLES-COUNT"]);
+}
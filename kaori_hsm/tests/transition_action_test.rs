@@ -0,0 +1,200 @@
+mod common;
+
+use common::*;
+use kaori_hsm::*;
+
+// Evt definition
+#[derive(Debug)]
+enum BasicEvt {
+    Cross,
+    Loop,
+    Pick,
+}
+
+struct BasicStateMachine {
+    sender: Sender<String>,
+}
+
+impl BasicStateMachine {
+    pub fn new(sender: Sender<String>) -> BasicStateMachine {
+        BasicStateMachine { sender }
+    }
+
+    fn post_string(&self, s: &str) {
+        self.sender.send(String::from(s)).unwrap();
+    }
+
+    fn on_cross(&mut self) {
+        self.post_string("ON-CROSS");
+    }
+
+    fn on_loop(&mut self) {
+        self.post_string("ON-LOOP");
+    }
+
+    fn on_pick(&mut self) {
+        self.post_string("ON-PICK");
+    }
+
+    fn on_chosen(&mut self) {
+        self.post_string("ON-CHOSEN");
+    }
+}
+
+impl TopState for BasicStateMachine {
+    type Evt = BasicEvt;
+
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("TOP_INIT");
+        init_transition!(Left)
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Left> for BasicStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        self.post_string("LEFT-INIT");
+        init_transition!(LeftLeaf)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("LEFT-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("LEFT-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Left)]
+impl State<LeftLeaf> for BasicStateMachine {
+    fn exit(&mut self) {
+        self.post_string("LEFT_LEAF-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("LEFT_LEAF-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::Cross => {
+                self.post_string("LEFT_LEAF-HANDLES-CROSS");
+                transition!(RightLeaf, action = Self::on_cross)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[state(super_state = Top)]
+impl State<Right> for BasicStateMachine {
+    fn init(&mut self) -> InitResult<Self> {
+        init_transition!(RightLeaf)
+    }
+
+    fn exit(&mut self) {
+        self.post_string("RIGHT-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("RIGHT-ENTRY");
+    }
+
+    fn handle(&mut self, _evt: &BasicEvt) -> HandleResult<Self> {
+        ignored!()
+    }
+}
+
+#[state(super_state = Right)]
+impl State<RightLeaf> for BasicStateMachine {
+    fn exit(&mut self) {
+        self.post_string("RIGHT_LEAF-EXIT");
+    }
+
+    fn entry(&mut self) {
+        self.post_string("RIGHT_LEAF-ENTRY");
+    }
+
+    fn handle(&mut self, evt: &BasicEvt) -> HandleResult<Self> {
+        match evt {
+            BasicEvt::Loop => {
+                self.post_string("RIGHT_LEAF-HANDLES-LOOP");
+                transition!(RightLeaf, action = Self::on_loop)
+            }
+            BasicEvt::Pick => {
+                self.post_string("RIGHT_LEAF-HANDLES-PICK");
+                transition!(SideChoice, action = Self::on_pick)
+            }
+            _ => ignored!(),
+        }
+    }
+}
+
+#[choice(super_state = Top, targets = [LeftLeaf])]
+impl Choice<SideChoice> for BasicStateMachine {
+    fn choose(&mut self) -> HandleResult<Self> {
+        self.post_string("SIDE_CHOICE");
+        transition!(LeftLeaf, action = Self::on_chosen)
+    }
+}
+
+#[test]
+fn transition_action_test() {
+    let (sender, mut receiver) = channel();
+
+    let basic_state_machine = BasicStateMachine::new(sender);
+    let ism = InitStateMachine::from(basic_state_machine);
+
+    let mut sm = ism.init();
+    expect_output_series(
+        &mut receiver,
+        vec!["TOP_INIT", "LEFT-ENTRY", "LEFT-INIT", "LEFT_LEAF-ENTRY"],
+    );
+
+    // The action is executed between the exit and the entry phases
+    sm.dispatch(&BasicEvt::Cross);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "LEFT_LEAF-HANDLES-CROSS",
+            "LEFT_LEAF-EXIT",
+            "LEFT-EXIT",
+            "ON-CROSS",
+            "RIGHT-ENTRY",
+            "RIGHT_LEAF-ENTRY",
+        ],
+    );
+
+    sm.dispatch(&BasicEvt::Loop);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "RIGHT_LEAF-HANDLES-LOOP",
+            "RIGHT_LEAF-EXIT",
+            "ON-LOOP",
+            "RIGHT_LEAF-ENTRY",
+        ],
+    );
+
+    // The action of a transition to a choice is executed before the guards are evaluated
+    sm.dispatch(&BasicEvt::Pick);
+    expect_output_series(
+        &mut receiver,
+        vec![
+            "RIGHT_LEAF-HANDLES-PICK",
+            "RIGHT_LEAF-EXIT",
+            "RIGHT-EXIT",
+            "ON-PICK",
+            "SIDE_CHOICE",
+            "ON-CHOSEN",
+            "LEFT-ENTRY",
+            "LEFT_LEAF-ENTRY",
+        ],
+    );
+    assert!(sm.is_in::<LeftLeaf>());
+}
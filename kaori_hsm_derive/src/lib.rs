@@ -1,13 +1,32 @@
-use proc_macro2::TokenStream;
-use quote::quote;
-use syn::{parse::Parse, Ident, ItemImpl, Token};
+use proc_macro2::{Literal, TokenStream, TokenTree};
+use quote::{format_ident, quote, quote_spanned, ToTokens};
+use syn::{
+    ext::IdentExt, parse::Parse, punctuated::Punctuated, spanned::Spanned, Ident, ItemImpl, Token,
+};
 /// Macro to call before every implementation of the `State<>` trait.
 /// Allow to decrease verbosity of the trait implementation.
 /// This is what the macro does:
 /// - Create an empty structure named after the tag sent as a generic parameter
-/// in the `State<>` trait implementation
+///   in the `State<>` trait implementation. The structure is private to the module unless a
+///   visibility is provided as the value of `vis`, as in `vis = pub(crate)`.
 /// - Implement the `State::get_parent_state()` method using the state tag of the parent provided
-/// as the value of `super_state`.
+///   as the value of `super_state`.
+/// - Define the `State::NAME` constant as the name of the tag.
+/// - If `shallow_history` or `deep_history` is provided, bind the `History` field it names to the
+///   state so that `transition_history!()` can target it.
+/// - If `regions = [RegionA, RegionB] in field_name` is provided, make the state an orthogonal
+///   state whose regions are rooted in the listed states, and whose active states are recorded in
+///   the `Regions` field named after `in`.
+/// - Validate the hierarchy at compile time: the state must not be its own ancestor, its parent
+///   state must implement `init()` or regions, and `init_transition!()` must target a substate.
+///   The hierarchy of a generic state machine structure is validated once the structure is
+///   instantiated.
+/// - Record the parent and the depth of the state in constant tables, from which the state
+///   machine ascends the hierarchy and computes the exit and entry paths of the transitions
+///   without calling `get_parent_state()`.
+/// - If the `try_handle()` method is defined instead of `handle()`, move it along with the
+///   optional `on_error()` method to an implementation of the `FallibleState<>` trait, and
+///   implement `State::handle()` so that the errors it returns are passed to `on_error()`.
 ///
 /// There are two use cases of the macro depending on the category of the parent state (see example
 /// below).
@@ -15,6 +34,17 @@ use syn::{parse::Parse, Ident, ItemImpl, Token};
 /// keyword to define the value of `super_state`.
 /// The second case being when the parent state is another user-defined state. In this case
 /// set your custom state as the name of `super_state`.
+///
+/// The states of a state machine may be spread across several modules. The tags of the states are
+/// then referred to by their path, as in `super_state = crate::motor::Running` or
+/// `transition!(motor::Running)`, and made visible to the other modules through `vis`. The tag of
+/// the implemented state must however be a single identifier, as in `impl State<Running>`, for
+/// the macro declares its structure in the module of the implementation.
+///
+/// The generated code refers to the library as `kaori_hsm`. If the library is renamed or
+/// re-exported by another crate, its path is provided as the value of `crate`, as in
+/// `crate = my_framework::hsm`. The same field is accepted by the `#[choice()]`,
+/// `#[final_state()]` and `#[state_machine()]` macros.
 /// ```rust,ignore
 ///# enum BasicEvt{A};
 ///# struct BasicStateMachine{}
@@ -53,7 +83,6 @@ use syn::{parse::Parse, Ident, ItemImpl, Token};
 ///    }
 /// }
 /// ```
-
 #[proc_macro_attribute]
 pub fn state(
     args: proc_macro::TokenStream,
@@ -67,87 +96,1393 @@ pub fn state(
 }
 
 struct AttrStateDecl {
-    super_state_tag: syn::Ident,
+    super_state_tag: syn::Path,
+    vis: syn::Visibility,
+    krate: Option<syn::Path>,
+    shallow_history_field: Option<syn::Ident>,
+    deep_history_field: Option<syn::Ident>,
+    regions: Option<AttrRegionsDecl>,
+}
+
+struct AttrRegionsDecl {
+    region_state_tags: Vec<syn::Path>,
+    regions_field: syn::Ident,
+}
+
+impl Parse for AttrRegionsDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let region_state_tags_input;
+        let bracket = syn::bracketed!(region_state_tags_input in input);
+        let region_state_tags: Vec<syn::Path> =
+            Punctuated::<syn::Path, Token![,]>::parse_terminated(&region_state_tags_input)?
+                .into_iter()
+                .collect();
+        if region_state_tags.is_empty() {
+            return Err(syn::Error::new(
+                bracket.span.join(),
+                "expected at least one region state",
+            ));
+        }
+        input.parse::<Token![in]>()?;
+        let regions_field = input.parse::<syn::Ident>()?;
+
+        Ok(AttrRegionsDecl {
+            region_state_tags,
+            regions_field,
+        })
+    }
 }
 
 impl Parse for AttrStateDecl {
     fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
-        let super_state_tag;
+        let mut super_state_tag: Option<syn::Path> = None;
+        let mut vis: Option<syn::Visibility> = None;
+        let mut krate: Option<syn::Path> = None;
+        let mut shallow_history_field: Option<syn::Ident> = None;
+        let mut deep_history_field: Option<syn::Ident> = None;
+        let mut regions: Option<AttrRegionsDecl> = None;
 
-        let attr_name = input.parse::<syn::Ident>()?;
+        while !input.is_empty() {
+            let attr_name = input.call(syn::Ident::parse_any)?;
+            input.parse::<Token![=]>()?;
 
-        match attr_name.to_string().as_str() {
-            "super_state" => {
-                input.parse::<Token![=]>()?;
-                super_state_tag = input.parse::<syn::Ident>()?;
-            }
-            _ => {
-                return Err(syn::Error::new(
+            match attr_name.to_string().as_str() {
+                "super_state" => {
+                    set_attr_field(&mut super_state_tag, &attr_name, input.parse()?)?;
+                }
+                "vis" => {
+                    set_attr_field(&mut vis, &attr_name, input.parse()?)?;
+                }
+                "crate" => {
+                    set_attr_field(&mut krate, &attr_name, input.parse()?)?;
+                }
+                "shallow_history" => {
+                    set_attr_field(&mut shallow_history_field, &attr_name, input.parse()?)?;
+                }
+                "deep_history" => {
+                    set_attr_field(&mut deep_history_field, &attr_name, input.parse()?)?;
+                }
+                "regions" => {
+                    set_attr_field(&mut regions, &attr_name, input.parse()?)?;
+                }
+                _ => return Err(syn::Error::new(
                     attr_name.span(),
-                    "expected field `super_state_name`",
-                ))
+                    format!("unknown field `{}`, expected `super_state`, `vis`, `crate`, `shallow_history`, `deep_history` or `regions`", attr_name),
+                )),
+            }
+
+            if input.is_empty() {
+                break;
             }
+            input.parse::<Token![,]>()?;
         }
 
-        Ok(AttrStateDecl { super_state_tag })
+        let super_state_tag = super_state_tag.ok_or_else(|| {
+            syn::Error::new(
+                input.span(),
+                "expected field `super_state`, naming the parent state or `Top`",
+            )
+        })?;
+
+        if let (Some(_), Some(deep_history_field)) = (&shallow_history_field, &deep_history_field) {
+            return Err(syn::Error::new(
+                deep_history_field.span(),
+                "a state cannot have both a shallow and a deep history",
+            ));
+        }
+
+        Ok(AttrStateDecl {
+            super_state_tag,
+            vis: vis.unwrap_or(syn::Visibility::Inherited),
+            krate,
+            shallow_history_field,
+            deep_history_field,
+            regions,
+        })
     }
 }
 
-fn get_user_state_tag_from_item_impl_ast(item_impl_ast: &ItemImpl) -> Ident {
-    let item_ast_trait = item_impl_ast.trait_.clone().unwrap().1;
+// Store the value of a field of a macro attribute, which may only be provided once
+fn set_attr_field<T>(field: &mut Option<T>, attr_name: &Ident, value: T) -> syn::Result<()> {
+    if field.is_some() {
+        return Err(syn::Error::new(
+            attr_name.span(),
+            format!("duplicate field `{}`", attr_name),
+        ));
+    }
+    *field = Some(value);
+    Ok(())
+}
 
-    for segment in item_ast_trait.segments {
-        if segment.ident.to_string() == "State" {
-            if let syn::PathArguments::AngleBracketed(generic_arguments) = segment.arguments {
-                let first_generic_argument = generic_arguments.args.first().unwrap();
-                if let syn::GenericArgument::Type(first_generic_argument) = first_generic_argument {
-                    if let syn::Type::Path(first_generic_argument) = first_generic_argument {
-                        let first_generic_argument_ident =
-                            first_generic_argument.path.segments[0].ident.clone();
-                        return first_generic_argument_ident;
-                    }
-                }
-            }
+fn get_user_state_tag_from_item_impl_ast(
+    item_impl_ast: &ItemImpl,
+    trait_name: &str,
+) -> syn::Result<Ident> {
+    let Some((_, item_ast_trait, _)) = &item_impl_ast.trait_ else {
+        return Err(syn::Error::new(
+            item_impl_ast.self_ty.span(),
+            format!(
+                "expected an implementation of the `{}<>` trait, as in `impl {}<Tag> for {}`",
+                trait_name,
+                trait_name,
+                item_impl_ast.self_ty.to_token_stream()
+            ),
+        ));
+    };
+
+    let segment = item_ast_trait.segments.last().unwrap();
+    if segment.ident != trait_name {
+        return Err(syn::Error::new(
+            segment.ident.span(),
+            format!(
+                "expected the `{}<>` trait, found `{}`",
+                trait_name, segment.ident
+            ),
+        ));
+    }
+
+    let missing_tag_error = || {
+        syn::Error::new(
+            segment.span(),
+            format!(
+                "expected a tag as generic argument of the `{}<>` trait, as in `{}<Tag>`",
+                trait_name, trait_name
+            ),
+        )
+    };
+    let syn::PathArguments::AngleBracketed(generic_arguments) = &segment.arguments else {
+        return Err(missing_tag_error());
+    };
+    match generic_arguments.args.first() {
+        Some(syn::GenericArgument::Type(syn::Type::Path(tag)))
+            if tag.qself.is_none() && generic_arguments.args.len() == 1 =>
+        {
+            // The tag is declared by the macro next to the implementation, so that a path to
+            // another module would name a structure which the macro does not declare
+            tag.path.get_ident().cloned().ok_or_else(|| {
+                syn::Error::new_spanned(&tag.path, "state tag must be a single identifier")
+            })
         }
+        _ => Err(missing_tag_error()),
     }
-    panic!()
+}
+
+// Path of the `kaori_hsm` crate in the generated code, unless overridden through `crate = path`
+fn get_default_crate_path() -> syn::Path {
+    syn::parse_quote!(kaori_hsm)
+}
+
+fn is_top_state_tag(state_tag: &syn::Path) -> bool {
+    state_tag.is_ident("Top")
+}
+
+// Write the path of a state tag as written by the user, for the compile-time error messages
+fn get_state_tag_string(state_tag: &syn::Path) -> String {
+    let segments = state_tag
+        .segments
+        .iter()
+        .map(|segment| segment.ident.to_string())
+        .collect::<Vec<_>>()
+        .join("::");
+    match state_tag.leading_colon {
+        Some(_) => format!("::{}", segments),
+        None => segments,
+    }
+}
+
+// The references to the super state are spanned at its tag, so that the compiler reports a super
+// state which is not a state of the state machine at the `super_state` field of the attribute
+fn get_super_state_fn(super_state_tag: &syn::Path, krate: &syn::Path) -> syn::ImplItemFn {
+    if is_top_state_tag(super_state_tag) {
+        syn::parse2(quote!(
+            fn get_parent_state() -> #krate::ParentState<Self> {
+                #krate::ParentState::TopReached
+            }
+        ))
+        .unwrap()
+    } else {
+        let super_state_fn = quote_spanned!(super_state_tag.span()=>
+            #krate::State::<#super_state_tag>::state_fn()
+        );
+        syn::parse2(quote!(
+            fn get_parent_state() -> #krate::ParentState<Self> {
+                #krate::ParentState::Exists(#super_state_fn)
+            }
+        ))
+        .unwrap()
+    }
+}
+
+// Record the table of the super state as a constant, through which the state machine ascends
+// the hierarchy
+fn get_parent_state_const(super_state_tag: &syn::Path, krate: &syn::Path) -> syn::ImplItemConst {
+    let parent_state_fn = if is_top_state_tag(super_state_tag) {
+        quote!(#krate::StateFn::top())
+    } else {
+        quote_spanned!(super_state_tag.span()=>
+            #krate::StateFn::of::<#super_state_tag>()
+        )
+    };
+    syn::parse2(quote!(
+        const PARENT_STATE_FN: Option<#krate::StateFn<Self>> = Some(#parent_state_fn);
+    ))
+    .unwrap()
 }
 
 pub(crate) fn state_impl(args: TokenStream, item: TokenStream) -> TokenStream {
+    try_state_impl(args, item).unwrap_or_else(syn::Error::into_compile_error)
+}
+
+fn try_state_impl(args: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    let attr_ast: AttrStateDecl = syn::parse2(args)?;
+    let item_ast: ItemImpl = syn::parse2(item)?;
+    expand_state(attr_ast, item_ast)
+}
+
+fn expand_state(attr_ast: AttrStateDecl, mut item_ast: ItemImpl) -> syn::Result<TokenStream> {
     // Get the tag of the super state
-    let attr_ast: AttrStateDecl = syn::parse2(args).unwrap();
-    let super_state_tag_ident = attr_ast.super_state_tag;
+    let super_state_tag = attr_ast.super_state_tag;
+    let krate = attr_ast.krate.unwrap_or_else(get_default_crate_path);
 
     // Get the tag of the current state
-    let mut item_ast: ItemImpl = syn::parse2(item).unwrap();
-    let user_state_tag_ident = get_user_state_tag_from_item_impl_ast(&item_ast);
+    let user_state_tag_ident = get_user_state_tag_from_item_impl_ast(&item_ast, "State")?;
+
+    // Create the function that will return the fn pointer to the super state and push it into the
+    // impl item AST
+    let get_super_state_fn = get_super_state_fn(&super_state_tag, &krate);
+    item_ast.items.push(syn::ImplItem::Fn(get_super_state_fn));
+    let parent_state_const = get_parent_state_const(&super_state_tag, &krate);
+    item_ast
+        .items
+        .push(syn::ImplItem::Const(parent_state_const));
+
+    // Name the state after its tag
+    let user_state_name = user_state_tag_ident.to_string();
+    let name_const: syn::ImplItemConst = syn::parse2(quote!(
+        const NAME: &'static str = #user_state_name;
+    ))
+    .unwrap();
+    item_ast.items.push(syn::ImplItem::Const(name_const));
+
+    // Place the state in the hierarchy so that it can be validated at compile time, and so that
+    // its depth is known at runtime. The state is identified by the path of its tag, for states
+    // declared in different modules may share the same name
+    let has_init = has_impl_item_fn(&item_ast, "init") || attr_ast.regions.is_some();
+    let parent_lineage = if is_top_state_tag(&super_state_tag) {
+        quote!(&#krate::Lineage::Top)
+    } else {
+        quote_spanned!(super_state_tag.span()=>
+            &<Self as #krate::State<#super_state_tag>>::LINEAGE
+        )
+    };
+    let lineage_const: syn::ImplItemConst = syn::parse2(quote!(
+        const LINEAGE: #krate::Lineage =
+            #krate::Lineage::state(
+                ::core::concat!(::core::module_path!(), "::", #user_state_name),
+                #parent_lineage,
+            );
+    ))
+    .unwrap();
+    item_ast.items.push(syn::ImplItem::Const(lineage_const));
+    if !has_init {
+        let has_init_const: syn::ImplItemConst = syn::parse2(quote!(
+            const HAS_INIT: bool = false;
+        ))
+        .unwrap();
+        item_ast.items.push(syn::ImplItem::Const(has_init_const));
+    }
+    let hierarchy_check_const =
+        get_hierarchy_check_const(&item_ast, &super_state_tag, &user_state_tag_ident, &krate);
+    item_ast
+        .items
+        .push(syn::ImplItem::Const(hierarchy_check_const));
+    let hierarchy_check = get_hierarchy_check(&item_ast, &user_state_tag_ident, &krate);
+    let state_id_check = get_state_id_check(&item_ast, &user_state_tag_ident, &krate);
+
+    // Give access to the history field of the state if it has one
+    if let Some(shallow_history_field) = attr_ast.shallow_history_field {
+        let shallow_history_fn: syn::ImplItemFn = syn::parse2(quote!(
+            fn shallow_history(&mut self) -> Option<&mut #krate::History<Self>> {
+                Some(&mut self.#shallow_history_field)
+            }
+        ))
+        .unwrap();
+        item_ast.items.push(syn::ImplItem::Fn(shallow_history_fn));
+    }
+    if let Some(deep_history_field) = attr_ast.deep_history_field {
+        let deep_history_fn: syn::ImplItemFn = syn::parse2(quote!(
+            fn deep_history(&mut self) -> Option<&mut #krate::History<Self>> {
+                Some(&mut self.#deep_history_field)
+            }
+        ))
+        .unwrap();
+        item_ast.items.push(syn::ImplItem::Fn(deep_history_fn));
+    }
+
+    // Give access to the region states and to the regions field of an orthogonal state
+    if let Some(regions) = attr_ast.regions {
+        let region_indexes = (0..regions.region_state_tags.len()).map(Literal::usize_unsuffixed);
+        let region_state_tags = regions.region_state_tags.iter();
+        let region_init_fn: syn::ImplItemFn = syn::parse2(quote!(
+            fn region_init(region: usize) -> #krate::InitResult<Self> {
+                match region {
+                    #(#region_indexes => #krate::InitResult::TargetState(#krate::State::<#region_state_tags>::state_fn()),)*
+                    _ => #krate::InitResult::NotImplemented,
+                }
+            }
+        ))
+        .unwrap();
+        item_ast.items.push(syn::ImplItem::Fn(region_init_fn));
 
-    // Create the function that will return the fn pointer to the super state
-    let get_super_state_fn: syn::ImplItemFn;
+        let region_count = Literal::usize_unsuffixed(regions.region_state_tags.len());
+        let regions_field = regions.regions_field;
+        let regions_fn: syn::ImplItemFn = syn::parse2(quote!(
+            fn regions(&self) -> Option<&[#krate::RegionRecord<Self>]> {
+                let regions: &#krate::Regions<Self, #region_count> = &self.#regions_field;
+                Some(regions.as_slice())
+            }
+        ))
+        .unwrap();
+        item_ast.items.push(syn::ImplItem::Fn(regions_fn));
 
-    if super_state_tag_ident.to_string() == "Top" {
-        get_super_state_fn = syn::parse2(quote!(
-            fn get_parent_state() -> kaori_hsm::ParentState<Self> {
-                kaori_hsm::ParentState::TopReached
+        let regions_mut_fn: syn::ImplItemFn = syn::parse2(quote!(
+            fn regions_mut(&mut self) -> Option<&mut [#krate::RegionRecord<Self>]> {
+                let regions: &mut #krate::Regions<Self, #region_count> = &mut self.#regions_field;
+                Some(regions.as_mut_slice())
             }
         ))
         .unwrap();
+        item_ast.items.push(syn::ImplItem::Fn(regions_mut_fn));
+    }
+
+    // Move the fallible handling of the events to the `FallibleState<>` trait and handle the
+    // events through it
+    let fallible_state_impl = if has_impl_item_fn(&item_ast, "try_handle") {
+        let mut fallible_state_impl = item_ast.clone();
+        let (fallible_items, state_items) =
+            item_ast.items.into_iter().partition(is_fallible_state_item);
+        fallible_state_impl.items = fallible_items;
+        item_ast.items = state_items;
+        fallible_state_impl.trait_ = Some((
+            None,
+            syn::parse2(quote!(#krate::FallibleState<#user_state_tag_ident>)).unwrap(),
+            Default::default(),
+        ));
+
+        let handle_fn: syn::ImplItemFn = syn::parse2(quote!(
+            fn handle(&mut self, evt: &<Self as #krate::TopState>::Evt) -> #krate::HandleResult<Self> {
+                match #krate::FallibleState::<#user_state_tag_ident>::try_handle(self, evt) {
+                    Ok(handle_result) => handle_result,
+                    Err(error) => #krate::FallibleState::<#user_state_tag_ident>::on_error(self, error),
+                }
+            }
+        ))
+        .unwrap();
+        item_ast.items.push(syn::ImplItem::Fn(handle_fn));
+        Some(fallible_state_impl)
     } else {
-        get_super_state_fn = syn::parse2(quote!(
-            fn get_parent_state() -> kaori_hsm::ParentState<Self> {
-                kaori_hsm::ParentState::Exists(kaori_hsm::State::<#super_state_tag_ident>::core_handle)
+        None
+    };
+
+    // Generate code from the item impl AST
+    let vis = attr_ast.vis;
+    Ok(
+        quote! {#vis struct #user_state_tag_ident{ } #item_ast #fallible_state_impl #hierarchy_check #state_id_check},
+    )
+}
+
+// Assert at compile time that the lineage of the state has no cycle, that its parent state has an
+// initial transition or regions, and that its initial transitions target its descendants
+fn get_hierarchy_check_const(
+    item_impl_ast: &ItemImpl,
+    super_state_tag: &syn::Path,
+    user_state_tag_ident: &Ident,
+    krate: &syn::Path,
+) -> syn::ImplItemConst {
+    let mut hierarchy_checks = quote_spanned!(user_state_tag_ident.span()=>
+        let _: &#krate::Lineage =
+            &<Self as #krate::State<#user_state_tag_ident>>::LINEAGE;
+    );
+
+    if !is_top_state_tag(super_state_tag) {
+        let message = format!(
+            "state `{}` has the substate `{}` but implements neither `init()` nor regions",
+            get_state_tag_string(super_state_tag),
+            user_state_tag_ident
+        );
+        hierarchy_checks.extend(quote_spanned!(super_state_tag.span()=>
+            assert!(
+                <Self as #krate::State<#super_state_tag>>::HAS_INIT,
+                #message
+            );
+        ));
+    }
+
+    let init_fn = item_impl_ast
+        .items
+        .iter()
+        .find_map(|impl_item| match impl_item {
+            syn::ImplItem::Fn(impl_item_fn) if impl_item_fn.sig.ident == "init" => {
+                Some(impl_item_fn)
+            }
+            _ => None,
+        });
+    if let Some(init_fn) = init_fn {
+        let mut init_target_tags = Vec::new();
+        get_init_transition_targets(init_fn.block.to_token_stream(), &mut init_target_tags);
+        for init_target_tag in init_target_tags {
+            let message = format!(
+                "`init_transition!({})` in state `{}` does not target a substate of `{}`",
+                get_state_tag_string(&init_target_tag),
+                user_state_tag_ident,
+                user_state_tag_ident
+            );
+            hierarchy_checks.extend(quote_spanned!(init_target_tag.span()=>
+                assert!(
+                    <Self as #krate::State<#init_target_tag>>::LINEAGE
+                        .may_descend_from(&<Self as #krate::State<#user_state_tag_ident>>::LINEAGE),
+                    #message
+                );
+            ));
+        }
+    }
+
+    syn::parse2(quote!(
+        const HIERARCHY_CHECK: () = {
+            #hierarchy_checks
+        };
+    ))
+    .unwrap()
+}
+
+// Evaluate the hierarchy checks of the state as soon as the crate is compiled. The checks of a
+// state machine having generic parameters can only be evaluated once it is instantiated, along
+// with the table of functions of the state.
+fn get_hierarchy_check(
+    item_impl_ast: &ItemImpl,
+    user_state_tag_ident: &Ident,
+    krate: &syn::Path,
+) -> TokenStream {
+    if !item_impl_ast.generics.params.is_empty() {
+        return TokenStream::new();
+    }
+
+    let user_state_machine_type = &item_impl_ast.self_ty;
+    quote_spanned!(user_state_tag_ident.span()=>
+        const _: () = <#user_state_machine_type as #krate::State<#user_state_tag_ident>>::HIERARCHY_CHECK;
+    )
+}
+
+// Check that the state is listed by the `#[state_machine()]` procedural macro if the state
+// machine has one. The check relies on the method resolution, which cannot tell whether a state
+// machine having generic parameters implements `HasStateId`.
+fn get_state_id_check(
+    item_impl_ast: &ItemImpl,
+    user_state_tag_ident: &Ident,
+    krate: &syn::Path,
+) -> TokenStream {
+    if !item_impl_ast.generics.params.is_empty() {
+        return TokenStream::new();
+    }
+
+    let user_state_machine_type = &item_impl_ast.self_ty;
+    quote_spanned!(user_state_tag_ident.span()=>
+        const _: fn() = || {
+            // Only one of the traits is used, depending on the state machine
+            #[allow(unused_imports)]
+            use #krate::{ProbeNoStateId as _, ProbeStateId as _};
+            let probe = #krate::StateIdProbe::<#user_state_machine_type, #user_state_tag_ident>::default();
+            #krate::check_listed_state((&&probe).probe());
+        };
+    )
+}
+
+// Collect the target states of the `init_transition!()` macro calls found in the token stream
+fn get_init_transition_targets(token_stream: TokenStream, init_target_tags: &mut Vec<syn::Path>) {
+    let mut token_trees = token_stream.into_iter().peekable();
+    while let Some(token_tree) = token_trees.next() {
+        match token_tree {
+            TokenTree::Ident(ident) if ident == "init_transition" => {
+                if let Some(TokenTree::Punct(punct)) = token_trees.peek() {
+                    if punct.as_char() == '!' {
+                        token_trees.next();
+                        if let Some(TokenTree::Group(group)) = token_trees.next() {
+                            if let Ok(target_tag) = syn::parse2::<syn::Path>(group.stream()) {
+                                init_target_tags.push(target_tag);
+                            }
+                        }
+                    }
+                }
+            }
+            TokenTree::Group(group) => {
+                get_init_transition_targets(group.stream(), init_target_tags)
             }
+            _ => {}
+        }
+    }
+}
+
+fn has_impl_item_fn(item_impl_ast: &ItemImpl, fn_name: &str) -> bool {
+    item_impl_ast.items.iter().any(
+        |impl_item| matches!(impl_item, syn::ImplItem::Fn(impl_item_fn) if impl_item_fn.sig.ident == fn_name),
+    )
+}
+
+fn is_fallible_state_item(impl_item: &syn::ImplItem) -> bool {
+    matches!(impl_item, syn::ImplItem::Fn(impl_item_fn)
+        if impl_item_fn.sig.ident == "try_handle" || impl_item_fn.sig.ident == "on_error")
+}
+
+/// Macro to call before every implementation of the `Choice<>` trait.
+/// This is what the macro does:
+/// - Create an empty structure named after the tag sent as a generic parameter
+///   in the `Choice<>` trait implementation, whose visibility is provided as the value of `vis`
+///   in the same way as for the `#[state()]` macro.
+/// - Implement the `State<>` trait for the same tag, so that the choice can be targeted by the
+///   `transition!()` macro. The parent of the choice is the state provided as the value of
+///   `super_state`, in the same way as for the `#[state()]` macro.
+/// - If `targets = [StateA, StateB]` is provided, define the `Choice::TARGETS` constant as the
+///   names of the listed states.
+/// ```rust,ignore
+///# enum BasicEvt{A};
+///# struct BasicStateMachine{ level: u8 }
+/// #[choice(super_state= S1, targets= [S11, S12])]
+/// impl Choice<LevelChoice> for BasicStateMachine {
+///     fn choose(&mut self) -> HandleResult<Self> {
+///         if self.level > 10 {
+///             transition!(S12)
+///         } else {
+///             transition!(S11)
+///         }
+///     }
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn choice(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let output_token_stream = choice_impl(
+        proc_macro2::TokenStream::from(args),
+        proc_macro2::TokenStream::from(item),
+    );
+    proc_macro::TokenStream::from(output_token_stream)
+}
+
+struct AttrChoiceDecl {
+    super_state_tag: syn::Path,
+    vis: syn::Visibility,
+    krate: syn::Path,
+    target_state_tags: Vec<syn::Path>,
+}
+
+impl Parse for AttrChoiceDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut super_state_tag: Option<syn::Path> = None;
+        let mut vis: Option<syn::Visibility> = None;
+        let mut krate: Option<syn::Path> = None;
+        let mut target_state_tags: Option<Vec<syn::Path>> = None;
+
+        while !input.is_empty() {
+            let attr_name = input.call(syn::Ident::parse_any)?;
+            input.parse::<Token![=]>()?;
+
+            match attr_name.to_string().as_str() {
+                "super_state" => {
+                    set_attr_field(&mut super_state_tag, &attr_name, input.parse()?)?;
+                }
+                "vis" => {
+                    set_attr_field(&mut vis, &attr_name, input.parse()?)?;
+                }
+                "crate" => {
+                    set_attr_field(&mut krate, &attr_name, input.parse()?)?;
+                }
+                "targets" => {
+                    let target_state_tags_input;
+                    syn::bracketed!(target_state_tags_input in input);
+                    let tags = Punctuated::<syn::Path, Token![,]>::parse_terminated(
+                        &target_state_tags_input,
+                    )?
+                    .into_iter()
+                    .collect();
+                    set_attr_field(&mut target_state_tags, &attr_name, tags)?;
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        attr_name.span(),
+                        format!(
+                        "unknown field `{}`, expected `super_state`, `vis`, `crate` or `targets`",
+                        attr_name
+                    ),
+                    ))
+                }
+            }
+
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+
+        let super_state_tag = super_state_tag.ok_or_else(|| {
+            syn::Error::new(
+                input.span(),
+                "expected field `super_state`, naming the parent state or `Top`",
+            )
+        })?;
+
+        Ok(AttrChoiceDecl {
+            super_state_tag,
+            vis: vis.unwrap_or(syn::Visibility::Inherited),
+            krate: krate.unwrap_or_else(get_default_crate_path),
+            target_state_tags: target_state_tags.unwrap_or_default(),
+        })
+    }
+}
+
+pub(crate) fn choice_impl(args: TokenStream, item: TokenStream) -> TokenStream {
+    try_choice_impl(args, item).unwrap_or_else(syn::Error::into_compile_error)
+}
+
+fn try_choice_impl(args: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    let attr_ast: AttrChoiceDecl = syn::parse2(args)?;
+
+    // Get the tag of the choice
+    let mut item_ast: ItemImpl = syn::parse2(item)?;
+    let user_choice_tag_ident = get_user_state_tag_from_item_impl_ast(&item_ast, "Choice")?;
+    let user_state_machine_type = item_ast.self_ty.clone();
+    let generics = item_ast.generics.clone();
+    let krate = &attr_ast.krate;
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    // Name the targets of the choice after their tags. Naming them through the `State<>` trait
+    // ensures that they exist.
+    if !attr_ast.target_state_tags.is_empty() {
+        let target_state_tags = attr_ast.target_state_tags.iter();
+        let targets_const: syn::ImplItemConst = syn::parse2(quote!(
+            const TARGETS: &'static [&'static str] = &[#(<Self as #krate::State<#target_state_tags>>::NAME),*];
         ))
         .unwrap();
+        item_ast.items.push(syn::ImplItem::Const(targets_const));
     }
 
-    let get_super_state_impl_item_fn = syn::ImplItem::Fn(get_super_state_fn);
+    // Implement the `State<>` trait through which the choice is reached
+    let get_super_state_fn = get_super_state_fn(&attr_ast.super_state_tag, krate);
+    let parent_state_const = get_parent_state_const(&attr_ast.super_state_tag, krate);
+    let user_choice_name = user_choice_tag_ident.to_string();
+    let state_impl = quote!(
+        impl #impl_generics #krate::State<#user_choice_tag_ident> for #user_state_machine_type #where_clause {
+            #get_super_state_fn
+
+            #parent_state_const
+
+            const NAME: &'static str = #user_choice_name;
 
-    // Push the function into the impl item AST
-    item_ast.items.push(get_super_state_impl_item_fn);
+            fn handle(&mut self, _evt: &<Self as #krate::TopState>::Evt) -> #krate::HandleResult<Self> {
+                #krate::HandleResult::Ignored
+            }
+
+            fn choose(&mut self) -> Option<#krate::HandleResult<Self>> {
+                Some(#krate::Choice::<#user_choice_tag_ident>::choose(self))
+            }
+        }
+    );
 
     // Generate code from the item impl AST
-    quote! {struct #user_state_tag_ident{ } #item_ast}.into()
+    let vis = attr_ast.vis;
+    Ok(quote! {#vis struct #user_choice_tag_ident{ } #state_impl #item_ast})
+}
+
+/// Macro to call before every implementation of the `FinalState<>` trait.
+/// This is what the macro does:
+/// - Create an empty structure named after the tag sent as a generic parameter
+///   in the `FinalState<>` trait implementation, whose visibility is provided as the value of
+///   `vis` in the same way as for the `#[state()]` macro.
+/// - Implement the `State<>` trait for the same tag, ignoring every event, so that the final
+///   state can be targeted by the `transition!()` macro. The parent of the final state is the
+///   state provided as the value of `super_state`, in the same way as for the `#[state()]` macro.
+/// ```rust,ignore
+///# struct BasicStateMachine{}
+/// #[final_state(super_state= S1)]
+/// impl FinalState<S1Done> for BasicStateMachine {}
+/// ```
+#[proc_macro_attribute]
+pub fn final_state(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let output_token_stream = final_state_impl(
+        proc_macro2::TokenStream::from(args),
+        proc_macro2::TokenStream::from(item),
+    );
+    proc_macro::TokenStream::from(output_token_stream)
+}
+
+struct AttrFinalStateDecl {
+    super_state_tag: syn::Path,
+    vis: syn::Visibility,
+    krate: syn::Path,
+}
+
+impl Parse for AttrFinalStateDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut super_state_tag: Option<syn::Path> = None;
+        let mut vis: Option<syn::Visibility> = None;
+        let mut krate: Option<syn::Path> = None;
+
+        while !input.is_empty() {
+            let attr_name = input.call(syn::Ident::parse_any)?;
+            input.parse::<Token![=]>()?;
+
+            match attr_name.to_string().as_str() {
+                "super_state" => {
+                    set_attr_field(&mut super_state_tag, &attr_name, input.parse()?)?;
+                }
+                "vis" => {
+                    set_attr_field(&mut vis, &attr_name, input.parse()?)?;
+                }
+                "crate" => {
+                    set_attr_field(&mut krate, &attr_name, input.parse()?)?;
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        attr_name.span(),
+                        format!(
+                            "unknown field `{}`, expected `super_state`, `vis` or `crate`",
+                            attr_name
+                        ),
+                    ))
+                }
+            }
+
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+
+        let super_state_tag = super_state_tag.ok_or_else(|| {
+            syn::Error::new(
+                input.span(),
+                "expected field `super_state`, naming the parent state or `Top`",
+            )
+        })?;
+
+        Ok(AttrFinalStateDecl {
+            super_state_tag,
+            vis: vis.unwrap_or(syn::Visibility::Inherited),
+            krate: krate.unwrap_or_else(get_default_crate_path),
+        })
+    }
+}
+
+pub(crate) fn final_state_impl(args: TokenStream, item: TokenStream) -> TokenStream {
+    try_final_state_impl(args, item).unwrap_or_else(syn::Error::into_compile_error)
+}
+
+fn try_final_state_impl(args: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    let attr_ast: AttrFinalStateDecl = syn::parse2(args)?;
+
+    // Get the tag of the final state
+    let item_ast: ItemImpl = syn::parse2(item)?;
+    let user_final_state_tag_ident =
+        get_user_state_tag_from_item_impl_ast(&item_ast, "FinalState")?;
+    let user_state_machine_type = item_ast.self_ty.clone();
+    let generics = item_ast.generics.clone();
+    let krate = &attr_ast.krate;
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+
+    // Implement the `State<>` trait through which the final state is reached
+    let get_super_state_fn = get_super_state_fn(&attr_ast.super_state_tag, krate);
+    let parent_state_const = get_parent_state_const(&attr_ast.super_state_tag, krate);
+    let user_final_state_name = user_final_state_tag_ident.to_string();
+    let state_impl = quote!(
+        impl #impl_generics #krate::State<#user_final_state_tag_ident> for #user_state_machine_type #where_clause {
+            #get_super_state_fn
+
+            #parent_state_const
+
+            const NAME: &'static str = #user_final_state_name;
+
+            const HAS_INIT: bool = false;
+
+            const IS_FINAL: bool = true;
+
+            fn handle(&mut self, _evt: &<Self as #krate::TopState>::Evt) -> #krate::HandleResult<Self> {
+                #krate::HandleResult::Ignored
+            }
+        }
+    );
+
+    let state_id_check = get_state_id_check(&item_ast, &user_final_state_tag_ident, krate);
+
+    // Generate code from the item impl AST
+    let vis = attr_ast.vis;
+    Ok(quote! {#vis struct #user_final_state_tag_ident{ } #state_impl #item_ast #state_id_check})
+}
+
+/// Macro to call before the definition of the state machine structure, in order to identify each
+/// of its states with a variant of a generated enum.
+/// This is what the macro does:
+/// - Create an enum named after the structure followed by `StateId`, or named after the value of
+///   `state_id` if provided, having a variant named after each of the tags listed as the value of
+///   `states`. A tag listed as `motor::Idle as MotorIdle` gives the `MotorIdle` variant, which is
+///   required when the tags of two listed states share the same name.
+/// - Implement the `HasStateId` trait for the structure, so that the identifier of its current
+///   state can be read with `StateMachine::current_state_id()`.
+/// - Implement `From<StateId>` for `&'static str`, returning the name of the state tag.
+/// - Mark each listed state as such, the `#[state()]` and `#[final_state()]` macros failing to
+///   compile a state of the structure which is not listed.
+/// ```rust,ignore
+/// #[state_machine(states = [S1, S11, S12])]
+/// struct BasicStateMachine{}
+///
+/// // ...
+/// match sm.current_state_id() {
+///     Some(BasicStateMachineStateId::S11) => println!("In S11"),
+///     _ => {}
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn state_machine(
+    args: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let output_token_stream = state_machine_impl(
+        proc_macro2::TokenStream::from(args),
+        proc_macro2::TokenStream::from(item),
+    );
+    proc_macro::TokenStream::from(output_token_stream)
+}
+
+// State listed by the `#[state_machine()]` macro, along with the variant identifying it, named
+// after the last segment of the path of its tag unless it is renamed with `as Name`
+struct ListedStateDecl {
+    state_tag: syn::Path,
+    state_variant: Ident,
+}
+
+impl Parse for ListedStateDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let state_tag: syn::Path = input.parse()?;
+        let state_variant = if input.parse::<Option<Token![as]>>()?.is_some() {
+            input.parse()?
+        } else {
+            state_tag.segments.last().unwrap().ident.clone()
+        };
+        Ok(ListedStateDecl {
+            state_tag,
+            state_variant,
+        })
+    }
+}
+
+struct AttrStateMachineDecl {
+    states: Vec<ListedStateDecl>,
+    state_id_ident: Option<syn::Ident>,
+    krate: syn::Path,
+}
+
+impl Parse for AttrStateMachineDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut states: Option<Vec<ListedStateDecl>> = None;
+        let mut state_id_ident: Option<syn::Ident> = None;
+        let mut krate: Option<syn::Path> = None;
+
+        while !input.is_empty() {
+            let attr_name = input.call(syn::Ident::parse_any)?;
+            input.parse::<Token![=]>()?;
+
+            match attr_name.to_string().as_str() {
+                "states" => {
+                    let states_input;
+                    syn::bracketed!(states_input in input);
+                    let listed_states =
+                        Punctuated::<ListedStateDecl, Token![,]>::parse_terminated(&states_input)?
+                            .into_iter()
+                            .collect();
+                    set_attr_field(&mut states, &attr_name, listed_states)?;
+                }
+                "state_id" => {
+                    set_attr_field(&mut state_id_ident, &attr_name, input.parse()?)?;
+                }
+                "crate" => {
+                    set_attr_field(&mut krate, &attr_name, input.parse()?)?;
+                }
+                _ => {
+                    return Err(syn::Error::new(
+                        attr_name.span(),
+                        format!(
+                            "unknown field `{}`, expected `states`, `state_id` or `crate`",
+                            attr_name
+                        ),
+                    ))
+                }
+            }
+
+            if input.is_empty() {
+                break;
+            }
+            input.parse::<Token![,]>()?;
+        }
+
+        let states =
+            states.ok_or_else(|| syn::Error::new(input.span(), "expected field `states`"))?;
+
+        Ok(AttrStateMachineDecl {
+            states,
+            state_id_ident,
+            krate: krate.unwrap_or_else(get_default_crate_path),
+        })
+    }
+}
+
+pub(crate) fn state_machine_impl(args: TokenStream, item: TokenStream) -> TokenStream {
+    try_state_machine_impl(args, item).unwrap_or_else(syn::Error::into_compile_error)
+}
+
+fn try_state_machine_impl(args: TokenStream, item: TokenStream) -> syn::Result<TokenStream> {
+    let attr_ast: AttrStateMachineDecl = syn::parse2(args)?;
+    let item_ast: syn::ItemStruct = syn::parse2(item)?;
+
+    let user_state_machine_ident = &item_ast.ident;
+    let vis = &item_ast.vis;
+    let krate = &attr_ast.krate;
+    let (impl_generics, type_generics, where_clause) = item_ast.generics.split_for_impl();
+    let state_id_ident = attr_ast
+        .state_id_ident
+        .unwrap_or_else(|| format_ident!("{}StateId", user_state_machine_ident));
+    // States of different modules sharing the same name must be renamed to tell their variants
+    // apart
+    for (index, state) in attr_ast.states.iter().enumerate() {
+        if attr_ast.states[..index]
+            .iter()
+            .any(|listed_state| listed_state.state_variant == state.state_variant)
+        {
+            return Err(syn::Error::new(
+                state.state_variant.span(),
+                format!(
+                    "duplicate state identifier `{}`, rename the state as in `{} as Name`",
+                    state.state_variant,
+                    get_state_tag_string(&state.state_tag)
+                ),
+            ));
+        }
+    }
+    let state_tags = attr_ast
+        .states
+        .iter()
+        .map(|state| &state.state_tag)
+        .collect::<Vec<_>>();
+    let state_variants = attr_ast
+        .states
+        .iter()
+        .map(|state| &state.state_variant)
+        .collect::<Vec<_>>();
+    // The name of a state is the last segment of the path of its tag, as its `State::NAME`
+    let state_names = state_tags
+        .iter()
+        .map(|state_tag| state_tag.segments.last().unwrap().ident.to_string());
+
+    Ok(quote! {
+        #item_ast
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #vis enum #state_id_ident {
+            #(#state_variants,)*
+        }
+
+        impl #impl_generics #krate::HasStateId for #user_state_machine_ident #type_generics #where_clause {
+            type StateId = #state_id_ident;
+
+            fn state_id(state: #krate::StateRef<Self>) -> Option<#state_id_ident> {
+                #(if state.is::<#state_tags>() {
+                    return Some(#state_id_ident::#state_variants);
+                })*
+                None
+            }
+        }
+
+        #(impl #impl_generics #krate::ListedState<#state_tags> for #user_state_machine_ident #type_generics #where_clause {})*
+
+        impl From<#state_id_ident> for &'static str {
+            fn from(state_id: #state_id_ident) -> Self {
+                match state_id {
+                    #(#state_id_ident::#state_variants => #state_names,)*
+                }
+            }
+        }
+    })
+}
+
+/// Macro declaring a whole state machine in one block, as a table of the reactions of each state
+/// to the events. It implements the `TopState` trait and, through the `#[state()]` procedural
+/// macro, the `State<>` trait for each of the declared states.
+///
+/// The block starts with the declaration of the state machine structure, the type of its events
+/// and the target of its initial transition. The path of the library may be provided in the
+/// first line of the block, as in `crate = my_framework::hsm;`, in the same way as for the
+/// `#[state()]` macro. The generic parameters of a generic structure are
+/// declared after the `machine` keyword, as in `machine<T: Uart> Driver<T> where T: Send { .. }`.
+/// Each state is then declared along with the arguments of the `#[state()]` macro, and may
+/// contain:
+/// - `init => Target;`: the initial transition of a composite state.
+/// - `entry / action;` and `exit / action;`: the actions executed on entry and on exit of the
+///   state.
+/// - `Pattern if guard => reaction;`: the reaction of the state to the events matching `Pattern`
+///   when `guard` is true, the guard being optional. The reaction may be either a target state,
+///   `history Target`, `handled`, `deferred` or `terminate`. The events which do not match any
+///   row are ignored.
+///
+/// Every initial transition and reaction may be followed by `/ action`. An action is a function
+/// taking `&mut self` as only argument. The action of a transition, to a target state or to its
+/// history, is executed once every state left by the transition has been exited, while the other actions are executed before returning
+/// from the event handler.
+/// ```rust,ignore
+///# enum BasicEvt{A, B(u8), C};
+///# struct BasicStateMachine{ count: u8 }
+/// hsm! {
+///     machine BasicStateMachine {
+///         type Evt = BasicEvt;
+///         init => S1;
+///     }
+///
+///     state S1(super_state = Top) {
+///         init => S11;
+///         entry / Self::on_s1_entry;
+///         BasicEvt::A => S1;
+///     }
+///
+///     state S11(super_state = S1) {
+///         BasicEvt::B(level) if *level > 10 => S12 / Self::on_high_level;
+///         BasicEvt::C => handled / Self::count;
+///     }
+///
+///     state S12(super_state = S1) {
+///         exit / Self::on_s12_exit;
+///         BasicEvt::C => deferred;
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn hsm(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let output_token_stream = hsm_impl(proc_macro2::TokenStream::from(input));
+    proc_macro::TokenStream::from(output_token_stream)
+}
+
+struct HsmDecl {
+    machine: HsmMachineDecl,
+    states: Vec<HsmStateDecl>,
+}
+
+struct HsmMachineDecl {
+    generics: syn::Generics,
+    user_state_machine_type: syn::Type,
+    krate: syn::Path,
+    evt_type: syn::Type,
+    init: HsmInitDecl,
+}
+
+struct HsmInitDecl {
+    target_state_tag: syn::Path,
+    action: Option<syn::Expr>,
+}
+
+struct HsmStateDecl {
+    user_state_tag: syn::Ident,
+    state_attr: AttrStateDecl,
+    init: Option<HsmInitDecl>,
+    entry_action: Option<syn::Expr>,
+    exit_action: Option<syn::Expr>,
+    rows: Vec<HsmRowDecl>,
+}
+
+struct HsmRowDecl {
+    pattern: syn::Pat,
+    guard: Option<syn::Expr>,
+    reaction: HsmReaction,
+    action: Option<syn::Expr>,
+}
+
+enum HsmReaction {
+    Transition(syn::Path),
+    HistoryTransition(syn::Path),
+    Handled,
+    Deferred,
+    Terminate,
+}
+
+fn parse_hsm_action(input: syn::parse::ParseStream) -> syn::Result<Option<syn::Expr>> {
+    if input.peek(Token![/]) {
+        input.parse::<Token![/]>()?;
+        Ok(Some(input.parse()?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn peek_hsm_keyword(input: syn::parse::ParseStream, keyword: &str) -> bool {
+    input
+        .fork()
+        .parse::<syn::Ident>()
+        .is_ok_and(|ident| ident == keyword)
+}
+
+impl Parse for HsmInitDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        input.parse::<syn::Ident>()?;
+        input.parse::<Token![=>]>()?;
+        let target_state_tag = input.parse()?;
+        let action = parse_hsm_action(input)?;
+        input.parse::<Token![;]>()?;
+
+        Ok(HsmInitDecl {
+            target_state_tag,
+            action,
+        })
+    }
+}
+
+impl Parse for HsmMachineDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let machine_keyword = input.parse::<syn::Ident>()?;
+        if machine_keyword != "machine" {
+            return Err(syn::Error::new(
+                machine_keyword.span(),
+                "expected `machine`",
+            ));
+        }
+        let mut generics: syn::Generics = input.parse()?;
+        let user_state_machine_type = input.parse()?;
+        generics.where_clause = input.parse()?;
+
+        let machine_input;
+        syn::braced!(machine_input in input);
+        let krate = if machine_input.peek(Token![crate]) {
+            machine_input.parse::<Token![crate]>()?;
+            machine_input.parse::<Token![=]>()?;
+            let krate = machine_input.parse()?;
+            machine_input.parse::<Token![;]>()?;
+            krate
+        } else {
+            get_default_crate_path()
+        };
+        machine_input.parse::<Token![type]>()?;
+        let evt_ident = machine_input.parse::<syn::Ident>()?;
+        if evt_ident != "Evt" {
+            return Err(syn::Error::new(evt_ident.span(), "expected `Evt`"));
+        }
+        machine_input.parse::<Token![=]>()?;
+        let evt_type = machine_input.parse()?;
+        machine_input.parse::<Token![;]>()?;
+        if !peek_hsm_keyword(&machine_input, "init") {
+            return Err(machine_input.error("expected `init`"));
+        }
+        let init = machine_input.parse()?;
+
+        Ok(HsmMachineDecl {
+            generics,
+            user_state_machine_type,
+            krate,
+            evt_type,
+            init,
+        })
+    }
+}
+
+impl Parse for HsmRowDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let pattern = syn::Pat::parse_multi_with_leading_vert(input)?;
+        let guard = if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            Some(input.parse()?)
+        } else {
+            None
+        };
+        input.parse::<Token![=>]>()?;
+
+        let reaction_path = input.parse::<syn::Path>()?;
+        let reaction = match reaction_path.get_ident().map(Ident::to_string).as_deref() {
+            Some("history") => HsmReaction::HistoryTransition(input.parse()?),
+            Some("handled") => HsmReaction::Handled,
+            Some("deferred") => HsmReaction::Deferred,
+            Some("terminate") => HsmReaction::Terminate,
+            _ => HsmReaction::Transition(reaction_path),
+        };
+        let action = parse_hsm_action(input)?;
+        input.parse::<Token![;]>()?;
+
+        Ok(HsmRowDecl {
+            pattern,
+            guard,
+            reaction,
+            action,
+        })
+    }
+}
+
+impl Parse for HsmStateDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let state_keyword = input.parse::<syn::Ident>()?;
+        if state_keyword != "state" {
+            return Err(syn::Error::new(state_keyword.span(), "expected `state`"));
+        }
+        let user_state_tag = input.parse()?;
+        let state_attr_input;
+        syn::parenthesized!(state_attr_input in input);
+        let state_attr = state_attr_input.parse()?;
+
+        let state_input;
+        syn::braced!(state_input in input);
+        let mut init = None;
+        let mut entry_action = None;
+        let mut exit_action = None;
+        let mut rows = Vec::new();
+        while !state_input.is_empty() {
+            if peek_hsm_keyword(&state_input, "init") && state_input.peek2(Token![=>]) {
+                init = Some(state_input.parse()?);
+            } else if peek_hsm_keyword(&state_input, "entry") && state_input.peek2(Token![/]) {
+                state_input.parse::<syn::Ident>()?;
+                entry_action = parse_hsm_action(&state_input)?;
+                state_input.parse::<Token![;]>()?;
+            } else if peek_hsm_keyword(&state_input, "exit") && state_input.peek2(Token![/]) {
+                state_input.parse::<syn::Ident>()?;
+                exit_action = parse_hsm_action(&state_input)?;
+                state_input.parse::<Token![;]>()?;
+            } else {
+                rows.push(state_input.parse()?);
+            }
+        }
+
+        Ok(HsmStateDecl {
+            user_state_tag,
+            state_attr,
+            init,
+            entry_action,
+            exit_action,
+            rows,
+        })
+    }
+}
+
+impl Parse for HsmDecl {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let machine = input.parse()?;
+        let mut states = Vec::new();
+        while !input.is_empty() {
+            states.push(input.parse()?);
+        }
+
+        Ok(HsmDecl { machine, states })
+    }
+}
+
+fn get_hsm_init_fn(init: &HsmInitDecl, krate: &syn::Path) -> TokenStream {
+    let target_state_tag = &init.target_state_tag;
+    let action_call = init.action.as_ref().map(|action| quote!((#action)(self);));
+    quote!(
+        fn init(&mut self) -> #krate::InitResult<Self> {
+            #action_call
+            #krate::init_transition!(#target_state_tag)
+        }
+    )
+}
+
+fn get_hsm_row_arm(row: &HsmRowDecl, krate: &syn::Path) -> TokenStream {
+    let pattern = &row.pattern;
+    let guard = row.guard.as_ref().map(|guard| quote!(if #guard));
+    let action = row.action.as_ref();
+    let handle_result = match (&row.reaction, action) {
+        (HsmReaction::Transition(target_state_tag), Some(action)) => {
+            quote!(#krate::transition!(#target_state_tag, action = #action))
+        }
+        (HsmReaction::Transition(target_state_tag), None) => {
+            quote!(#krate::transition!(#target_state_tag))
+        }
+        (HsmReaction::HistoryTransition(target_state_tag), Some(action)) => {
+            quote!(#krate::transition_history!(#target_state_tag, action = #action))
+        }
+        (HsmReaction::HistoryTransition(target_state_tag), None) => {
+            quote!(#krate::transition_history!(#target_state_tag))
+        }
+        (HsmReaction::Handled, _) => quote!(#krate::HandleResult::Handled),
+        (HsmReaction::Deferred, _) => quote!(#krate::HandleResult::Deferred),
+        (HsmReaction::Terminate, _) => quote!(#krate::HandleResult::Terminate),
+    };
+    // Only the action of a transition is deferred until the exit of the states
+    let action_call = match (&row.reaction, action) {
+        (HsmReaction::Transition(_) | HsmReaction::HistoryTransition(_), _) | (_, None) => None,
+        (_, Some(action)) => Some(quote!((#action)(self);)),
+    };
+    quote!(#pattern #guard => {
+        #action_call
+        #handle_result
+    })
+}
+
+pub(crate) fn hsm_impl(input: TokenStream) -> TokenStream {
+    try_hsm_impl(input).unwrap_or_else(syn::Error::into_compile_error)
+}
+
+fn try_hsm_impl(input: TokenStream) -> syn::Result<TokenStream> {
+    let HsmDecl { machine, states } = syn::parse2(input)?;
+    let user_state_machine_type = &machine.user_state_machine_type;
+    let (impl_generics, _, where_clause) = machine.generics.split_for_impl();
+    let krate = &machine.krate;
+
+    // Implement the `TopState` trait
+    let evt_type = &machine.evt_type;
+    let top_init_fn = get_hsm_init_fn(&machine.init, krate);
+    let mut output = quote!(
+        impl #impl_generics #krate::TopState for #user_state_machine_type #where_clause {
+            type Evt = #evt_type;
+
+            #top_init_fn
+        }
+    );
+
+    // Implement the `State<>` trait of every state through the `#[state()]` macro
+    for mut state in states {
+        let user_state_tag = &state.user_state_tag;
+        let init_fn = state.init.as_ref().map(|init| get_hsm_init_fn(init, krate));
+        let entry_fn = state.entry_action.as_ref().map(|entry_action| {
+            quote!(
+                fn entry(&mut self) {
+                    (#entry_action)(self)
+                }
+            )
+        });
+        let exit_fn = state.exit_action.as_ref().map(|exit_action| {
+            quote!(
+                fn exit(&mut self) {
+                    (#exit_action)(self)
+                }
+            )
+        });
+        let row_arms = state.rows.iter().map(|row| get_hsm_row_arm(row, krate));
+        let item = quote!(
+            impl #impl_generics #krate::State<#user_state_tag> for #user_state_machine_type #where_clause {
+                #init_fn
+
+                #entry_fn
+
+                #exit_fn
+
+                #[allow(unreachable_patterns)]
+                fn handle(&mut self, evt: &<Self as #krate::TopState>::Evt) -> #krate::HandleResult<Self> {
+                    match evt {
+                        #(#row_arms)*
+                        _ => #krate::HandleResult::Ignored,
+                    }
+                }
+            }
+        );
+        // The states are generated with the crate path of the machine unless they override it
+        state.state_attr.krate.get_or_insert_with(|| krate.clone());
+        output.extend(expand_state(state.state_attr, syn::parse2(item)?)?);
+    }
+
+    Ok(output)
 }
 
 #[cfg(test)]
@@ -162,8 +1497,198 @@ mod test {
 
         let attr_tokens = TokenStream::from_str(attr).unwrap();
         let item_tokens = TokenStream::from_str(item).unwrap();
-        let expected_str = "struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } }";
+        let expected_str = "struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: top ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & kaori_hsm :: Lineage :: Top ,) ; const HAS_INIT : bool = false ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; } ; } const _ : () = < UserStateMachine as kaori_hsm :: State < StateName >> :: HIERARCHY_CHECK ; const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , StateName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
         let res = crate::state_impl(attr_tokens, item_tokens);
         assert_eq!(expected_str, res.to_string());
     }
+
+    #[test]
+    fn test_state_impl_shallow_history() {
+        let attr = "super_state = Parent, shallow_history = state_history";
+        let item = "impl kaori_hsm::State<StateName> for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: Exists (kaori_hsm :: State :: < Parent > :: state_fn ()) } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: of :: < Parent > ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & < Self as kaori_hsm :: State < Parent > > :: LINEAGE ,) ; const HAS_INIT : bool = false ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; assert ! (< Self as kaori_hsm :: State < Parent >> :: HAS_INIT , \"state `Parent` has the substate `StateName` but implements neither `init()` nor regions\") ; } ; fn shallow_history (& mut self) -> Option < & mut kaori_hsm :: History < Self > > { Some (& mut self . state_history) } } const _ : () = < UserStateMachine as kaori_hsm :: State < StateName >> :: HIERARCHY_CHECK ; const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , StateName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_regions() {
+        let attr = "super_state = Top, regions = [RegionA, RegionB] in state_regions";
+        let item = "impl kaori_hsm::State<StateName> for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: top ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & kaori_hsm :: Lineage :: Top ,) ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; } ; fn region_init (region : usize) -> kaori_hsm :: InitResult < Self > { match region { 0 => kaori_hsm :: InitResult :: TargetState (kaori_hsm :: State :: < RegionA > :: state_fn ()) , 1 => kaori_hsm :: InitResult :: TargetState (kaori_hsm :: State :: < RegionB > :: state_fn ()) , _ => kaori_hsm :: InitResult :: NotImplemented , } } fn regions (& self) -> Option < & [kaori_hsm :: RegionRecord < Self >] > { let regions : & kaori_hsm :: Regions < Self , 2 > = & self . state_regions ; Some (regions . as_slice ()) } fn regions_mut (& mut self) -> Option < & mut [kaori_hsm :: RegionRecord < Self >] > { let regions : & mut kaori_hsm :: Regions < Self , 2 > = & mut self . state_regions ; Some (regions . as_mut_slice ()) } } const _ : () = < UserStateMachine as kaori_hsm :: State < StateName >> :: HIERARCHY_CHECK ; const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , StateName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_init() {
+        let attr = "super_state = Top";
+        let item = "impl kaori_hsm::State<StateName> for UserStateMachine{ fn init(&mut self) -> InitResult<Self> { if self.flag { init_transition!(StateA) } else { init_transition!(StateB) } } }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn init (& mut self) -> InitResult < Self > { if self . flag { init_transition ! (StateA) } else { init_transition ! (StateB) } } fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: top ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & kaori_hsm :: Lineage :: Top ,) ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; assert ! (< Self as kaori_hsm :: State < StateA >> :: LINEAGE . may_descend_from (& < Self as kaori_hsm :: State < StateName >> :: LINEAGE) , \"`init_transition!(StateA)` in state `StateName` does not target a substate of `StateName`\") ; assert ! (< Self as kaori_hsm :: State < StateB >> :: LINEAGE . may_descend_from (& < Self as kaori_hsm :: State < StateName >> :: LINEAGE) , \"`init_transition!(StateB)` in state `StateName` does not target a substate of `StateName`\") ; } ; } const _ : () = < UserStateMachine as kaori_hsm :: State < StateName >> :: HIERARCHY_CHECK ; const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , StateName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_fallible() {
+        let attr = "super_state = Top";
+        let item = "impl kaori_hsm::State<StateName> for UserStateMachine{ fn try_handle(&mut self, evt: &Evt) -> Result<HandleResult<Self>, Error> { Ok(handled!()) } }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: top ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & kaori_hsm :: Lineage :: Top ,) ; const HAS_INIT : bool = false ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; } ; fn handle (& mut self , evt : & < Self as kaori_hsm :: TopState > :: Evt) -> kaori_hsm :: HandleResult < Self > { match kaori_hsm :: FallibleState :: < StateName > :: try_handle (self , evt) { Ok (handle_result) => handle_result , Err (error) => kaori_hsm :: FallibleState :: < StateName > :: on_error (self , error) , } } } impl kaori_hsm :: FallibleState < StateName > for UserStateMachine { fn try_handle (& mut self , evt : & Evt) -> Result < HandleResult < Self > , Error > { Ok (handled ! ()) } } const _ : () = < UserStateMachine as kaori_hsm :: State < StateName >> :: HIERARCHY_CHECK ; const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , StateName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_generic() {
+        let attr = "super_state = Top";
+        let item =
+            "impl<'a, T: Uart> kaori_hsm::State<StateName> for Driver<'a, T> where T: Send { }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct StateName { } impl < 'a , T : Uart > kaori_hsm :: State < StateName > for Driver < 'a , T > where T : Send { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: top ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & kaori_hsm :: Lineage :: Top ,) ; const HAS_INIT : bool = false ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; } ; }";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_super_state_path() {
+        let attr = "super_state = crate::motor::Running, vis = pub(crate)";
+        let item = "impl kaori_hsm::State<StateName> for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "pub (crate) struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: Exists (kaori_hsm :: State :: < crate :: motor :: Running > :: state_fn ()) } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: of :: < crate :: motor :: Running > ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & < Self as kaori_hsm :: State < crate :: motor :: Running > > :: LINEAGE ,) ; const HAS_INIT : bool = false ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; assert ! (< Self as kaori_hsm :: State < crate :: motor :: Running >> :: HAS_INIT , \"state `crate::motor::Running` has the substate `StateName` but implements neither `init()` nor regions\") ; } ; } const _ : () = < UserStateMachine as kaori_hsm :: State < StateName >> :: HIERARCHY_CHECK ; const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , StateName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_unknown_field() {
+        let attr = "super_state = Top, history = state_history";
+        let item = "impl kaori_hsm::State<StateName> for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = ":: core :: compile_error ! { \"unknown field `history`, expected `super_state`, `vis`, `crate`, `shallow_history`, `deep_history` or `regions`\" }";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_missing_tag() {
+        let attr = "super_state = Top";
+        let item = "impl kaori_hsm::State for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = ":: core :: compile_error ! { \"expected a tag as generic argument of the `State<>` trait, as in `State<Tag>`\" }";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_impl_tag_path() {
+        let attr = "super_state = Top";
+        let item = "impl kaori_hsm::State<motor::StateName> for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str =
+            ":: core :: compile_error ! { \"state tag must be a single identifier\" }";
+        let res = crate::state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_choice_impl() {
+        let attr = "super_state = Parent, targets = [StateA, StateB]";
+        let item = "impl kaori_hsm::Choice<ChoiceName> for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct ChoiceName { } impl kaori_hsm :: State < ChoiceName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: Exists (kaori_hsm :: State :: < Parent > :: state_fn ()) } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: of :: < Parent > ()) ; const NAME : & 'static str = \"ChoiceName\" ; fn handle (& mut self , _evt : & < Self as kaori_hsm :: TopState > :: Evt) -> kaori_hsm :: HandleResult < Self > { kaori_hsm :: HandleResult :: Ignored } fn choose (& mut self) -> Option < kaori_hsm :: HandleResult < Self >> { Some (kaori_hsm :: Choice :: < ChoiceName > :: choose (self)) } } impl kaori_hsm :: Choice < ChoiceName > for UserStateMachine { const TARGETS : & 'static [& 'static str] = & [< Self as kaori_hsm :: State < StateA > > :: NAME , < Self as kaori_hsm :: State < StateB > > :: NAME] ; }";
+        let res = crate::choice_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_final_state_impl() {
+        let attr = "super_state = Parent";
+        let item = "impl kaori_hsm::FinalState<FinalName> for UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct FinalName { } impl kaori_hsm :: State < FinalName > for UserStateMachine { fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: Exists (kaori_hsm :: State :: < Parent > :: state_fn ()) } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: of :: < Parent > ()) ; const NAME : & 'static str = \"FinalName\" ; const HAS_INIT : bool = false ; const IS_FINAL : bool = true ; fn handle (& mut self , _evt : & < Self as kaori_hsm :: TopState > :: Evt) -> kaori_hsm :: HandleResult < Self > { kaori_hsm :: HandleResult :: Ignored } } impl kaori_hsm :: FinalState < FinalName > for UserStateMachine { } const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , FinalName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
+        let res = crate::final_state_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_hsm_impl() {
+        let input = "machine UserStateMachine { type Evt = Evt; init => StateName; } state StateName(super_state = Top) { entry / Self::on_entry; Evt::A(level) if *level > 1 => StateName / Self::on_a; Evt::B => handled / Self::on_b; }";
+
+        let input_tokens = TokenStream::from_str(input).unwrap();
+        let expected_str = "impl kaori_hsm :: TopState for UserStateMachine { type Evt = Evt ; fn init (& mut self) -> kaori_hsm :: InitResult < Self > { kaori_hsm :: init_transition ! (StateName) } } struct StateName { } impl kaori_hsm :: State < StateName > for UserStateMachine { fn entry (& mut self) { (Self :: on_entry) (self) } # [allow (unreachable_patterns)] fn handle (& mut self , evt : & < Self as kaori_hsm :: TopState > :: Evt) -> kaori_hsm :: HandleResult < Self > { match evt { Evt :: A (level) if * level > 1 => { kaori_hsm :: transition ! (StateName , action = Self :: on_a) } Evt :: B => { (Self :: on_b) (self) ; kaori_hsm :: HandleResult :: Handled } _ => kaori_hsm :: HandleResult :: Ignored , } } fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: top ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & kaori_hsm :: Lineage :: Top ,) ; const HAS_INIT : bool = false ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; } ; } const _ : () = < UserStateMachine as kaori_hsm :: State < StateName >> :: HIERARCHY_CHECK ; const _ : fn () = || { # [allow (unused_imports)] use kaori_hsm :: { ProbeNoStateId as _ , ProbeStateId as _ } ; let probe = kaori_hsm :: StateIdProbe :: < UserStateMachine , StateName > :: default () ; kaori_hsm :: check_listed_state ((&& probe) . probe ()) ; } ;";
+        let res = crate::hsm_impl(input_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_hsm_impl_generic() {
+        let input = "machine<T: Uart> Driver<T> where T: Send { type Evt = Evt; init => StateName; } state StateName(super_state = Top) { Evt::A => handled; }";
+
+        let input_tokens = TokenStream::from_str(input).unwrap();
+        let expected_str = "impl < T : Uart > kaori_hsm :: TopState for Driver < T > where T : Send { type Evt = Evt ; fn init (& mut self) -> kaori_hsm :: InitResult < Self > { kaori_hsm :: init_transition ! (StateName) } } struct StateName { } impl < T : Uart > kaori_hsm :: State < StateName > for Driver < T > where T : Send { # [allow (unreachable_patterns)] fn handle (& mut self , evt : & < Self as kaori_hsm :: TopState > :: Evt) -> kaori_hsm :: HandleResult < Self > { match evt { Evt :: A => { kaori_hsm :: HandleResult :: Handled } _ => kaori_hsm :: HandleResult :: Ignored , } } fn get_parent_state () -> kaori_hsm :: ParentState < Self > { kaori_hsm :: ParentState :: TopReached } const PARENT_STATE_FN : Option < kaori_hsm :: StateFn < Self > > = Some (kaori_hsm :: StateFn :: top ()) ; const NAME : & 'static str = \"StateName\" ; const LINEAGE : kaori_hsm :: Lineage = kaori_hsm :: Lineage :: state (:: core :: concat ! (:: core :: module_path ! () , \"::\" , \"StateName\") , & kaori_hsm :: Lineage :: Top ,) ; const HAS_INIT : bool = false ; const HIERARCHY_CHECK : () = { let _ : & kaori_hsm :: Lineage = & < Self as kaori_hsm :: State < StateName > > :: LINEAGE ; } ; }";
+        let res = crate::hsm_impl(input_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_machine_impl() {
+        let attr = "states = [StateA, StateB]";
+        let item = "struct UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct UserStateMachine { } # [derive (Debug , Clone , Copy , PartialEq , Eq , Hash)] enum UserStateMachineStateId { StateA , StateB , } impl kaori_hsm :: HasStateId for UserStateMachine { type StateId = UserStateMachineStateId ; fn state_id (state : kaori_hsm :: StateRef < Self >) -> Option < UserStateMachineStateId > { if state . is :: < StateA > () { return Some (UserStateMachineStateId :: StateA) ; } if state . is :: < StateB > () { return Some (UserStateMachineStateId :: StateB) ; } None } } impl kaori_hsm :: ListedState < StateA > for UserStateMachine { } impl kaori_hsm :: ListedState < StateB > for UserStateMachine { } impl From < UserStateMachineStateId > for & 'static str { fn from (state_id : UserStateMachineStateId) -> Self { match state_id { UserStateMachineStateId :: StateA => \"StateA\" , UserStateMachineStateId :: StateB => \"StateB\" , } } }";
+        let res = crate::state_machine_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_machine_impl_renamed_state() {
+        let attr = "states = [a::Idle, b::Idle as BIdle]";
+        let item = "struct UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = "struct UserStateMachine { } # [derive (Debug , Clone , Copy , PartialEq , Eq , Hash)] enum UserStateMachineStateId { Idle , BIdle , } impl kaori_hsm :: HasStateId for UserStateMachine { type StateId = UserStateMachineStateId ; fn state_id (state : kaori_hsm :: StateRef < Self >) -> Option < UserStateMachineStateId > { if state . is :: < a :: Idle > () { return Some (UserStateMachineStateId :: Idle) ; } if state . is :: < b :: Idle > () { return Some (UserStateMachineStateId :: BIdle) ; } None } } impl kaori_hsm :: ListedState < a :: Idle > for UserStateMachine { } impl kaori_hsm :: ListedState < b :: Idle > for UserStateMachine { } impl From < UserStateMachineStateId > for & 'static str { fn from (state_id : UserStateMachineStateId) -> Self { match state_id { UserStateMachineStateId :: Idle => \"Idle\" , UserStateMachineStateId :: BIdle => \"Idle\" , } } }";
+        let res = crate::state_machine_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
+
+    #[test]
+    fn test_state_machine_impl_duplicate_state() {
+        let attr = "states = [a::Idle, b::Idle]";
+        let item = "struct UserStateMachine{ }";
+
+        let attr_tokens = TokenStream::from_str(attr).unwrap();
+        let item_tokens = TokenStream::from_str(item).unwrap();
+        let expected_str = ":: core :: compile_error ! { \"duplicate state identifier `Idle`, rename the state as in `b::Idle as Name`\" }";
+        let res = crate::state_machine_impl(attr_tokens, item_tokens);
+        assert_eq!(expected_str, res.to_string());
+    }
 }